@@ -1,6 +1,11 @@
 #[macro_use]
 extern crate criterion;
 
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::thread;
+use std::time::Duration;
+
 use criterion::Criterion;
 
 use xi::Stream;
@@ -12,6 +17,49 @@ fn map_benchmark(c: &mut Criterion) {
     c.bench_function("map", move |b| b.iter(|| sink.update(42)));
 }
 
+fn map_chain_benchmark(c: &mut Criterion) {
+    let sink = Stream::sink();
+    let mut chain = sink.stream();
+    for _ in 0..32 {
+        chain = chain.map(|x| x + 1);
+    }
+    let _ = chain.subscribe(|_| {});
+    c.bench_function("map chain 32", move |b| b.iter(|| sink.update(0)));
+}
+
+fn fan_out_benchmark(c: &mut Criterion) {
+    let sink = Stream::sink();
+    let stream = sink.stream();
+    let maps: Vec<_> = (0..64).map(|_| stream.map(|x| x * 2)).collect();
+    for map in &maps {
+        let _ = map.subscribe(|_| {});
+    }
+    c.bench_function("fan out 64", move |b| b.iter(|| sink.update(42)));
+}
+
+fn contention_benchmark(c: &mut Criterion) {
+    let sink = Arc::new(Stream::sink());
+    let map = sink.stream().map(|x| x * 2);
+    let _ = map.subscribe(|_| {});
+    c.bench_function("8 threads x 1000 updates", move |b| {
+        b.iter(|| {
+            let handles: Vec<_> = (0..8)
+                .map(|_| {
+                    let sink = sink.clone();
+                    thread::spawn(move || {
+                        for i in 0..1000 {
+                            sink.update(i);
+                        }
+                    })
+                })
+                .collect();
+            for h in handles {
+                h.join().unwrap();
+            }
+        })
+    });
+}
+
 fn imitator_benchmark(c: &mut Criterion) {
     let imitator = Stream::imitator();
 
@@ -30,5 +78,48 @@ fn imitator_benchmark(c: &mut Criterion) {
     c.bench_function("imitator", move |b| b.iter(|| sink.update(1)));
 }
 
-criterion_group!(benches, map_benchmark, imitator_benchmark);
+fn late_subscribe_benchmark(c: &mut Criterion) {
+    let sink = Stream::sink();
+    let rem = sink.stream().remember();
+    sink.update(vec![0_u8; 1024]);
+    c.bench_function("late subscribe remembered", move |b| {
+        b.iter(|| rem.subscribe(|_| {}).unsubscribe())
+    });
+}
+
+fn collector_monitor_benchmark(c: &mut Criterion) {
+    // unit events, so the collected vec doesn't grow in memory
+    let sink = Stream::sink();
+    let _coll = sink.stream().collect();
+    c.bench_function("collect", move |b| b.iter(|| sink.update(())));
+
+    // the same, with another thread polling the collector's length
+    let sink = Stream::sink();
+    let coll = Arc::new(sink.stream().collect());
+    let stop = Arc::new(AtomicBool::new(false));
+    let monitor = {
+        let coll = coll.clone();
+        let stop = stop.clone();
+        thread::spawn(move || {
+            while !stop.load(Ordering::Relaxed) {
+                criterion::black_box(coll.len());
+                thread::sleep(Duration::from_micros(100));
+            }
+        })
+    };
+    c.bench_function("collect with monitor", move |b| b.iter(|| sink.update(())));
+    stop.store(true, Ordering::Relaxed);
+    monitor.join().unwrap();
+}
+
+criterion_group!(
+    benches,
+    map_benchmark,
+    map_chain_benchmark,
+    fan_out_benchmark,
+    contention_benchmark,
+    imitator_benchmark,
+    late_subscribe_benchmark,
+    collector_monitor_benchmark
+);
 criterion_main!(benches);
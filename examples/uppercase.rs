@@ -0,0 +1,22 @@
+//! Reads lines from standard input and prints them in upper case.
+//!
+//! `echo hello | cargo run --example uppercase`
+
+fn main() {
+    let stdin = std::io::stdin();
+    let (lines, driver) = xi::io::lines(stdin.lock());
+
+    let upper = lines.map(|l| l.to_uppercase());
+    let sub = upper.subscribe(|l| {
+        if let Some(l) = l {
+            println!("{}", l);
+        }
+    });
+
+    if let Err(e) = driver.run() {
+        eprintln!("{}", e);
+        std::process::exit(1);
+    }
+
+    sub.unsubscribe();
+}
@@ -0,0 +1,111 @@
+//! Instrumented types shared by tests and benchmarks.
+//!
+//! Only available with the `bench` feature.
+
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+
+/// Counts the clones of the values it wraps.
+///
+/// ```
+/// use xi::bench_support::CloneCounter;
+///
+/// let counter = CloneCounter::new();
+///
+/// let sink = xi::Stream::sink();
+/// let rem = sink.stream().remember();
+///
+/// sink.update(counter.wrap(42));
+///
+/// // remember() keeps a clone
+/// assert_eq!(counter.count(), 1);
+/// assert_eq!(rem.collect().take()[0].value, 42);
+/// ```
+#[derive(Debug, Clone, Default)]
+pub struct CloneCounter(Arc<AtomicUsize>);
+
+impl CloneCounter {
+    /// Create a counter, starting at 0.
+    pub fn new() -> Self {
+        CloneCounter(Arc::new(AtomicUsize::new(0)))
+    }
+
+    /// Wrap a value to count its clones with this counter.
+    pub fn wrap<T>(&self, value: T) -> Counted<T> {
+        Counted {
+            value,
+            counter: self.clone(),
+        }
+    }
+
+    /// The number of clones made of values wrapped by this counter.
+    pub fn count(&self) -> usize {
+        self.0.load(Ordering::SeqCst)
+    }
+
+    /// Reset the count to 0.
+    pub fn reset(&self) {
+        self.0.store(0, Ordering::SeqCst);
+    }
+}
+
+/// A value that increases a [`CloneCounter`](struct.CloneCounter.html) when cloned.
+#[derive(Debug)]
+pub struct Counted<T> {
+    /// The wrapped value.
+    pub value: T,
+    counter: CloneCounter,
+}
+
+impl<T: Clone> Clone for Counted<T> {
+    fn clone(&self) -> Self {
+        self.counter.0.fetch_add(1, Ordering::SeqCst);
+        Counted {
+            value: self.value.clone(),
+            counter: self.counter.clone(),
+        }
+    }
+}
+
+static ALLOCATIONS: AtomicUsize = AtomicUsize::new(0);
+
+/// A global allocator that counts allocations, delegating to the system allocator.
+///
+/// It must be installed by the binary using it.
+///
+/// ```no_run
+/// use xi::bench_support::{allocations, CountingAlloc};
+///
+/// #[global_allocator]
+/// static ALLOC: CountingAlloc = CountingAlloc;
+///
+/// fn main() {
+///     let before = allocations();
+///     let v = vec![1, 2, 3];
+///     assert_eq!(allocations() - before, 1);
+/// }
+/// ```
+pub struct CountingAlloc;
+
+unsafe impl GlobalAlloc for CountingAlloc {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        ALLOCATIONS.fetch_add(1, Ordering::Relaxed);
+        System.alloc(layout)
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        System.dealloc(ptr, layout)
+    }
+
+    unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        ALLOCATIONS.fetch_add(1, Ordering::Relaxed);
+        System.realloc(ptr, layout, new_size)
+    }
+}
+
+/// The number of allocations made through [`CountingAlloc`](struct.CountingAlloc.html)
+/// by the whole process so far.
+pub fn allocations() -> usize {
+    ALLOCATIONS.load(Ordering::Relaxed)
+}
@@ -0,0 +1,181 @@
+use std::collections::VecDeque;
+use std::sync::{Arc, Condvar, Mutex};
+
+use crate::peg::Peg;
+use crate::Stream;
+
+/// What a bridge does with an event when its queue is full. Used with
+/// [`Stream::bridge_bounded()`](struct.Stream.html#method.bridge_bounded).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OverflowPolicy {
+    /// Drop the oldest queued event to make room for the new one.
+    DropOldest,
+    /// Drop the new event.
+    DropNewest,
+    /// Block the thread updating the stream until there is room in the queue.
+    BlockProducer,
+}
+
+/// The receiving end of a bounded queue of events, to be read on another thread. Created
+/// by [`Stream::bridge_bounded()`](struct.Stream.html#method.bridge_bounded).
+///
+/// Dropping the receiver stops listening to the stream, and releases a blocked producer.
+pub struct BridgeReceiver<T: 'static> {
+    peg: Peg,
+    state: Arc<(Mutex<Queue<T>>, Condvar)>,
+}
+
+struct Queue<T> {
+    events: VecDeque<T>,
+    ended: bool,
+    dropped: bool,
+}
+
+impl<T: Clone> BridgeReceiver<T> {
+    pub(crate) fn new(stream: &Stream<T>, capacity: usize, policy: OverflowPolicy) -> Self {
+        if capacity == 0 {
+            panic!("bridge_bounded() with a capacity of 0");
+        }
+        let state = Arc::new((
+            Mutex::new(Queue {
+                events: VecDeque::with_capacity(capacity),
+                ended: false,
+                dropped: false,
+            }),
+            Condvar::new(),
+        ));
+        let clone = state.clone();
+        let peg = stream.internal_subscribe(move |t| {
+            let (lock, cvar) = &*clone;
+            let mut queue = lock.lock().unwrap();
+            if queue.dropped {
+                return;
+            }
+            if let Some(t) = t {
+                if queue.events.len() == capacity {
+                    match policy {
+                        OverflowPolicy::DropOldest => {
+                            queue.events.pop_front();
+                        }
+                        OverflowPolicy::DropNewest => return,
+                        OverflowPolicy::BlockProducer => {
+                            while queue.events.len() == capacity && !queue.dropped {
+                                queue = cvar.wait(queue).unwrap();
+                            }
+                            if queue.dropped {
+                                return;
+                            }
+                        }
+                    }
+                }
+                queue.events.push_back(t.clone());
+            } else {
+                queue.ended = true;
+            }
+            cvar.notify_all();
+        });
+        BridgeReceiver { peg, state }
+    }
+}
+
+impl<T> BridgeReceiver<T> {
+    /// Block until the next event. Returns `None` once the stream has ended and all queued
+    /// events have been received.
+    pub fn recv(&self) -> Option<T> {
+        let (lock, cvar) = &*self.state;
+        let mut queue = lock.lock().unwrap();
+        loop {
+            if let Some(t) = queue.events.pop_front() {
+                // there's room for a blocked producer
+                cvar.notify_all();
+                return Some(t);
+            }
+            if queue.ended {
+                return None;
+            }
+            queue = cvar.wait(queue).unwrap();
+        }
+    }
+}
+
+impl<T> Drop for BridgeReceiver<T> {
+    fn drop(&mut self) {
+        let (lock, cvar) = &*self.state;
+        lock.lock().unwrap().dropped = true;
+        cvar.notify_all();
+        self.peg.unpeg();
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::thread;
+    use std::time::Duration;
+
+    fn run_fast_producer(policy: OverflowPolicy) -> Vec<u32> {
+        let sink = Stream::sink();
+        let rx = sink.stream().bridge_bounded(3, policy);
+        let producer = thread::spawn(move || {
+            for i in 0..10 {
+                sink.update(i);
+            }
+            sink.end();
+        });
+        if policy != OverflowPolicy::BlockProducer {
+            // let the queue overflow before consuming
+            producer.join().unwrap();
+        }
+        let mut all = vec![];
+        while let Some(v) = rx.recv() {
+            thread::sleep(Duration::from_millis(1));
+            all.push(v);
+        }
+        all
+    }
+
+    #[test]
+    fn test_bridge_drop_oldest() {
+        assert_eq!(run_fast_producer(OverflowPolicy::DropOldest), vec![7, 8, 9]);
+    }
+
+    #[test]
+    fn test_bridge_drop_newest() {
+        assert_eq!(run_fast_producer(OverflowPolicy::DropNewest), vec![0, 1, 2]);
+    }
+
+    #[test]
+    fn test_bridge_block_producer() {
+        assert_eq!(
+            run_fast_producer(OverflowPolicy::BlockProducer),
+            (0..10).collect::<Vec<_>>()
+        );
+    }
+
+    #[test]
+    fn test_bridge_drop_releases_producer() {
+        let sink = Stream::sink();
+        let rx = sink
+            .stream()
+            .bridge_bounded(1, OverflowPolicy::BlockProducer);
+        let producer = thread::spawn(move || {
+            sink.update(0);
+            sink.update(1); // blocks until rx is dropped
+            sink.update(2);
+        });
+        thread::sleep(Duration::from_millis(10));
+        drop(rx);
+        producer.join().unwrap();
+    }
+
+    #[test]
+    fn test_bridge_recv_after_end() {
+        let sink = Stream::sink();
+        let rx = sink.stream().bridge_bounded(2, OverflowPolicy::DropNewest);
+        sink.update("a");
+        sink.end();
+        assert_eq!(rx.recv(), Some("a"));
+        assert_eq!(rx.recv(), None);
+        assert_eq!(rx.recv(), None);
+    }
+}
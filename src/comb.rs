@@ -1,15 +1,17 @@
 //
 
 macro_rules! mk_combine {
-    ($name:ident => $alive:tt $($var:ident $rvar:ident $type:tt),+) => {
+    ($name:ident => $($var:ident $rvar:ident $type:tt),+) => {
       impl<$($type,)+> Stream<($($type,)+)>
             where
               $( $type: Clone, )+
         {
-            /// Combine a number of streams into one.
+            /// Combine the latest values of a number of streams into one.
             ///
-            /// The resulting stream emits when any of the incoming streams emit, but only
-            /// when all incoming have had an initial value.
+            /// The streams can each have their own event type. The resulting stream emits
+            /// when any of the incoming streams emit, but only when all incoming have had
+            /// an initial value.
+            #[allow(clippy::too_many_arguments)]
             pub fn $name($( $var: &Stream<$type>, )+) -> Stream<($( $type, )+)> {
                 let inner = SafeInner::new(MemoryMode::NoMemory, None);
                 let inner_clone = inner.clone();
@@ -28,7 +30,7 @@ macro_rules! mk_combine {
                         None
                     }
                 };
-                let alive = Arc::new(AtomicUsize::new($alive));
+                let alive = Arc::new(AtomicUsize::new([$(stringify!($var)),+].len()));
                 let pegs: Vec<_> = vec![
                     $(
                         {
@@ -69,9 +71,203 @@ macro_rules! mk_combine {
     };
 }
 
-mk_combine!(combine2 => 2 a ra A, b rb B);
-mk_combine!(combine3 => 3 a ra A, b rb B, c rc C);
-mk_combine!(combine4 => 4 a ra A, b rb B, c rc C, d rd D);
-mk_combine!(combine5 => 5 a ra A, b rb B, c rc C, d rd D, e re E);
-mk_combine!(combine6 => 6 a ra A, b rb B, c rc C, d rd D, e re E, f rf F);
-mk_combine!(combine7 => 7 a ra A, b rb B, c rc C, d rd D, e re E, f rf F, g rg G);
+macro_rules! mk_zip {
+    ($name:ident => $($var:ident $qvar:ident $type:tt),+) => {
+      impl<$($type,)+> Stream<($($type,)+)>
+            where
+              $( $type: Clone, )+
+        {
+            /// Pair up the events of a number of streams in lockstep.
+            ///
+            /// The streams can each have their own event type. The nth event of the
+            /// resulting stream is a tuple of the nth event of each incoming stream. Events
+            /// waiting for their counterparts are queued. The resulting stream ends when an
+            /// incoming stream has ended and has no queued events left. See
+            /// [`zip()`](struct.Stream.html#method.zip).
+            #[allow(clippy::too_many_arguments)]
+            pub fn $name($( $var: &Stream<$type>, )+) -> Stream<($( $type, )+)> {
+                let inner = SafeInner::new(MemoryMode::NoMemory, None);
+                $(
+                    // queued events, and whether the stream has ended.
+                    let $qvar: Arc<Mutex<(VecDeque<$type>, bool)>> =
+                        Arc::new(Mutex::new((VecDeque::new(), false)));
+                )+
+                // whether the zip is done, only changed with all the queues locked.
+                let finished = Arc::new(AtomicBool::new(false));
+                // the subscriptions are held by the peg of the resulting stream, and
+                // released once nothing more can be paired.
+                let (pegged, peg) = Pegged::new_pair(vec![]);
+                let pegged = Arc::new(pegged);
+                let pop = {
+                    let finished = finished.clone();
+                    $(
+                        let $qvar = $qvar.clone();
+                    )+
+                    move || {
+                        $(
+                            let mut $qvar = $qvar.lock().unwrap();
+                        )+
+                        if finished.load(Ordering::SeqCst) {
+                            return (None, false);
+                        }
+                        let ready = $( !$qvar.0.is_empty() )&&+;
+                        let v = if ready {
+                            Some(($( $qvar.0.pop_front().unwrap(), )+))
+                        } else {
+                            None
+                        };
+                        let done = $( ($qvar.1 && $qvar.0.is_empty()) )||+;
+                        if done {
+                            $(
+                                $qvar.0.clear();
+                            )+
+                            finished.store(true, Ordering::SeqCst);
+                        }
+                        (v, done)
+                    }
+                };
+                let pegs: Vec<_> = vec![
+                    $(
+                        {
+                            let pop = pop.clone();
+                            let inner_clone = inner.clone();
+                            let $qvar = $qvar.clone();
+                            let finished = finished.clone();
+                            let pegged = pegged.clone();
+                            $var.internal_subscribe(move |t| {
+                                {
+                                    let mut q = $qvar.lock().unwrap();
+                                    if finished.load(Ordering::SeqCst) {
+                                        return;
+                                    }
+                                    match t {
+                                        Some(t) => q.0.push_back(t.clone()),
+                                        None => q.1 = true,
+                                    }
+                                }
+                                let (v, done) = pop();
+                                let mut lock = inner_clone.lock();
+                                if v.is_some() {
+                                    lock.update_owned(v);
+                                }
+                                if done {
+                                    lock.update_owned(None);
+                                    release_deferred(&pegged);
+                                }
+                            })
+                        }
+                    ),+
+                ];
+                hold_subs(&pegged, &inner, pegs);
+                Stream { peg, inner }
+            }
+        }
+    };
+}
+
+// Invoke a macro for every arity from the initial variables up to all variables, with
+// one name per arity. I.e. `[combine2 combine3]` and variables for a, b, c gives
+// `mk_combine!(combine2 => a.., b..)` and `mk_combine!(combine3 => a.., b.., c..)`.
+macro_rules! mk_arities {
+    ($mk:ident [$($acc:tt)*] []) => {};
+    ($mk:ident [$($acc:tt)*] [$name:ident $($names:ident)*]
+        $var:ident $rvar:ident $type:ident $(, $($rest:tt)*)?) => {
+        $mk!($name => $($acc)* $var $rvar $type);
+        mk_arities!($mk [$($acc)* $var $rvar $type,] [$($names)*] $($($rest)*)?);
+    };
+}
+
+mk_arities!(mk_combine [a ra A,]
+    [combine2 combine3 combine4 combine5 combine6 combine7 combine8]
+    b rb B, c rc C, d rd D, e re E, f rf F, g rg G, h rh H);
+
+mk_arities!(mk_zip [a qa A,]
+    [zip2 zip3 zip4 zip5 zip6 zip7 zip8]
+    b qb B, c qc C, d qd D, e qe E, f qf F, g qg G, h qh H);
+
+impl<U> Stream<U> {
+    /// Fold the events of two streams into one accumulator.
+    ///
+    /// Events of `a` are folded with `fa`, and events of `b` with `fb`, and every new value
+    /// of the accumulator is emitted. Like [`fold()`](struct.Stream.html#method.fold), the
+    /// seed is emitted straight away and the result is a "memory" stream. The resulting
+    /// stream ends when both incoming streams have ended.
+    ///
+    /// The accumulator is the memory of the resulting stream, so events arriving on both
+    /// streams at once from different threads are folded one at a time.
+    ///
+    /// ```
+    /// use xi::Stream;
+    ///
+    /// let commands = Stream::sink();
+    /// let acks = Stream::sink();
+    ///
+    /// // commands in flight
+    /// let in_flight = Stream::fold2(
+    ///     &commands.stream(),
+    ///     &acks.stream(),
+    ///     0,
+    ///     |n, _: &&str| n + 1,
+    ///     |n, _: &u32| n - 1,
+    /// );
+    ///
+    /// let coll = in_flight.collect();
+    ///
+    /// commands.update("a");
+    /// commands.update("b");
+    /// acks.update(1);
+    /// commands.end();
+    /// acks.end();
+    ///
+    /// assert_eq!(coll.wait(), vec![0, 1, 2, 1]);
+    /// ```
+    pub fn fold2<A, B, FA, FB>(
+        a: &Stream<A>,
+        b: &Stream<B>,
+        seed: U,
+        mut fa: FA,
+        mut fb: FB,
+    ) -> Stream<U>
+    where
+        FA: FnMut(U, &A) -> U + Send + 'static,
+        FB: FnMut(U, &B) -> U + Send + 'static,
+    {
+        let inner = SafeInner::new(MemoryMode::KeepUntilEnd, Some(seed));
+        let alive = Arc::new(AtomicUsize::new(2));
+        let peg_a = {
+            let inner_clone = inner.clone();
+            let alive = alive.clone();
+            a.internal_subscribe(move |t| {
+                if let Some(t) = t {
+                    let mut lock = inner_clone.lock();
+                    if let Some(prev) = lock.take_memory() {
+                        let next = fa(prev, t);
+                        lock.update_owned(Some(next));
+                    } else {
+                        panic!("fold2 without a previous value");
+                    }
+                } else if alive.fetch_sub(1, Ordering::SeqCst) == 1 {
+                    inner_clone.lock().update_owned(None);
+                }
+            })
+        };
+        let peg_b = {
+            let inner_clone = inner.clone();
+            b.internal_subscribe(move |t| {
+                if let Some(t) = t {
+                    let mut lock = inner_clone.lock();
+                    if let Some(prev) = lock.take_memory() {
+                        let next = fb(prev, t);
+                        lock.update_owned(Some(next));
+                    } else {
+                        panic!("fold2 without a previous value");
+                    }
+                } else if alive.fetch_sub(1, Ordering::SeqCst) == 1 {
+                    inner_clone.lock().update_owned(None);
+                }
+            })
+        };
+        let peg = Peg::many(vec![peg_a, peg_b]);
+        Stream { peg, inner }
+    }
+}
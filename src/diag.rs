@@ -0,0 +1,55 @@
+//! Diagnostics, compiled out entirely without the `diagnostics` feature.
+
+/// Run diagnostics code, such as a counter increment. Without the `diagnostics` feature
+/// the code is not compiled at all, so it costs nothing.
+macro_rules! diag {
+    ($($body:tt)*) => {
+        #[cfg(feature = "diagnostics")]
+        {
+            $($body)*
+        }
+    };
+}
+
+#[cfg(feature = "diagnostics")]
+pub use self::stats::*;
+
+#[cfg(feature = "diagnostics")]
+mod stats {
+    use std::sync::atomic::{AtomicU64, Ordering};
+
+    pub(crate) static UPDATES: AtomicU64 = AtomicU64::new(0);
+    pub(crate) static SUBSCRIBES: AtomicU64 = AtomicU64::new(0);
+
+    /// Process wide counters. Only available with the `diagnostics` feature.
+    #[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+    pub struct Stats {
+        /// Events, including ends, dispatched by any stream.
+        pub updates: u64,
+        /// Listeners added to any stream, both subscriptions and operators.
+        pub subscribes: u64,
+    }
+
+    /// Read the counters. Only available with the `diagnostics` feature.
+    ///
+    /// ```
+    /// let before = xi::stats();
+    ///
+    /// let sink = xi::Stream::sink();
+    /// let doubled = sink.stream().map(|v| v * 2);
+    /// let _sub = doubled.subscribe(|_| {});
+    ///
+    /// sink.update(1);
+    ///
+    /// // other threads may count too
+    /// let after = xi::stats();
+    /// assert!(after.subscribes - before.subscribes >= 2);
+    /// assert!(after.updates - before.updates >= 2);
+    /// ```
+    pub fn stats() -> Stats {
+        Stats {
+            updates: UPDATES.load(Ordering::Relaxed),
+            subscribes: SUBSCRIBES.load(Ordering::Relaxed),
+        }
+    }
+}
@@ -88,6 +88,39 @@ use crate::{MemoryMode, Stream, Subscription};
 ///     driver_out.imitate(&app_sinks.my_driver);
 /// }
 /// ```
+///
+/// ## Updates from imitated events
+///
+/// The imitator holds back events until the update that caused them has propagated through
+/// the tree. The held back events are then delivered in the order they were produced.
+///
+/// A subscriber receiving an imitated event may update a sink, also the one that started
+/// the cycle. That update propagates straight away, and any events it causes imitators to
+/// hold back are queued after the ones already waiting. No events are dropped.
+///
+/// ```
+/// use std::sync::Arc;
+/// use xi::Stream;
+///
+/// let sink = Arc::new(Stream::sink());
+/// let stream = sink.stream();
+/// let coll = stream.collect();
+///
+/// let imitator = Stream::imitator();
+///
+/// // feed values below 100 back into the sink, times ten.
+/// let sink_clone = sink.clone();
+/// let _sub = imitator.stream().subscribe(move |v| if let Some(v) = v {
+///     sink_clone.update(v * 10);
+/// });
+///
+/// let small = stream.filter(|v| *v < 100);
+/// imitator.imitate(&small);
+///
+/// sink.update(1);
+///
+/// assert_eq!(coll.take(), vec![1, 10, 100]);
+/// ```
 pub struct Imitator<T: 'static> {
     inner: SafeInner<T>,
 }
@@ -1,10 +1,81 @@
-use std::cell::RefCell;
-use std::sync::{Arc, Mutex, MutexGuard};
+use std::any::Any;
+use std::cell::{Cell, RefCell};
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex, MutexGuard, Weak};
 
 use crate::peg::Peg;
+use crate::policy;
 use crate::sub::Listeners;
 
 thread_local!(pub static IMITATORS: RefCell<Vec<Box<dyn FnMut()>>> = RefCell::new(vec![]));
+thread_local!(static FLUSHING: Cell<bool> = const { Cell::new(false) });
+
+// the id of the next stream node.
+static NEXT_ID: AtomicUsize = AtomicUsize::new(0);
+
+thread_local!(static MISDISPATCHED: Cell<bool> = const { Cell::new(false) });
+
+/// Note that a subscriber bound to another thread was dispatched to from this one.
+pub(crate) fn misdispatched() {
+    MISDISPATCHED.with(|m| m.set(true));
+}
+
+/// Panic if a subscriber bound to another thread was dispatched to from this one. Called
+/// once an update has propagated and its locks are released, so they aren't poisoned.
+pub(crate) fn check_misdispatch() {
+    if MISDISPATCHED.with(|m| m.replace(false)) {
+        panic!("subscribe_local() subscriber called from another thread");
+    }
+}
+
+/// Queue a function to run once the current update has propagated through the tree.
+pub(crate) fn defer<F: FnMut() + 'static>(f: F) {
+    IMITATORS.with(|imit_cell| {
+        let mut imit = imit_cell.borrow_mut();
+        imit.push(Box::new(f));
+    });
+}
+
+/// Run queued functions until the queue is empty.
+///
+/// Updates made by the queued functions propagate straight away, but anything they queue
+/// joins the end of the queue of the flush already running further up the stack. That way
+/// the queue is always run in FIFO order, however deep the updates nest.
+pub(crate) fn run_deferred() {
+    if FLUSHING.with(|f| f.replace(true)) {
+        return;
+    }
+    let _flushing = Flushing;
+    loop {
+        let imit = IMITATORS.with(|imit_cell| imit_cell.borrow_mut().split_off(0));
+        if imit.is_empty() {
+            break;
+        }
+        for mut i in imit {
+            i();
+        }
+    }
+}
+
+/// Deliver the events queued by
+/// [`Stream::subscribe_deferred_replay()`](struct.Stream.html#method.subscribe_deferred_replay),
+/// along with anything else queued to run after the current update, on this thread.
+///
+/// Updating a sink does this too, once the update has propagated. Called from inside a
+/// subscriber, the queue is run once the update being dispatched is done.
+pub fn flush_deferred() {
+    run_deferred();
+}
+
+/// Resets the flushing flag, also if a queued function panics.
+struct Flushing;
+
+impl Drop for Flushing {
+    fn drop(&mut self) {
+        FLUSHING.with(|f| f.set(false));
+    }
+}
 
 pub struct SafeInner<T: 'static>(Arc<Mutex<Inner<T>>>);
 
@@ -15,6 +86,20 @@ impl<T> SafeInner<T> {
     pub(crate) fn lock<'a>(&'a self) -> MutexGuard<'a, Inner<T>> {
         self.0.lock().unwrap()
     }
+    pub(crate) fn downgrade(&self) -> WeakInner<T> {
+        WeakInner(Arc::downgrade(&self.0))
+    }
+}
+
+pub(crate) struct WeakInner<T: 'static>(Weak<Mutex<Inner<T>>>);
+
+impl<T> WeakInner<T> {
+    pub fn upgrade(&self) -> Option<SafeInner<T>> {
+        self.0.upgrade().map(SafeInner)
+    }
+    pub fn is_dropped(&self) -> bool {
+        self.0.strong_count() == 0
+    }
 }
 
 #[derive(Clone, Copy, PartialEq, Eq)]
@@ -22,80 +107,111 @@ pub(crate) enum MemoryMode {
     NoMemory,
     KeepUntilEnd,
     KeepAfterEnd,
+    // every value is kept, and replayed to new listeners also after the end.
+    KeepAll,
 }
 
 impl MemoryMode {
     pub fn is_memory(self) -> bool {
-        match self {
-            MemoryMode::NoMemory => false,
-            _ => true,
-        }
+        !matches!(self, MemoryMode::NoMemory)
     }
 }
 
 pub(crate) struct Inner<T: 'static> {
+    id: usize,
+    // whether updates after the end are reported by the ended update policy, and the
+    // name they are reported with once needed.
+    report_late: bool,
+    late_name: Option<&'static str>,
     alive: bool,
     listeners: Listeners<T>,
     memory_mode: MemoryMode,
     memory: Option<T>,
+    history: Vec<T>,
+    // streams derived by shared_map(), by tag.
+    shared: HashMap<&'static str, Box<dyn Any + Send + Sync>>,
 }
 
 impl<T> Inner<T> {
     pub fn new(memory_mode: MemoryMode, memory: Option<T>) -> Self {
         Inner {
+            id: NEXT_ID.fetch_add(1, Ordering::Relaxed),
+            report_late: false,
+            late_name: None,
             alive: true,
             listeners: Listeners::new(),
             memory_mode,
             memory,
+            history: vec![],
+            shared: HashMap::new(),
         }
     }
 
+    pub fn id(&self) -> usize {
+        self.id
+    }
+
+    /// Report updates after the end to the ended update policy. Only sinks do, since
+    /// operators routinely ignore events arriving after they ended themselves.
+    pub fn set_report_late(&mut self) {
+        self.report_late = true;
+    }
+
+    /// Apply the ended update policy to an update after the end.
+    pub fn late_update(&mut self) {
+        if !self.report_late || policy::is_ignore() {
+            return;
+        }
+        let id = self.id;
+        let name = *self.late_name.get_or_insert_with(|| {
+            // leaked once per stream, and only with a policy set.
+            let name = format!("#{} ({})", id, std::any::type_name::<T>());
+            Box::leak(name.into_boxed_str())
+        });
+        policy::ended_update(name);
+    }
+
     pub fn memory_mode(&self) -> MemoryMode {
         self.memory_mode
     }
 
+    pub fn is_ended(&self) -> bool {
+        !self.alive
+    }
+
+    #[cfg(test)]
+    pub fn listener_count(&self) -> usize {
+        self.listeners.len()
+    }
+
     pub fn add<F: FnMut(Option<&T>) + 'static>(&mut self, mut listener: F) -> Peg {
+        diag!(crate::diag::SUBSCRIBES.fetch_add(1, Ordering::Relaxed););
+        if self.memory_mode == MemoryMode::KeepAll && !self.history.is_empty() {
+            for v in &self.history {
+                listener(Some(v));
+            }
+            run_deferred();
+        }
         if !self.alive {
             listener(None);
-            self.run_imitators();
+            run_deferred();
             return Peg::new_fake();
         }
         if self.memory_mode.is_memory() {
             if let Some(v) = self.memory.as_ref() {
                 listener(Some(v));
-                self.run_imitators();
+                run_deferred();
             }
         }
         self.listeners.add(listener)
     }
 
-    pub fn update_and_imitate(&mut self, t: Option<T>) {
-        if !self.alive {
-            return;
-        }
-        self.update_owned(t);
-        self.run_imitators();
-    }
-
-    fn run_imitators(&self) {
-        loop {
-            let mut imit = vec![];
-            IMITATORS.with(|imit_cell| {
-                imit = imit_cell.borrow_mut().split_off(0);
-            });
-            if imit.is_empty() {
-                break;
-            }
-            for mut i in imit {
-                i();
-            }
-        }
-    }
-
     pub fn update_owned(&mut self, t: Option<T>) {
         if !self.alive {
+            self.late_update();
             return;
         }
+        diag!(crate::diag::UPDATES.fetch_add(1, Ordering::Relaxed););
         self.listeners.iter(|l| l(t.as_ref()));
         let is_end = t.is_none();
         match self.memory_mode {
@@ -108,6 +224,9 @@ impl<T> Inner<T> {
                     self.memory = t;
                 }
             }
+            MemoryMode::KeepAll => {
+                self.history.extend(t);
+            }
         }
         if is_end {
             self.end();
@@ -116,8 +235,10 @@ impl<T> Inner<T> {
 
     pub fn update_borrowed(&mut self, t: Option<&T>) {
         if !self.alive {
+            self.late_update();
             return;
         }
+        diag!(crate::diag::UPDATES.fetch_add(1, Ordering::Relaxed););
         self.listeners.iter(|l| l(t));
         let is_end = t.is_none();
         if is_end {
@@ -125,6 +246,14 @@ impl<T> Inner<T> {
         }
     }
 
+    pub fn shared(&self, tag: &'static str) -> Option<&(dyn Any + Send + Sync)> {
+        self.shared.get(tag).map(|b| &**b)
+    }
+
+    pub fn insert_shared(&mut self, tag: &'static str, shared: Box<dyn Any + Send + Sync>) {
+        self.shared.insert(tag, shared);
+    }
+
     pub fn take_memory(&mut self) -> Option<T> {
         self.memory.take()
     }
@@ -136,6 +265,8 @@ impl<T> Inner<T> {
     fn end(&mut self) {
         self.alive = false;
         self.listeners.clear();
+        // the shared streams have ended too.
+        self.shared.clear();
     }
 }
 
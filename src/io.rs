@@ -0,0 +1,105 @@
+//! Glue for feeding I/O into streams.
+
+use std::io::{self, BufRead};
+
+use crate::{Sink, Stream};
+
+/// Stream the lines of a reader.
+///
+/// Returns the stream of lines together with the [`Driver`](struct.Driver.html) that reads
+/// them. Nothing is read until [`run()`](struct.Driver.html#method.run) is called, so the
+/// pipeline can be built first, and the caller decides which thread does the reading.
+///
+/// Lines are split like [`BufRead::lines`], i.e. without the trailing `\n` or `\r\n`.
+///
+/// ```
+/// use std::io::Cursor;
+///
+/// let (lines, driver) = xi::io::lines(Cursor::new("one\ntwo\n"));
+///
+/// let upper = lines.map(|l| l.to_uppercase()).collect();
+///
+/// driver.run().unwrap();
+///
+/// assert_eq!(upper.wait(), vec!["ONE", "TWO"]);
+/// ```
+///
+/// Reading standard input:
+///
+/// ```no_run
+/// let stdin = std::io::stdin();
+/// let (lines, driver) = xi::io::lines(stdin.lock());
+///
+/// lines.subscribe(|l| if let Some(l) = l { println!("{}", l) });
+///
+/// driver.run().unwrap();
+/// ```
+pub fn lines<R: BufRead>(reader: R) -> (Stream<String>, Driver<R>) {
+    let sink = Stream::sink();
+    let stream = sink.stream();
+    (stream, Driver { reader, sink })
+}
+
+/// Reads from a reader into a stream. Created by [`lines()`](fn.lines.html).
+pub struct Driver<R> {
+    reader: R,
+    sink: Sink<String>,
+}
+
+impl<R: BufRead> Driver<R> {
+    /// Block the current thread reading until the end of input, updating the stream with
+    /// every line read.
+    ///
+    /// The stream is ended both at the end of input and on a read error. In the latter case
+    /// the error is returned, such as a line not being valid UTF-8.
+    ///
+    /// ```
+    /// use std::io::{Cursor, ErrorKind};
+    ///
+    /// let (lines, driver) = xi::io::lines(Cursor::new(b"ok\n\xff\nnot read\n".to_vec()));
+    ///
+    /// let all = lines.collect();
+    ///
+    /// let err = driver.run().unwrap_err();
+    ///
+    /// assert_eq!(err.kind(), ErrorKind::InvalidData);
+    /// assert_eq!(all.wait(), vec!["ok"]);
+    /// ```
+    pub fn run(self) -> io::Result<()> {
+        let Driver { reader, sink } = self;
+        for line in reader.lines() {
+            match line {
+                Ok(line) => sink.update(line),
+                Err(e) => {
+                    sink.end();
+                    return Err(e);
+                }
+            }
+        }
+        sink.end();
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::io::{Cursor, ErrorKind};
+
+    #[test]
+    fn test_lines_until_eof() {
+        let (lines, driver) = lines(Cursor::new("a\r\nb\n\nc"));
+        let all = lines.collect();
+        driver.run().unwrap();
+        assert_eq!(all.wait(), vec!["a", "b", "", "c"]);
+    }
+
+    #[test]
+    fn test_lines_invalid_utf8() {
+        let (lines, driver) = lines(Cursor::new(b"a\n\xc3\x28\nb\n".to_vec()));
+        let all = lines.collect();
+        let err = driver.run().unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::InvalidData);
+        assert_eq!(all.wait(), vec!["a"]);
+    }
+}
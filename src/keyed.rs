@@ -0,0 +1,76 @@
+use std::collections::{HashMap, VecDeque};
+use std::hash::Hash;
+
+/// Values waiting for a match, keyed. Optionally bounded, evicting the oldest.
+pub(crate) struct Pending<K, V> {
+    values: HashMap<K, (u64, V)>,
+    // insertion order. entries are stale if the sequence number no longer matches.
+    order: VecDeque<(u64, K)>,
+    seq: u64,
+    max: Option<usize>,
+}
+
+impl<K: Eq + Hash + Clone, V> Pending<K, V> {
+    pub fn new(max: Option<usize>) -> Self {
+        Pending {
+            values: HashMap::new(),
+            order: VecDeque::new(),
+            seq: 0,
+            max,
+        }
+    }
+
+    /// Insert a value, replacing any previous value for the same key.
+    pub fn insert(&mut self, k: K, v: V) {
+        self.seq += 1;
+        self.values.insert(k.clone(), (self.seq, v));
+        self.order.push_back((self.seq, k));
+        if let Some(max) = self.max {
+            while self.values.len() > max {
+                self.evict_oldest();
+            }
+        }
+        if self.order.len() > 2 * self.values.len() + 16 {
+            // drop stale entries so the order doesn't grow with replacements.
+            let values = &self.values;
+            self.order
+                .retain(|(seq, k)| values.get(k).map(|(s, _)| s == seq).unwrap_or(false));
+        }
+    }
+
+    pub fn remove(&mut self, k: &K) -> Option<V> {
+        self.values.remove(k).map(|(_, v)| v)
+    }
+
+    pub fn clear(&mut self) {
+        self.values.clear();
+        self.order.clear();
+    }
+
+    fn evict_oldest(&mut self) {
+        while let Some((seq, k)) = self.order.pop_front() {
+            let current = self.values.get(&k).map(|(s, _)| *s == seq);
+            if current == Some(true) {
+                self.values.remove(&k);
+                return;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_pending_replace_moves_to_back() {
+        let mut p = Pending::new(Some(2));
+        p.insert("a", 1);
+        p.insert("b", 2);
+        p.insert("a", 3); // a is now newest
+        p.insert("c", 4); // evicts b
+        assert_eq!(p.remove(&"b"), None);
+        assert_eq!(p.remove(&"a"), Some(3));
+        assert_eq!(p.remove(&"c"), Some(4));
+    }
+}
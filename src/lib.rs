@@ -78,29 +78,172 @@
 //! Xi tries to impose a minimum of cognitive load when using it.
 //!
 //! * Every operator is an `FnMut(&T)` to make it the most usable possible.
-//! * Not require `Sync` and/or `Send` on operator functions.
+//! * Not require `Sync` on operator functions. They are `Send`, like subscribers, since
+//!   they run on whatever thread updates the sink. A subscriber can be bound to a thread
+//!   with `subscribe_local()` instead.
 //! * Xi stream instances themselves are `Sync` and `Send`.
 //! * Impose a minimum of constraints the event value `T`.
 //!
+//! What operator functions and subscribers can capture:
+//!
+//! ```
+//! use std::cell::{Cell, RefCell};
+//! use std::rc::Rc;
+//!
+//! let sink = xi::Stream::sink();
+//!
+//! // Send but not Sync is fine
+//! let seen = RefCell::new(vec![]);
+//! let count = Cell::new(0);
+//! let mapped = sink.stream().map(move |v: &u32| {
+//!     seen.borrow_mut().push(*v);
+//!     count.set(count.get() + 1);
+//!     count.get()
+//! });
+//!
+//! // a thread bound subscriber can capture anything
+//! let rc = Rc::new(0);
+//! mapped.subscribe_local(move |v| println!("{:?} {}", v, rc));
+//! ```
+//!
+//! ```compile_fail
+//! use std::rc::Rc;
+//!
+//! let sink = xi::Stream::sink();
+//! let rc = Rc::new(1);
+//!
+//! // Rc is not Send
+//! sink.stream().map(move |v: &u32| v + *rc);
+//! ```
+//!
+//! ```compile_fail
+//! use std::rc::Rc;
+//!
+//! let sink = xi::Stream::sink();
+//! let rc = Rc::new(1);
+//!
+//! sink.stream().filter(move |v: &u32| *v > *rc);
+//! ```
+//!
+//! ```compile_fail
+//! use std::rc::Rc;
+//!
+//! let sink = xi::Stream::sink();
+//! let rc = Rc::new(1);
+//!
+//! sink.stream().fold(0, move |acc, v: &u32| acc + v + *rc);
+//! ```
+//!
+//! ```compile_fail
+//! use std::rc::Rc;
+//!
+//! let sink = xi::Stream::sink();
+//!
+//! // the state is kept in the operator
+//! sink.stream().map_with_state(Rc::new(0), |s, v: &u32| **s + v);
+//! ```
+//!
+//! ```compile_fail
+//! use std::rc::Rc;
+//!
+//! let sink = xi::Stream::sink();
+//! let rc = Rc::new(1);
+//!
+//! sink.stream().tap(move |_: &u32| println!("{}", rc));
+//! ```
+//!
+//! ```compile_fail
+//! use std::rc::Rc;
+//!
+//! let sink = xi::Stream::sink();
+//! let rc = Rc::new(1);
+//!
+//! sink.stream().subscribe_pair(move |_: &u32| println!("{}", rc), || {});
+//! ```
+//!
+//! ```compile_fail
+//! use std::rc::Rc;
+//!
+//! // a sink of values that aren't Send can't move to another thread
+//! let sink = xi::Stream::sink();
+//! sink.update(Rc::new(1));
+//! std::thread::spawn(move || sink.update(Rc::new(2)));
+//! ```
+//!
 //! ## Subscription lifetimes
 //!
 //! See [`Subscription`](struct.Subscription.html#subscription-lifetimes)
+//!
+//! ## Clone
+//!
+//! Events are passed by reference through the tree. Only operators that must keep an
+//! event beyond its dispatch require `T: Clone`:
+//!
+//! * `collect()`, `last()` and `remember()` keep copies of events.
+//! * `dedupe()` and `dedupe_with()` keep the previous event to compare with. Use
+//!   `dedupe_by()` to keep an extracted value instead. A dedupe storing nothing can't
+//!   hand the previous event to a comparison, so there is no such variant.
+//! * `pairwise()` keeps the previous event to pair with.
+//! * `sample_combine()` and `combineN()` keep the latest events to build tuples.
+//! * `zip()` queues events waiting for their counterpart.
+//! * `imitator()` and `merge_left_first()` hold back events until the update has
+//!   propagated.
+//!
+//! For `last()`, [`last_by()`](struct.Stream.html#method.last_by) extracts an owned value
+//! from each event and has no `Clone` requirement.
+//!
+//! ## Diagnostics
+//!
+//! Counters and other diagnostics are behind the `diagnostics` feature. Without it, they
+//! are not compiled at all, and dispatching an event costs exactly what it would without
+//! them. With it, each dispatch and each added listener is a relaxed atomic increment,
+//! readable through `stats()`.
 
 #![warn(clippy::all)]
 #![allow(clippy::new_without_default)]
 
-use std::sync::atomic::{AtomicUsize, Ordering};
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::hash::Hash;
+use std::ops::{Bound, RangeBounds};
+use std::sync::atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering};
 use std::sync::{Arc, Condvar, Mutex};
 
+#[cfg(any(test, feature = "bench"))]
+pub mod bench_support;
+mod bridge;
+#[macro_use]
+mod diag;
 mod imit;
 mod inner;
+pub mod io;
+mod keyed;
+mod mailbox;
 mod peg;
+mod policy;
+mod shutdown;
+mod step;
 mod sub;
+pub mod test;
 
+pub use crate::bridge::{BridgeReceiver, OverflowPolicy};
+#[cfg(feature = "diagnostics")]
+pub use crate::diag::{stats, Stats};
 pub use crate::imit::Imitator;
-use crate::inner::{MemoryMode, SafeInner, IMITATORS};
-use crate::peg::Peg;
-pub use crate::sub::Subscription;
+pub use crate::inner::flush_deferred;
+use crate::inner::{
+    check_misdispatch, defer, misdispatched, run_deferred, Inner, MemoryMode, SafeInner, WeakInner,
+};
+use crate::keyed::Pending;
+pub use crate::mailbox::Mailbox;
+use crate::peg::{Peg, Pegged, WeakPeg};
+pub use crate::policy::{set_ended_update_policy, EndedUpdatePolicy};
+pub use crate::shutdown::shutdown;
+use crate::shutdown::InFlight;
+pub use crate::step::Step;
+pub use crate::sub::{StreamEnded, Subscription, SubscriptionSet};
+
+/// Process wide sequence for Stream::sequenced().
+static SEQUENCE: AtomicU64 = AtomicU64::new(0);
 
 /// A stream of events, values in time.
 ///
@@ -143,6 +286,16 @@ impl<T> Stream<T> {
         Sink::new()
     }
 
+    /// Create a sink that is ended by [`xi::shutdown()`](fn.shutdown.html).
+    ///
+    /// The sink is otherwise the same as one created by
+    /// [`Stream::sink()`](struct.Stream.html#method.sink).
+    pub fn sink_registered() -> Sink<T> {
+        let sink = Sink::new();
+        shutdown::register(&sink.inner);
+        sink
+    }
+
     /// Create a stream with memory that only emits one single value to anyone subscribing.
     ///
     /// ```
@@ -156,11 +309,154 @@ impl<T> Stream<T> {
     /// assert_eq!(coll1.take(), [42]);
     /// assert_eq!(coll2.take(), [42]);
     /// ```
-    pub fn of(value: T) -> Stream<T>
+    pub fn of(value: T) -> Stream<T> {
+        let inner = SafeInner::new(MemoryMode::KeepUntilEnd, Some(value));
+        Stream {
+            peg: Peg::new_fake(),
+            inner,
+        }
+    }
+
+    /// Create a stream that emits a value to every subscriber, and never ends.
+    ///
+    /// The same as [`of()`](struct.Stream.html#method.of). The value is handed out by
+    /// reference, so any number of subscribers get it without it being cloned. Handy as
+    /// a constant in combining operators.
+    ///
+    /// ```
+    /// use xi::Stream;
+    ///
+    /// let sink = Stream::sink();
+    ///
+    /// let scaled = Stream::combine2(&sink.stream(), &Stream::repeat(10))
+    ///     .map(|(v, factor)| v * factor);
+    ///
+    /// let coll = scaled.collect();
+    ///
+    /// sink.update(1);
+    /// sink.update(2);
+    ///
+    /// assert_eq!(coll.take(), vec![10, 20]);
+    /// ```
+    pub fn repeat(value: T) -> Stream<T> {
+        Stream::of(value)
+    }
+
+    /// Create a stream of `n` copies of a value, which then ends.
+    ///
+    /// Like [`from_iter()`](struct.Stream.html#method.from_iter), every subscriber receives
+    /// all of them.
+    ///
+    /// ```
+    /// use xi::Stream;
+    ///
+    /// assert_eq!(Stream::repeat_n("x", 3).collect().wait(), vec!["x", "x", "x"]);
+    /// assert_eq!(Stream::repeat_n("x", 0).collect().wait(), Vec::<&str>::new());
+    /// ```
+    pub fn repeat_n(value: T, n: usize) -> Stream<T>
     where
         T: Clone,
     {
-        let inner = SafeInner::new(MemoryMode::KeepUntilEnd, Some(value));
+        // std::iter::repeat_n() needs Rust 1.82.
+        #[allow(clippy::manual_repeat_n)]
+        Stream::from_iter(std::iter::repeat(value).take(n))
+    }
+
+    /// Create a stream of the values returned by a closure, until it returns `None`.
+    ///
+    /// The stream equivalent of [`std::iter::from_fn`]. The closure is called repeatedly
+    /// straight away, and the stream remembers the values like
+    /// [`from_iter()`](struct.Stream.html#method.from_iter).
+    ///
+    /// ```
+    /// use xi::Stream;
+    ///
+    /// let mut count = 0;
+    /// let counter = Stream::from_fn(move || {
+    ///     count += 1;
+    ///     if count <= 3 { Some(count) } else { None }
+    /// });
+    ///
+    /// assert_eq!(counter.collect().wait(), vec![1, 2, 3]);
+    /// ```
+    pub fn from_fn<F>(f: F) -> Stream<T>
+    where
+        F: FnMut() -> Option<T>,
+    {
+        Stream::from_iter(std::iter::from_fn(f))
+    }
+
+    /// Create a stream counting through a range of integers.
+    ///
+    /// The values are emitted in order straight away, and the stream ends. Like
+    /// [`from_iter()`](struct.Stream.html#method.from_iter), every subscriber receives all
+    /// of them. Panics if the range has no end.
+    ///
+    /// ```
+    /// use xi::Stream;
+    ///
+    /// assert_eq!(Stream::range(0..3).collect().wait(), vec![0, 1, 2]);
+    /// assert_eq!(Stream::range(-1_i64..=1).collect().wait(), vec![-1, 0, 1]);
+    /// ```
+    pub fn range<R>(range: R) -> Stream<T>
+    where
+        T: Step,
+        R: RangeBounds<T>,
+    {
+        let start = match range.start_bound() {
+            Bound::Included(s) => Some(*s),
+            Bound::Excluded(s) => s.forward(),
+            Bound::Unbounded => Some(T::min_value()),
+        };
+        let end = match range.end_bound() {
+            Bound::Included(e) => Some(*e),
+            Bound::Excluded(e) => e.backward(),
+            Bound::Unbounded => panic!("range() without an end"),
+        };
+        let first = match (start, end) {
+            (Some(start), Some(end)) if start <= end => Some(start),
+            _ => None,
+        };
+        // end is only None if the first value would be past it.
+        let end = end.unwrap_or_else(T::min_value);
+        Stream::from_iter(std::iter::successors(first, move |v| {
+            if *v < end {
+                v.forward()
+            } else {
+                None
+            }
+        }))
+    }
+
+    /// Create a stream of the values of an iterator.
+    ///
+    /// The iterator is run to completion straight away, and the stream ends. The stream
+    /// has memory of all the values: every subscriber receives all of them in order,
+    /// followed by the end. Like for other memory streams, an operator receives the values
+    /// when it is created, so they are gone by the time anyone subscribes to the operator.
+    ///
+    /// ```
+    /// use xi::Stream;
+    ///
+    /// let numbers = Stream::from_iter(1..=3);
+    ///
+    /// // every subscriber gets all the values
+    /// assert_eq!(numbers.collect().wait(), vec![1, 2, 3]);
+    /// assert_eq!(numbers.collect().wait(), vec![1, 2, 3]);
+    /// ```
+    #[allow(clippy::should_implement_trait)]
+    pub fn from_iter<I>(iter: I) -> Stream<T>
+    where
+        I: IntoIterator<Item = T>,
+    {
+        let inner = SafeInner::new(MemoryMode::KeepAll, None);
+        {
+            let mut lock = inner.lock();
+            for t in iter {
+                lock.update_owned(Some(t));
+            }
+            lock.update_owned(None);
+        }
         Stream {
             peg: Peg::new_fake(),
             inner,
@@ -184,6 +480,94 @@ impl<T> Stream<T> {
         }
     }
 
+    /// Concatenate streams, one after the other.
+    ///
+    /// Subscribes to the first stream, and only once that ends, to the second, and so on.
+    /// Events emitted by a stream before its turn are not seen, apart from a remembered
+    /// value delivered when subscribing. The stream ends when the last stream ends, and an
+    /// empty vec gives a stream that is ended straight away.
+    ///
+    /// ```
+    /// use xi::Stream;
+    ///
+    /// let sink1 = Stream::sink();
+    /// let sink2 = Stream::sink();
+    ///
+    /// let concat = Stream::concat(vec![sink1.stream(), sink2.stream()]);
+    ///
+    /// let coll = concat.collect();
+    ///
+    /// sink1.update(0);
+    /// sink2.update(10); // lost, sink1 hasn't ended
+    /// sink1.update(1);
+    /// sink1.end();
+    /// sink2.update(11);
+    /// sink2.end();
+    ///
+    /// assert_eq!(coll.wait(), vec![0, 1, 11]);
+    /// ```
+    pub fn concat(streams: Vec<Stream<T>>) -> Stream<T> {
+        let inner = SafeInner::new(MemoryMode::NoMemory, None);
+        // the subscriptions are held by the peg of the concatenated stream.
+        let (pegged, peg) = Pegged::new_pair(vec![]);
+        let mut streams = VecDeque::from(streams);
+        let next: NextStream<T> = Arc::new(Mutex::new(move || streams.pop_front()));
+        Stream::concat_next(next, inner.clone(), Arc::new(pegged));
+        Stream { peg, inner }
+    }
+
+    /// Subscribe to the next stream of a `concat()`, or end if there are none left.
+    ///
+    /// A stream ending while it is being subscribed to moves straight on to the next one.
+    /// Otherwise it ends while dispatching, maybe with the lock of a stream that comes
+    /// later held, so the next subscription is deferred until the dispatch is done.
+    fn concat_next(next: NextStream<T>, inner: SafeInner<T>, pegged: Arc<Pegged<Vec<Peg>>>) {
+        loop {
+            let stream = (next.lock().unwrap())();
+            let stream = match stream {
+                Some(stream) => stream,
+                None => {
+                    inner.lock().update_borrowed(None);
+                    return;
+                }
+            };
+            // whether the stream is being subscribed to, and whether it ended meanwhile.
+            let subscribing = Arc::new(Mutex::new((true, false)));
+            let inner_clone = inner.clone();
+            let pegged_clone = pegged.clone();
+            let next_clone = next.clone();
+            let subscribing_clone = subscribing.clone();
+            let sub = stream.internal_subscribe(move |t| {
+                if t.is_some() {
+                    inner_clone.lock().update_borrowed(t);
+                    return;
+                }
+                let mut lock = subscribing_clone.lock().unwrap();
+                if lock.0 {
+                    lock.1 = true;
+                } else {
+                    let next = next_clone.clone();
+                    let inner = inner_clone.clone();
+                    let pegged = pegged_clone.clone();
+                    defer(move || {
+                        Stream::concat_next(next.clone(), inner.clone(), pegged.clone());
+                    });
+                }
+            });
+            let mut sub = Some(sub);
+            pegged.with_value(|subs| {
+                if let Some(subs) = subs {
+                    subs.extend(sub.take());
+                }
+            });
+            let mut lock = subscribing.lock().unwrap();
+            lock.0 = false;
+            if !lock.1 {
+                return;
+            }
+        }
+    }
+
     /// Check if this stream has "memory".
     ///
     /// Streams with memory keeps a copy of the last value they produced so that any
@@ -211,6 +595,39 @@ impl<T> Stream<T> {
         self.inner.lock().memory_mode().is_memory()
     }
 
+    /// Check if this stream has ended.
+    ///
+    /// Once ended, a stream stays ended. Subscribing to an ended stream immediately
+    /// delivers the `None` end event.
+    ///
+    /// ```
+    /// let sink = xi::Stream::sink();
+    /// let stream = sink.stream();
+    ///
+    /// sink.update(0);
+    /// assert!(!stream.is_ended());
+    ///
+    /// sink.end();
+    /// assert!(stream.is_ended());
+    /// ```
+    pub fn is_ended(&self) -> bool {
+        self.inner.lock().is_ended()
+    }
+
+    /// An id for the events of this stream, unique in the process. Clones of a stream, and
+    /// the streams of a sink, share it.
+    ///
+    /// ```
+    /// let sink = xi::Stream::<u32>::sink();
+    /// let stream = sink.stream();
+    ///
+    /// assert_eq!(stream.id(), sink.id());
+    /// assert_ne!(stream.id(), stream.map(|v| v + 1).id());
+    /// ```
+    pub fn id(&self) -> usize {
+        self.inner.lock().id()
+    }
+
     /// Creates an imitator. Imitators are used to make cyclic streams.
     ///
     ///
@@ -249,1059 +666,7831 @@ impl<T> Stream<T> {
     ///
     /// handle.join();
     /// ```
+    ///
+    /// The subscriber runs on whatever thread updates the sink, so it must be `Send`. Use
+    /// [`.subscribe_local()`](struct.Stream.html#method.subscribe_local) for subscribers
+    /// that are bound to one thread.
+    ///
+    /// ```compile_fail
+    /// use std::rc::Rc;
+    ///
+    /// let sink = xi::Stream::sink();
+    /// let rc = Rc::new(0);
+    ///
+    /// // Rc is not Send
+    /// sink.stream().subscribe(move |v: Option<&u32>| println!("{}", rc));
+    /// ```
     pub fn subscribe<F>(&self, f: F) -> Subscription
     where
-        F: FnMut(Option<&T>) + 'static,
+        F: FnMut(Option<&T>) + Send + 'static,
     {
-        let peg = self.inner.lock().add(f);
-        peg.keep_mode();
-        Subscription::new(peg)
-    }
-
-    /// Internal subscribe that stops subscribing if the subscription goes out of scope.
-    fn internal_subscribe<F: FnMut(Option<&T>) + 'static>(&self, f: F) -> Peg {
-        let mut peg = self.inner.lock().add(f);
-        peg.add_related(self.peg.clone());
-        peg
+        self.subscribe_unchecked(f)
     }
 
-    /// Collect events into a `Collector`. This is mostly interesting for testing.
+    /// Subscribe to events from this stream, without delivering a remembered value inside
+    /// the call.
+    ///
+    /// Where [`.subscribe()`](struct.Stream.html#method.subscribe) hands the remembered value
+    /// to the subscriber before returning, this queues it, together with any event arriving
+    /// in the meantime. The queue is delivered in order by whichever comes first: the next
+    /// event on the stream, from any thread; the next update on the subscribing thread,
+    /// once it has propagated; or an explicit
+    /// [`xi::flush_deferred()`](fn.flush_deferred.html) on the subscribing thread. That
+    /// gives the caller a chance to finish wiring up before the subscriber starts firing.
     ///
     /// ```
+    /// use std::sync::{Arc, Mutex};
+    ///
     /// let sink = xi::Stream::sink();
+    /// let rem = sink.stream().remember();
+    /// sink.update(1);
     ///
-    /// // collect all values emitted into the sink
-    /// let coll = sink.stream().collect();
+    /// let seen = Arc::new(Mutex::new(vec![]));
+    /// let seen2 = seen.clone();
     ///
-    /// std::thread::spawn(move || {
-    ///   sink.update(0);
-    ///   sink.update(1);
-    ///   sink.update(2);
-    ///   sink.end();
+    /// let sub = rem.subscribe_deferred_replay(move |v| if let Some(v) = v {
+    ///     seen2.lock().unwrap().push(*v);
     /// });
     ///
-    /// let result = coll.wait(); // wait for stream to end
-    /// assert_eq!(result, vec![0, 1, 2]);
+    /// // nothing delivered yet
+    /// assert!(seen.lock().unwrap().is_empty());
+    ///
+    /// xi::flush_deferred();
+    ///
+    /// assert_eq!(*seen.lock().unwrap(), vec![1]);
     /// ```
-    pub fn collect(&self) -> Collector<T>
+    ///
+    /// Unsubscribing before the queue is delivered discards it.
+    #[allow(clippy::type_complexity)]
+    pub fn subscribe_deferred_replay<F>(&self, f: F) -> Subscription
     where
         T: Clone,
+        F: FnMut(Option<&T>) + Send + 'static,
     {
-        let state = Arc::new((Mutex::new((false, Some(vec![]))), Condvar::new()));
-        let clone = state.clone();
-        let peg = self.internal_subscribe(move |t| {
-            let mut lock = clone.0.lock().unwrap();
-            if let Some(t) = t {
-                if let Some(v) = lock.1.as_mut() {
-                    v.push(t.clone());
+        let f = Arc::new(Mutex::new(f));
+        let f2 = f.clone();
+        // events waiting to be delivered, None once delivered, and whether someone is
+        // delivering them. Nobody delivers until the subscription is set up.
+        let pending: Arc<Mutex<(Option<Vec<Option<T>>>, bool)>> =
+            Arc::new(Mutex::new((Some(vec![]), true)));
+        let drain = {
+            let pending = pending.clone();
+            Arc::new(move || loop {
+                let queued = {
+                    let mut lock = pending.lock().unwrap();
+                    match lock.0.take() {
+                        Some(queued) if !queued.is_empty() => {
+                            // keep queueing while delivering, to stay in order.
+                            lock.0 = Some(vec![]);
+                            queued
+                        }
+                        _ => {
+                            *lock = (None, false);
+                            return;
+                        }
+                    }
+                };
+                let mut f = f.lock().unwrap();
+                for t in queued {
+                    f(t.as_ref());
                 }
-            } else {
-                lock.0 = true;
-                clone.1.notify_all();
+            })
+        };
+        let pending2 = pending.clone();
+        let drain2 = drain.clone();
+        let peg = self.inner.lock().add(move |t| {
+            let mut lock = pending2.lock().unwrap();
+            if let Some(queue) = lock.0.as_mut() {
+                queue.push(t.cloned());
+                // the next event, from whichever thread, delivers the queue.
+                if !lock.1 {
+                    lock.1 = true;
+                    drop(lock);
+                    drain2();
+                }
+                return;
+            }
+            drop(lock);
+            (f2.lock().unwrap())(t);
+        });
+        peg.keep_mode();
+        {
+            let mut lock = pending.lock().unwrap();
+            if lock.0.as_ref().map(|q| q.is_empty()).unwrap_or(true) {
+                // nothing to replay
+                *lock = (None, false);
+                return Subscription::new(peg);
+            }
+            lock.1 = false;
+        }
+        // a stream ended on subscribe has no listener left to unsubscribe.
+        let live = peg.is_pegged();
+        let peg2 = peg.clone();
+        defer(move || {
+            {
+                let mut lock = pending.lock().unwrap();
+                if live && !peg2.is_pegged() {
+                    // unsubscribed before the flush
+                    *lock = (None, true);
+                    return;
+                }
+                if lock.1 {
+                    // already being delivered
+                    return;
+                }
+                lock.1 = true;
             }
+            drain();
         });
-        Collector { peg, state }
+        Subscription::new(peg)
     }
 
-    /// Dedupe stream by the event itself.
+    /// Subscribe with one function for the events and another for the end.
     ///
-    /// This clones every event to compare with the next.
+    /// The same as [`subscribe()`](struct.Stream.html#method.subscribe), without matching
+    /// on the `Option`. `end_f` is called once, when the stream ends.
     ///
     /// ```
+    /// use std::sync::{Arc, Mutex};
+    ///
     /// let sink = xi::Stream::sink();
     ///
-    /// let deduped = sink.stream().dedupe();
+    /// let log = Arc::new(Mutex::new(vec![]));
+    /// let (log1, log2) = (log.clone(), log.clone());
     ///
-    /// let coll = deduped.collect();
+    /// sink.stream().subscribe_pair(
+    ///     move |v| log1.lock().unwrap().push(format!("got {}", v)),
+    ///     move || log2.lock().unwrap().push("done".to_string()),
+    /// );
     ///
-    /// sink.update(0);
-    /// sink.update(0);
-    /// sink.update(1);
     /// sink.update(1);
     /// sink.end();
     ///
-    /// assert_eq!(coll.wait(), vec![0, 1]);
+    /// assert_eq!(*log.lock().unwrap(), vec!["got 1", "done"]);
     /// ```
-    pub fn dedupe(&self) -> Stream<T>
+    pub fn subscribe_pair<F, E>(&self, mut next_f: F, mut end_f: E) -> Subscription
     where
-        T: Clone + PartialEq,
+        F: FnMut(&T) + Send + 'static,
+        E: FnMut() + Send + 'static,
     {
-        self.dedupe_by(|v| v.clone())
+        self.subscribe(move |t| match t {
+            Some(t) => next_f(t),
+            None => end_f(),
+        })
     }
 
-    /// Dedupe stream by some extracted value.
+    /// Subscribe to events from this stream with a subscriber bound to the current thread.
     ///
-    /// ```
-    /// use xi::{Stream, Sink};
+    /// The subscriber doesn't need to be `Send`, for instance it can capture an `Rc`.
+    /// Instead the calling thread is recorded, and the subscriber only ever runs on that
+    /// thread. An update from another thread that reaches the subscriber skips it, and
+    /// panics once the update has propagated, so no stream is left locked or poisoned.
     ///
-    /// #[derive(Clone, Debug)]
-    /// struct Foo(&'static str, usize);
+    /// ```
+    /// use std::cell::RefCell;
+    /// use std::rc::Rc;
     ///
-    /// let sink: Sink<Foo> = Stream::sink();
+    /// let sink = xi::Stream::sink();
     ///
-    /// // dedupe this stream of Foo on the contained usize
-    /// let deduped = sink.stream().dedupe_by(|v| v.1);
+    /// let seen = Rc::new(RefCell::new(vec![]));
+    /// let seen_clone = seen.clone();
     ///
-    /// let coll = deduped.collect();
+    /// sink.stream().subscribe_local(move |v| if let Some(v) = v {
+    ///     seen_clone.borrow_mut().push(*v);
+    /// });
     ///
-    /// sink.update(Foo("yo", 1));
-    /// sink.update(Foo("bro", 1));
-    /// sink.update(Foo("lo", 2));
-    /// sink.update(Foo("lo", 2));
-    /// sink.end();
+    /// sink.update(0);
+    /// sink.update(1);
     ///
-    /// assert_eq!(format!("{:?}", coll.wait()),
-    ///     "[Foo(\"yo\", 1), Foo(\"lo\", 2)]");
+    /// assert_eq!(*seen.borrow(), vec![0, 1]);
     /// ```
-    pub fn dedupe_by<U, F>(&self, mut f: F) -> Stream<T>
+    ///
+    /// The subscriber is dropped when it is released on its own thread. If it is released
+    /// on another thread, for instance when the stream ends there, it is leaked instead.
+    pub fn subscribe_local<F>(&self, f: F) -> Subscription
     where
-        U: PartialEq + 'static,
-        F: FnMut(&T) -> U + 'static,
+        F: FnMut(Option<&T>) + 'static,
     {
-        let inner = SafeInner::new(MemoryMode::NoMemory, None);
-        let inner_clone = inner.clone();
-        let mut prev: Option<U> = None;
-        let peg = self.internal_subscribe(move |t| {
-            if let Some(t) = t {
-                let propagate = match (prev.take(), f(t)) {
-                    (None, u) => {
-                        // no previous value, save this and propagate
-                        prev = Some(u);
-                        true
-                    }
-                    (Some(pu), u) => {
-                        if pu != u {
-                            // new value is different to previous, save and propagate
-                            prev = Some(u);
-                            true
-                        } else {
-                            // new value is same as before, don't propagate
-                            false
-                        }
-                    }
-                };
-                if propagate {
-                    inner_clone.lock().update_borrowed(Some(t));
-                }
-            } else {
-                inner_clone.lock().update_borrowed(t);
+        let mut local = Local {
+            f: Some(f),
+            thread: std::thread::current().id(),
+        };
+        self.subscribe_unchecked(move |t| {
+            if std::thread::current().id() != local.thread {
+                misdispatched();
+                return;
             }
-        });
-        Stream { peg, inner }
+            if let Some(f) = local.f.as_mut() {
+                f(t);
+            }
+        })
     }
 
-    /// Drop an amount of initial values.
+    /// Internal subscribe without the Send requirement.
+    fn subscribe_unchecked<F>(&self, f: F) -> Subscription
+    where
+        F: FnMut(Option<&T>) + 'static,
+    {
+        let peg = self.inner.lock().add(f);
+        peg.keep_mode();
+        Subscription::new(peg)
+    }
+
+    /// Subscribe to events from this stream, unless it has already ended.
+    ///
+    /// Where [`.subscribe()`](struct.Stream.html#method.subscribe) delivers the end event
+    /// straight away to a listener added on an ended stream, this refuses to register the
+    /// listener and returns an error instead. The check is done under the same lock as the
+    /// registration, so the answer is authoritative even if another thread ends the stream
+    /// concurrently.
     ///
     /// ```
-    /// let sink = xi::Stream::sink();
+    /// use xi::{Stream, StreamEnded};
     ///
-    /// // drop 2 initial values
-    /// let dropped = sink.stream().drop(2);
+    /// let sink = Stream::sink();
+    /// let stream = sink.stream();
     ///
-    /// let coll = dropped.collect();
+    /// assert!(stream.try_subscribe(|v: Option<&u32>| {}).is_ok());
     ///
-    /// sink.update(0);
-    /// sink.update(1);
-    /// sink.update(2);
-    /// sink.update(3);
     /// sink.end();
     ///
-    /// assert_eq!(coll.wait(), vec![2, 3]);
+    /// assert_eq!(stream.try_subscribe(|_| {}).err(), Some(StreamEnded));
     /// ```
-    pub fn drop(&self, amount: usize) -> Stream<T> {
-        let mut todo = amount + 1;
-        self.drop_while(move |_| {
-            if todo > 0 {
-                todo -= 1;
-            }
-            todo > 0
-        })
+    pub fn try_subscribe<F>(&self, f: F) -> Result<Subscription, StreamEnded>
+    where
+        F: FnMut(Option<&T>) + Send + 'static,
+    {
+        let mut lock = self.inner.lock();
+        if lock.is_ended() {
+            return Err(StreamEnded);
+        }
+        let peg = lock.add(f);
+        peg.keep_mode();
+        Ok(Subscription::new(peg))
     }
 
-    /// Don't take values while some condition holds true. Once the condition is false,
-    /// the resulting stream emits all events.
+    /// Internal subscribe that stops subscribing if the subscription goes out of scope.
+    fn internal_subscribe<F: FnMut(Option<&T>) + 'static>(&self, f: F) -> Peg {
+        let mut peg = self.inner.lock().add(f);
+        peg.add_related(self.peg.clone());
+        peg
+    }
+
+    /// Route events through one of two sub-pipelines depending on a predicate, and merge
+    /// the results.
+    ///
+    /// Events for which `pred` returns true go into the stream given to `true_branch`,
+    /// the others into the stream given to `false_branch`, as split by
+    /// [`partition()`](struct.Stream.html#method.partition). The resulting stream ends when
+    /// both branches have ended.
     ///
     /// ```
     /// let sink = xi::Stream::sink();
     ///
-    /// // drop initial odd values
-    /// let dropped = sink.stream().drop_while(|v| v % 2 == 1);
+    /// let branched = sink.stream().branch_if(
+    ///     |v| v % 2 == 0,
+    ///     |even| even.map(|v| format!("{} is even", v)),
+    ///     |odd| odd.map(|v| format!("{} is odd", v)),
+    /// );
     ///
-    /// let coll = dropped.collect();
+    /// let coll = branched.collect();
     ///
     /// sink.update(1);
-    /// sink.update(3);
-    /// sink.update(4);
-    /// sink.update(5); // not dropped
+    /// sink.update(2);
     /// sink.end();
     ///
-    /// assert_eq!(coll.wait(), vec![4, 5]);
+    /// assert_eq!(coll.wait(), vec!["1 is odd", "2 is even"]);
     /// ```
-    pub fn drop_while<F>(&self, mut f: F) -> Stream<T>
+    pub fn branch_if<U, P, FT, FF>(&self, pred: P, true_branch: FT, false_branch: FF) -> Stream<U>
     where
-        F: FnMut(&T) -> bool + 'static,
+        P: FnMut(&T) -> bool + Send + 'static,
+        FT: FnOnce(Stream<T>) -> Stream<U>,
+        FF: FnOnce(Stream<T>) -> Stream<U>,
     {
-        let inner = SafeInner::new(MemoryMode::NoMemory, None);
-        let inner_clone = inner.clone();
-        let mut dropping = true;
-        let peg = self.internal_subscribe(move |t| {
-            if let Some(t) = t {
-                if dropping && !f(t) {
-                    dropping = false;
-                }
-                if dropping {
-                    return;
-                }
-                inner_clone.lock().update_borrowed(Some(t));
-            } else {
-                inner_clone.lock().update_borrowed(t);
-            }
-        });
-        Stream { peg, inner }
+        let (yes, no) = self.partition(pred);
+        Stream::merge(vec![true_branch(yes), false_branch(no)])
     }
 
-    /// Produce a stream that ends when some other stream ends.
+    /// Hand events over to another thread through a bounded queue.
+    ///
+    /// The events are queued as they arrive, and are received by the returned
+    /// [`BridgeReceiver`](struct.BridgeReceiver.html), typically on a slower consumer
+    /// thread. When the queue holds `capacity` events, the `policy` decides whether to drop
+    /// an event or to block the thread updating the stream. The end is always delivered,
+    /// once the queued events have been received.
+    ///
+    /// Panics if `capacity` is 0.
     ///
     /// ```
-    /// use xi::Stream;
+    /// use xi::OverflowPolicy;
     ///
-    /// let sink1 = Stream::sink();
-    /// let sink2 = Stream::sink();
+    /// let sink = xi::Stream::sink();
     ///
-    /// // ending shows values of sink1, but ends when sink2 does.
-    /// let ending = sink1.stream().end_when(&sink2.stream());
+    /// let rx = sink.stream().bridge_bounded(16, OverflowPolicy::BlockProducer);
     ///
-    /// let coll = ending.collect();
+    /// let consumer = std::thread::spawn(move || {
+    ///     let mut sum = 0;
+    ///     while let Some(v) = rx.recv() {
+    ///         sum += v;
+    ///     }
+    ///     sum
+    /// });
     ///
-    /// sink1.update(0);
-    /// sink2.update("yo");
-    /// sink1.update(1);
-    /// sink2.end();
-    /// sink1.update(2); // collector never sees this value
+    /// for i in 0..100 {
+    ///     sink.update(i);
+    /// }
+    /// sink.end();
     ///
-    /// assert_eq!(coll.wait(), [0, 1]);
+    /// assert_eq!(consumer.join().unwrap(), 4950);
     /// ```
-    pub fn end_when<U>(&self, other: &Stream<U>) -> Stream<T> {
-        let inner = SafeInner::new(MemoryMode::NoMemory, None);
-        let inner_clone1 = inner.clone();
-        let inner_clone2 = inner.clone();
-        let peg1 = other.internal_subscribe(move |o| {
-            if o.is_none() {
-                inner_clone1.lock().update_borrowed(None);
-            }
-        });
-        let peg2 = self.internal_subscribe(move |t| {
-            inner_clone2.lock().update_borrowed(t);
-        });
-        let peg = Peg::many(vec![peg1, peg2]);
-        Stream { peg, inner }
+    pub fn bridge_bounded(&self, capacity: usize, policy: OverflowPolicy) -> BridgeReceiver<T>
+    where
+        T: Clone,
+    {
+        BridgeReceiver::new(self, capacity, policy)
     }
 
-    /// Filter out a subset of the events in the stream.
+    /// Batch events into vecs of `n` events.
+    ///
+    /// When the stream ends, a partially filled batch is emitted before the end. Panics if
+    /// `n` is 0.
     ///
     /// ```
     /// let sink = xi::Stream::sink();
     ///
-    /// // keep even numbers
-    /// let filtered = sink.stream().filter(|v| v % 2 == 0);
+    /// let batched = sink.stream().buffer_count(2);
     ///
-    /// let coll = filtered.collect();
+    /// let coll = batched.collect();
     ///
-    /// sink.update(0);
     /// sink.update(1);
     /// sink.update(2);
+    /// sink.update(3);
     /// sink.end();
     ///
-    /// assert_eq!(coll.wait(), vec![0, 2]);
+    /// assert_eq!(coll.wait(), vec![vec![1, 2], vec![3]]);
     /// ```
-    pub fn filter<F>(&self, mut f: F) -> Stream<T>
+    pub fn buffer_count(&self, n: usize) -> Stream<Vec<T>>
+    where
+        T: Clone,
+    {
+        assert!(n > 0, "buffer_count() with a size of 0");
+        self.batch(n, true)
+    }
+
+    /// Internal batching, with whether to emit a partially filled batch on end.
+    fn batch(&self, n: usize, flush_partial: bool) -> Stream<Vec<T>>
     where
-        F: FnMut(&T) -> bool + 'static,
+        T: Clone,
     {
         let inner = SafeInner::new(MemoryMode::NoMemory, None);
         let inner_clone = inner.clone();
+        let mut buf = Vec::new();
         let peg = self.internal_subscribe(move |t| {
             if let Some(t) = t {
-                if f(t) {
-                    inner_clone.lock().update_borrowed(Some(t));
+                buf.push(t.clone());
+                if buf.len() == n {
+                    let full = std::mem::take(&mut buf);
+                    inner_clone.lock().update_owned(Some(full));
                 }
             } else {
-                inner_clone.lock().update_borrowed(t);
+                let mut lock = inner_clone.lock();
+                if flush_partial && !buf.is_empty() {
+                    lock.update_owned(Some(std::mem::take(&mut buf)));
+                }
+                buf.clear();
+                lock.update_owned(None);
             }
         });
         Stream { peg, inner }
     }
 
-    /// Combine events from the past, with new events to produce an output.
-    ///
-    /// This is roughly equivalent to a "fold" or "reduce" over an array. For each event we
-    /// emit the latest state out. The seed value is emitted straight away.
+    /// Batch events into vecs of `size` events.
     ///
-    /// The result is always a "memory" stream.
+    /// The same as [`buffer_count()`](struct.Stream.html#method.buffer_count): when the stream
+    /// ends, a partially filled batch is emitted before the end. See
+    /// [`chunk_exact()`](struct.Stream.html#method.chunk_exact) to drop it instead. Panics if
+    /// `size` is 0.
     ///
     /// ```
     /// let sink = xi::Stream::sink();
     ///
-    /// let folded = sink.stream()
-    ///     .fold(40.5, |prev, next| prev + (*next as f32) / 2.0);
+    /// let chunked = sink.stream().chunk(3);
     ///
-    /// let coll = folded.collect();
+    /// let coll = chunked.collect();
     ///
-    /// sink.update(0);
-    /// sink.update(1);
-    /// sink.update(2);
+    /// for i in 0..5 {
+    ///     sink.update(i);
+    /// }
     /// sink.end();
     ///
-    /// assert_eq!(coll.wait(), vec![40.5, 40.5, 41.0, 42.0]);
+    /// assert_eq!(coll.wait(), vec![vec![0, 1, 2], vec![3, 4]]);
     /// ```
-    pub fn fold<U, F>(&self, seed: U, mut f: F) -> Stream<U>
-    where
-        U: 'static,
-        F: FnMut(U, &T) -> U + 'static,
-    {
-        let inner = SafeInner::new(MemoryMode::KeepUntilEnd, Some(seed));
-        let inner_clone = inner.clone();
-        let peg = self.internal_subscribe(move |t| {
-            if let Some(t) = t {
-                let mut lock = inner_clone.lock();
-                if let Some(prev) = lock.take_memory() {
-                    let next = f(prev, t);
-                    lock.update_owned(Some(next));
-                } else {
-                    panic!("fold without a previous value");
-                }
-            } else {
-                inner_clone.lock().update_owned(None);
-            }
-        });
-        Stream { peg, inner }
-    }
-
-    /// Internal imitate for imitator.
-    fn imitate(&self, imitator: SafeInner<T>) -> Peg
+    pub fn chunk(&self, size: usize) -> Stream<Vec<T>>
     where
         T: Clone,
     {
-        self.internal_subscribe(move |t| {
-            let imitator_clone = imitator.clone();
-            if t.is_some() {
-                let t_clone = t.cloned();
-                IMITATORS.with(|imit_cell| {
-                    let mut imit = imit_cell.borrow_mut();
-                    imit.push(Box::new(move || {
-                        // this is one clone too many. if we could use
-                        // Box<FnOnce> on stable, we would do that instead
-                        let t = t_clone.clone();
-                        imitator_clone.lock().update_owned(t.clone());
-                    }));
-                });
-            } else {
-                imitator_clone.lock().update_owned(None);
-            }
-        })
+        assert!(size > 0, "chunk() with a size of 0");
+        self.batch(size, true)
     }
 
-    /// Emits the last seen event when the stream closes.
+    /// Batch events into vecs of exactly `size` events.
+    ///
+    /// When the stream ends, a partially filled batch is dropped. Panics if `size` is 0.
     ///
     /// ```
     /// let sink = xi::Stream::sink();
     ///
-    /// let coll = sink.stream().last().collect();
+    /// let chunked = sink.stream().chunk_exact(3);
     ///
-    /// sink.update(0);
-    /// sink.update(1);
-    /// sink.update(2);
+    /// let coll = chunked.collect();
+    ///
+    /// for i in 0..5 {
+    ///     sink.update(i);
+    /// }
     /// sink.end();
     ///
-    /// assert_eq!(coll.wait(), vec![2]);
+    /// assert_eq!(coll.wait(), vec![vec![0, 1, 2]]);
     /// ```
-    pub fn last(&self) -> Stream<T>
+    pub fn chunk_exact(&self, size: usize) -> Stream<Vec<T>>
     where
         T: Clone,
     {
-        let inner = SafeInner::new(MemoryMode::NoMemory, None);
-        let inner_clone = inner.clone();
-        let last = Mutex::new(None);
-        let peg = self.internal_subscribe(move |t| {
-            let mut lock = last.lock().unwrap();
-            if t.is_some() {
-                *lock = t.cloned();
-            } else {
-                let mut ilock = inner_clone.lock();
-                if let Some(l) = lock.take() {
-                    ilock.update_owned(Some(l));
-                }
-                ilock.update_owned(None);
-            }
-        });
-        Stream { peg, inner }
+        assert!(size > 0, "chunk_exact() with a size of 0");
+        self.batch(size, false)
     }
 
-    /// Transform events.
+    /// Group adjacent events into chunks as long as a predicate holds between them.
+    ///
+    /// The predicate is called with the last event of the current chunk and the new event.
+    /// When it returns false, the current chunk is emitted and a new one is started. The
+    /// last chunk is emitted when the stream ends.
     ///
     /// ```
     /// let sink = xi::Stream::sink();
     ///
-    /// let mapped = sink.stream().map(|v| format!("yo {}", v));
+    /// // chunk runs of increasing numbers
+    /// let chunked = sink.stream().chunk_while(|a, b| a < b);
     ///
-    /// let coll = mapped.collect();
+    /// let coll = chunked.collect();
     ///
-    /// sink.update(41);
-    /// sink.update(42);
+    /// sink.update(1);
+    /// sink.update(2);
+    /// sink.update(0);
+    /// sink.update(3);
+    /// sink.update(3);
     /// sink.end();
     ///
-    /// assert_eq!(coll.wait(),
-    ///     vec!["yo 41".to_string(), "yo 42".to_string()]);
+    /// assert_eq!(coll.wait(), vec![vec![1, 2], vec![0, 3], vec![3]]);
     /// ```
-    pub fn map<U, F>(&self, mut f: F) -> Stream<U>
+    pub fn chunk_while<F>(&self, mut f: F) -> Stream<Vec<T>>
     where
-        U: 'static,
-        F: FnMut(&T) -> U + 'static,
+        T: Clone,
+        F: FnMut(&T, &T) -> bool + Send + 'static,
     {
         let inner = SafeInner::new(MemoryMode::NoMemory, None);
         let inner_clone = inner.clone();
+        let mut chunk: Vec<T> = vec![];
         let peg = self.internal_subscribe(move |t| {
             if let Some(t) = t {
-                let u = f(t);
-                inner_clone.lock().update_owned(Some(u));
+                let split = match chunk.last() {
+                    Some(last) => !f(last, t),
+                    None => false,
+                };
+                if split {
+                    let full = std::mem::take(&mut chunk);
+                    inner_clone.lock().update_owned(Some(full));
+                }
+                chunk.push(t.clone());
             } else {
-                inner_clone.lock().update_owned(None);
+                let mut lock = inner_clone.lock();
+                if !chunk.is_empty() {
+                    let rest = std::mem::take(&mut chunk);
+                    lock.update_owned(Some(rest));
+                }
+                lock.update_owned(None);
             }
         });
         Stream { peg, inner }
     }
 
-    /// For every event, emit a static value.
+    /// Collect events into a `Collector`. This is mostly interesting for testing.
     ///
     /// ```
     /// let sink = xi::Stream::sink();
     ///
-    /// let mapped = sink.stream().map_to(42.0);
-    ///
-    /// let coll = mapped.collect();
+    /// // collect all values emitted into the sink
+    /// let coll = sink.stream().collect();
     ///
-    /// sink.update(0);
-    /// sink.update(1);
-    /// sink.end();
+    /// std::thread::spawn(move || {
+    ///   sink.update(0);
+    ///   sink.update(1);
+    ///   sink.update(2);
+    ///   sink.end();
+    /// });
     ///
-    /// assert_eq!(coll.wait(), vec![42.0, 42.0]);
+    /// let result = coll.wait(); // wait for stream to end
+    /// assert_eq!(result, vec![0, 1, 2]);
     /// ```
-    pub fn map_to<U>(&self, u: U) -> Stream<U>
+    pub fn collect(&self) -> Collector<T>
     where
-        U: Clone + 'static,
+        T: Clone,
     {
-        self.map(move |_| u.clone())
+        let state = Arc::new((Mutex::new((false, Some(vec![]))), Condvar::new()));
+        let hints = Arc::new((AtomicUsize::new(0), AtomicBool::new(false)));
+        let clone = state.clone();
+        let hints_clone = hints.clone();
+        let peg = self.internal_subscribe(move |t| {
+            let mut lock = clone.0.lock().unwrap();
+            if let Some(t) = t {
+                if let Some(v) = lock.1.as_mut() {
+                    v.push(t.clone());
+                    hints_clone.0.fetch_add(1, Ordering::Relaxed);
+                }
+            } else {
+                lock.0 = true;
+                hints_clone.1.store(true, Ordering::Relaxed);
+                clone.1.notify_all();
+            }
+        });
+        Collector { peg, state, hints }
     }
 
-    /// Merge events from a bunch of streams to one stream.
+    /// Combine the latest values of this and another stream, and only emit when the pair
+    /// changes.
     ///
-    /// ```
-    /// use xi::Stream;
+    /// This is [`combine2()`](struct.Stream.html#method.combine2) followed by
+    /// [`dedupe()`](struct.Stream.html#method.dedupe), for state derived from two streams
+    /// where an update to the same value shouldn't emit again.
     ///
-    /// let sink1 = Stream::sink();
-    /// let sink2 = Stream::sink();
+    /// ```
+    /// let sink1 = xi::Stream::sink();
+    /// let sink2 = xi::Stream::sink();
     ///
-    /// let merged = Stream::merge(vec![
-    ///     sink1.stream(),
-    ///     sink2.stream()
-    /// ]);
+    /// let comb = sink1.stream().combine_and_dedupe(&sink2.stream());
     ///
-    /// let coll = merged.collect();
+    /// let coll = comb.collect();
     ///
-    /// sink1.update(0);
-    /// sink2.update(10);
     /// sink1.update(1);
-    /// sink2.update(11);
+    /// sink2.update("a");
+    /// sink2.update("a"); // no change
+    /// sink1.update(2);
     /// sink1.end();
     /// sink2.end();
     ///
-    /// assert_eq!(coll.wait(), vec![0, 10, 1, 11]);
+    /// assert_eq!(coll.wait(), vec![(1, "a"), (2, "a")]);
     /// ```
-    pub fn merge(streams: Vec<Stream<T>>) -> Stream<T> {
-        let inner = SafeInner::new(MemoryMode::NoMemory, None);
-        let inner_clone = inner.clone();
-        let active = Arc::new(AtomicUsize::new(streams.len()));
-        let pegs: Vec<_> = streams
-            .into_iter()
-            .map(|stream| {
-                let inner_clone = inner_clone.clone();
-                let active = active.clone();
-                stream.internal_subscribe(move |t| {
-                    if t.is_some() {
-                        inner_clone.lock().update_borrowed(t);
-                    } else if active.fetch_sub(1, Ordering::SeqCst) == 1 {
-                        // all streams are ended. close the merged one
-                        inner_clone.lock().update_borrowed(None);
-                    }
-                })
-            })
-            .collect();
-        let peg = Peg::many(pegs);
-        Stream { peg, inner }
+    pub fn combine_and_dedupe<U>(&self, other: &Stream<U>) -> Stream<(T, U)>
+    where
+        T: Clone + PartialEq,
+        U: Clone + PartialEq,
+    {
+        Stream::combine2(self, other).dedupe()
     }
 
-    /// Make a stream in memory mode. Each value is remembered for future subscribers.
+    /// Emits the number of events seen when the stream ends.
+    ///
+    /// A stream ending without any events emits `0`.
     ///
     /// ```
     /// let sink = xi::Stream::sink();
     ///
-    /// let rem = sink.stream().remember();
-    ///
-    /// sink.update(0);
-    /// sink.update(1);
-    ///
-    /// // receives last "remembered" value
-    /// let coll = rem.collect();
+    /// let coll = sink.stream().count().collect();
     ///
-    /// sink.update(2);
+    /// sink.update("a");
+    /// sink.update("b");
     /// sink.end();
     ///
-    /// assert_eq!(coll.wait(), vec![1, 2]);
+    /// assert_eq!(coll.wait(), vec![2]);
     /// ```
-    pub fn remember(&self) -> Stream<T>
-    where
-        T: Clone,
-    {
-        self.remember_mode(MemoryMode::KeepUntilEnd)
-    }
-
-    /// Internal remember where we can chose "mode"
-    fn remember_mode(&self, mode: MemoryMode) -> Stream<T>
-    where
-        T: Clone,
-    {
-        let inner = SafeInner::new(mode, None);
+    pub fn count(&self) -> Stream<usize> {
+        let inner = SafeInner::new(MemoryMode::NoMemory, None);
         let inner_clone = inner.clone();
+        let mut count = 0;
         let peg = self.internal_subscribe(move |t| {
-            let t = t.cloned();
-            inner_clone.lock().update_owned(t);
+            if t.is_some() {
+                count += 1;
+            } else {
+                let mut lock = inner_clone.lock();
+                lock.update_owned(Some(count));
+                lock.update_owned(None);
+            }
         });
         Stream { peg, inner }
     }
 
-    /// On every event in this stream, combine with the last value of the other stream.
+    /// Count down from a number for each event, then end the stream.
     ///
-    /// ```
-    /// use xi::Stream;
+    /// Emits `from, from - 1, ..., 1` for the incoming events and ends after emitting `1`,
+    /// regardless of whether the source has more events. With `from` 0 the resulting stream
+    /// ends on the first event.
     ///
-    /// let sink1 = Stream::sink();
-    /// let sink2 = Stream::sink();
+    /// ```
+    /// let sink = xi::Stream::sink();
     ///
-    /// let comb = sink1.stream().sample_combine(&sink2.stream());
+    /// let counted = sink.stream().count_down(3);
     ///
-    /// let coll = comb.collect();
+    /// let coll = counted.collect();
     ///
-    /// sink1.update(0);     // lost, because no value in sink2
-    /// sink2.update("foo"); // doesn't trigger combine
-    /// sink1.update(1);
-    /// sink1.update(2);
-    /// sink2.update("bar");
-    /// sink2.end();         // sink2 is "bar" forever
-    /// sink1.update(3);
-    /// sink1.end();
+    /// sink.update("a");
+    /// sink.update("b");
+    /// sink.update("c"); // counted ends here
+    /// sink.update("d");
     ///
-    /// assert_eq!(coll.wait(),
-    ///   vec![(1, "foo"), (2, "foo"), (3, "bar")])
+    /// assert_eq!(coll.wait(), vec![3, 2, 1]);
     /// ```
-    pub fn sample_combine<U>(&self, other: &Stream<U>) -> Stream<(T, U)>
-    where
-        T: Clone,
-        U: Clone,
-    {
+    pub fn count_down(&self, from: usize) -> Stream<usize> {
         let inner = SafeInner::new(MemoryMode::NoMemory, None);
         let inner_clone = inner.clone();
-        let rem = other.remember_mode(MemoryMode::KeepAfterEnd);
+        let mut left = from;
         let peg = self.internal_subscribe(move |t| {
-            if let Some(t) = t {
-                let rlock = rem.inner.lock();
-                if let Some(u) = rlock.peek_memory().as_ref() {
-                    // we have both t and u
-                    let v = (t.clone(), u.clone());
-                    inner_clone.lock().update_owned(Some(v));
+            let mut lock = inner_clone.lock();
+            if t.is_some() && left > 0 {
+                lock.update_owned(Some(left));
+                left -= 1;
+                if left == 0 {
+                    lock.update_owned(None);
                 }
             } else {
-                inner_clone.lock().update_borrowed(None);
+                lock.update_owned(None);
             }
         });
         Stream { peg, inner }
     }
 
-    /// Prepend a start value to the stream. The result is a memory stream.
+    /// Dedupe stream by the event itself.
+    ///
+    /// This clones every event to compare with the next.
     ///
     /// ```
     /// let sink = xi::Stream::sink();
     ///
-    /// sink.update(0); // lost
-    ///
-    /// let started = sink.stream().start_with(1);
+    /// let deduped = sink.stream().dedupe();
     ///
-    /// let coll = started.collect(); // receives 1 and following
+    /// let coll = deduped.collect();
     ///
-    /// sink.update(2);
+    /// sink.update(0);
+    /// sink.update(0);
+    /// sink.update(1);
+    /// sink.update(1);
     /// sink.end();
     ///
-    /// assert_eq!(coll.wait(), vec![1, 2]);
+    /// assert_eq!(coll.wait(), vec![0, 1]);
     /// ```
-    pub fn start_with(&self, start: T) -> Stream<T> {
-        let inner = SafeInner::new(MemoryMode::KeepUntilEnd, Some(start));
-        let inner_clone = inner.clone();
-        let peg = self.internal_subscribe(move |t| {
-            inner_clone.lock().update_borrowed(t);
-        });
-        Stream { peg, inner }
+    pub fn dedupe(&self) -> Stream<T>
+    where
+        T: Clone + PartialEq,
+    {
+        self.dedupe_by(|v| v.clone())
     }
 
-    /// Take a number of events, then end the stream.
+    /// Dedupe stream by some extracted value.
     ///
     /// ```
-    /// let sink = xi::Stream::sink();
+    /// use xi::{Stream, Sink};
     ///
-    /// let take2 = sink.stream().take(2);
+    /// #[derive(Clone, Debug)]
+    /// struct Foo(&'static str, usize);
     ///
-    /// let coll = take2.collect();
+    /// let sink: Sink<Foo> = Stream::sink();
     ///
-    /// sink.update(0);
-    /// sink.update(1); // take2 ends here
-    /// sink.update(2);
+    /// // dedupe this stream of Foo on the contained usize
+    /// let deduped = sink.stream().dedupe_by(|v| v.1);
     ///
-    /// assert_eq!(coll.wait(), vec![0, 1]);
+    /// let coll = deduped.collect();
+    ///
+    /// sink.update(Foo("yo", 1));
+    /// sink.update(Foo("bro", 1));
+    /// sink.update(Foo("lo", 2));
+    /// sink.update(Foo("lo", 2));
+    /// sink.end();
+    ///
+    /// assert_eq!(format!("{:?}", coll.wait()),
+    ///     "[Foo(\"yo\", 1), Foo(\"lo\", 2)]");
     /// ```
-    pub fn take(&self, amount: usize) -> Stream<T> {
-        let mut todo = amount + 1;
-        self.take_while(move |_| {
-            if todo > 0 {
-                todo -= 1;
+    pub fn dedupe_by<U, F>(&self, mut f: F) -> Stream<T>
+    where
+        U: PartialEq + 'static,
+        F: FnMut(&T) -> U + Send + 'static,
+    {
+        let inner = SafeInner::new(MemoryMode::NoMemory, None);
+        let inner_clone = inner.clone();
+        let mut prev: Option<U> = None;
+        let peg = self.internal_subscribe(move |t| {
+            if let Some(t) = t {
+                let propagate = match (prev.take(), f(t)) {
+                    (None, u) => {
+                        // no previous value, save this and propagate
+                        prev = Some(u);
+                        true
+                    }
+                    (Some(pu), u) => {
+                        if pu != u {
+                            // new value is different to previous, save and propagate
+                            prev = Some(u);
+                            true
+                        } else {
+                            // new value is same as before, don't propagate
+                            prev = Some(pu);
+                            false
+                        }
+                    }
+                };
+                if propagate {
+                    inner_clone.lock().update_borrowed(Some(t));
+                }
+            } else {
+                inner_clone.lock().update_borrowed(t);
             }
-            todo > 0
-        })
+        });
+        Stream { peg, inner }
     }
 
-    /// Take events from the stream as long as a condition holds true.
+    /// Dedupe stream with a comparison function.
+    ///
+    /// Each event is compared with the last _emitted_ event, and suppressed if the function
+    /// returns true, meaning "same". Comparing with the last emitted rather than the last
+    /// seen event means a slow drift eventually produces an emission.
     ///
     /// ```
     /// let sink = xi::Stream::sink();
     ///
-    /// // take events as long as they are even
-    /// let take = sink.stream().take_while(|v| *v % 2 == 0);
+    /// // same if within 2 of each other
+    /// let deduped = sink.stream().dedupe_with(|a: &i32, b| (a - b).abs() <= 2);
     ///
-    /// let coll = take.collect();
+    /// let coll = deduped.collect();
     ///
     /// sink.update(0);
+    /// sink.update(1);
     /// sink.update(2);
-    /// sink.update(3); // take ends here
-    /// sink.update(4);
+    /// sink.update(3); // 3 away from the last emitted 0
+    /// sink.update(0);
+    /// sink.end();
     ///
-    /// assert_eq!(coll.wait(), vec![0, 2]);
+    /// assert_eq!(coll.wait(), vec![0, 3, 0]);
     /// ```
-    pub fn take_while<F>(&self, mut f: F) -> Stream<T>
+    pub fn dedupe_with<F>(&self, mut f: F) -> Stream<T>
     where
-        F: FnMut(&T) -> bool + 'static,
+        T: Clone,
+        F: FnMut(&T, &T) -> bool + Send + 'static,
     {
         let inner = SafeInner::new(MemoryMode::NoMemory, None);
         let inner_clone = inner.clone();
+        let mut prev: Option<T> = None;
         let peg = self.internal_subscribe(move |t| {
             if let Some(t) = t {
-                if f(t) {
+                let same = match prev.as_ref() {
+                    Some(p) => f(p, t),
+                    None => false,
+                };
+                if !same {
+                    prev = Some(t.clone());
                     inner_clone.lock().update_borrowed(Some(t));
-                } else {
-                    inner_clone.lock().update_borrowed(None);
                 }
             } else {
+                prev.take();
                 inner_clone.lock().update_borrowed(t);
             }
         });
         Stream { peg, inner }
     }
 
-    /// Stalls calling thread until the stream ends.
+    /// Only emit events that haven't been seen before.
+    ///
+    /// Unlike [`dedupe()`](struct.Stream.html#method.dedupe), which only suppresses
+    /// consecutive duplicates, this remembers every event seen. That memory grows without
+    /// bound until the stream ends.
     ///
     /// ```
     /// let sink = xi::Stream::sink();
-    /// let stream = sink.stream();
     ///
-    /// std::thread::spawn(move || {
-    ///   sink.update(0);
-    ///   sink.update(1);
-    ///   sink.update(2);
-    ///   sink.end(); // this releases the wait
-    /// });
+    /// let distinct = sink.stream().distinct();
     ///
-    /// stream.wait(); // wait for other thread
+    /// let coll = distinct.collect();
+    ///
+    /// sink.update("a");
+    /// sink.update("b");
+    /// sink.update("a");
+    /// sink.update("c");
+    /// sink.update("b");
+    /// sink.end();
+    ///
+    /// assert_eq!(coll.wait(), vec!["a", "b", "c"]);
     /// ```
-    #[allow(clippy::mutex_atomic)]
-    pub fn wait(&self) {
-        let pair = Arc::new((Mutex::new(false), Condvar::new()));
-        let pair2 = pair.clone();
-        let _sub = self.internal_subscribe(move |t| {
-            if t.is_none() {
-                let mut lock = pair2.0.lock().unwrap();
-                *lock = true;
-                pair2.1.notify_all();
-            }
-        });
-        let mut lock = pair.0.lock().unwrap();
-        while !*lock {
-            lock = pair.1.wait(lock).unwrap();
-        }
+    pub fn distinct(&self) -> Stream<T>
+    where
+        T: Clone + Eq + Hash,
+    {
+        self.distinct_by(|t| t.clone())
     }
-}
-
-impl<T> Stream<Stream<T>> {
-    //
 
-    /// Flatten out a stream of streams, sequentially.
+    /// Only emit events with a key that hasn't been seen before.
     ///
-    /// For each new stream, unsubscribe from the previous, and subscribe to the new. The new
-    /// stream "interrupts" the previous stream.
+    /// Every key seen is remembered, which grows without bound until the stream ends.
     ///
     /// ```
-    /// use xi::{Stream, Sink};
-    ///
-    /// let sink1: Sink<Stream<u32>> = Stream::sink();
-    /// let sink2: Sink<u32> = Stream::sink();
-    /// let sink3: Sink<u32> = Stream::sink();
-    ///
-    /// let flat = sink1.stream().flatten();
-    ///
-    /// let coll = flat.collect();
-    ///
-    /// sink2.update(0); // lost
-    ///
-    /// sink1.update(sink2.stream());
-    /// sink2.update(1);
-    /// sink2.update(2);
-    /// sink2.end(); // does not end sink1
+    /// let sink = xi::Stream::sink();
     ///
-    /// sink3.update(10); // lost
+    /// // first event per id
+    /// let distinct = sink.stream().distinct_by(|(id, _)| *id);
     ///
-    /// sink1.update(sink3.stream());
-    /// sink3.update(11);
+    /// let coll = distinct.collect();
     ///
-    /// sink1.end();
+    /// sink.update((1, "a"));
+    /// sink.update((2, "b"));
+    /// sink.update((1, "c"));
+    /// sink.end();
     ///
-    /// assert_eq!(coll.wait(), vec![1, 2, 11]);
+    /// assert_eq!(coll.wait(), vec![(1, "a"), (2, "b")]);
     /// ```
-    pub fn flatten(&self) -> Stream<T> {
+    pub fn distinct_by<K, F>(&self, mut f: F) -> Stream<T>
+    where
+        K: Eq + Hash + 'static,
+        F: FnMut(&T) -> K + Send + 'static,
+    {
         let inner = SafeInner::new(MemoryMode::NoMemory, None);
         let inner_clone = inner.clone();
-        let mut ipeg = None;
-        let peg = self.internal_subscribe(move |ts| {
-            if let Some(ts) = ts {
-                let inner_clone = inner_clone.clone();
-                ipeg = Some(ts.internal_subscribe(move |tv| {
-                    if let Some(tv) = tv {
-                        inner_clone.lock().update_borrowed(Some(tv));
-                    } else {
-                        // inner stream end does nothing to outer
-                    }
-                }));
+        let mut seen = HashSet::new();
+        let peg = self.internal_subscribe(move |t| {
+            if let Some(t) = t {
+                if seen.insert(f(t)) {
+                    inner_clone.lock().update_borrowed(Some(t));
+                }
             } else {
-                ipeg.take();
-                inner_clone.lock().update_borrowed(None);
+                seen = HashSet::new();
+                inner_clone.lock().update_borrowed(t);
             }
         });
         Stream { peg, inner }
     }
 
-    /// Flatten out a stream of streams, concurrently.
-    ///
-    /// For each new stream, keep the previous, and subscribe to the new.
+    /// Drop an amount of initial values.
     ///
     /// ```
-    /// use xi::{Stream, Sink};
+    /// let sink = xi::Stream::sink();
     ///
-    /// let sink1: Sink<Stream<u32>> = Stream::sink();
-    /// let sink2: Sink<u32> = Stream::sink();
-    /// let sink3: Sink<u32> = Stream::sink();
+    /// // drop 2 initial values
+    /// let dropped = sink.stream().drop(2);
     ///
-    /// let flat = sink1.stream().flatten_concurrently();
+    /// let coll = dropped.collect();
     ///
-    /// let coll = flat.collect();
+    /// sink.update(0);
+    /// sink.update(1);
+    /// sink.update(2);
+    /// sink.update(3);
+    /// sink.end();
     ///
-    /// sink2.update(0); // lost
+    /// assert_eq!(coll.wait(), vec![2, 3]);
+    /// ```
+    pub fn drop(&self, amount: usize) -> Stream<T> {
+        let mut todo = amount + 1;
+        self.drop_while(move |_| {
+            todo = todo.saturating_sub(1);
+            todo > 0
+        })
+    }
+
+    /// Drop the final `n` events.
     ///
-    /// sink1.update(sink2.stream());
-    /// sink2.update(1);
-    /// sink2.update(2);
+    /// Each event is held back until `n` more have arrived, so the last `n` never come out.
+    /// The held back events are let go of when the stream ends.
     ///
-    /// sink3.update(10); // lost
+    /// ```
+    /// let sink = xi::Stream::sink();
     ///
-    /// sink1.update(sink3.stream());
-    /// sink3.update(11);
-    /// sink2.update(3);
-    /// sink3.update(12);
+    /// // trim a trailing checksum and terminator
+    /// let trimmed = sink.stream().drop_last(2);
     ///
-    /// sink1.end();
+    /// let coll = trimmed.collect();
     ///
-    /// assert_eq!(coll.wait(), vec![1, 2, 11, 3, 12]);
+    /// sink.update("a");
+    /// sink.update("b");
+    /// sink.update("checksum");
+    /// sink.update("terminator");
+    /// sink.end();
+    ///
+    /// assert_eq!(coll.wait(), vec!["a", "b"]);
     /// ```
-    pub fn flatten_concurrently(&self) -> Stream<T> {
+    pub fn drop_last(&self, n: usize) -> Stream<T>
+    where
+        T: Clone,
+    {
         let inner = SafeInner::new(MemoryMode::NoMemory, None);
         let inner_clone = inner.clone();
-        let peg = self.internal_subscribe(move |ts| {
-            if let Some(ts) = ts {
-                let inner_clone = inner_clone.clone();
-                let ipeg = ts.internal_subscribe(move |tv| {
-                    if let Some(tv) = tv {
-                        inner_clone.lock().update_borrowed(Some(tv));
-                    } else {
-                        // inner stream end does nothing to outer
-                    }
-                });
-                ipeg.keep_mode(); // we drop ipeg, but keep listening
+        let mut held = VecDeque::new();
+        let peg = self.internal_subscribe(move |t| {
+            if let Some(t) = t {
+                if n == 0 {
+                    inner_clone.lock().update_borrowed(Some(t));
+                    return;
+                }
+                held.push_back(t.clone());
+                if held.len() > n {
+                    let oldest = held.pop_front();
+                    inner_clone.lock().update_owned(oldest);
+                }
             } else {
-                inner_clone.lock().update_borrowed(None);
+                held.clear();
+                inner_clone.lock().update_owned(None);
             }
         });
         Stream { peg, inner }
     }
-}
-
-include!("./comb.rs");
-
-/// A sink is a producer of events. Created by [`Stream::sink()`](struct.Stream.html#method.sink).
-pub struct Sink<T: 'static> {
-    inner: SafeInner<T>,
-}
-impl<T> Sink<T> {
-    /// Create a new sink that in turn is used to stream events.
-    fn new() -> Self {
-        Sink {
-            inner: SafeInner::new(MemoryMode::NoMemory, None),
-        }
-    }
 
-    /// Get a stream of events from this sink. One stream instance is created for each call,
-    /// and they all receive the events from the sink.
+    /// Don't take values while some condition holds true. Once the condition is false,
+    /// the resulting stream emits all events.
     ///
     /// ```
     /// let sink = xi::Stream::sink();
     ///
-    /// let stream1 = sink.stream();
-    /// let stream2 = sink.stream();
+    /// // drop initial odd values
+    /// let dropped = sink.stream().drop_while(|v| v % 2 == 1);
     ///
-    /// let coll1 = stream1.collect();
-    /// let coll2 = stream1.collect();
+    /// let coll = dropped.collect();
     ///
-    /// sink.update(42);
+    /// sink.update(1);
+    /// sink.update(3);
+    /// sink.update(4);
+    /// sink.update(5); // not dropped
     /// sink.end();
     ///
-    /// assert_eq!(coll1.wait(), vec![42]);
-    /// assert_eq!(coll2.wait(), vec![42]);
+    /// assert_eq!(coll.wait(), vec![4, 5]);
     /// ```
-    pub fn stream(&self) -> Stream<T> {
-        Stream {
-            peg: Peg::new_fake(),
-            inner: self.inner.clone(),
-        }
+    pub fn drop_while<F>(&self, mut f: F) -> Stream<T>
+    where
+        F: FnMut(&T) -> bool + Send + 'static,
+    {
+        let inner = SafeInner::new(MemoryMode::NoMemory, None);
+        let inner_clone = inner.clone();
+        let mut dropping = true;
+        let peg = self.internal_subscribe(move |t| {
+            if let Some(t) = t {
+                if dropping && !f(t) {
+                    dropping = false;
+                }
+                if dropping {
+                    return;
+                }
+                inner_clone.lock().update_borrowed(Some(t));
+            } else {
+                inner_clone.lock().update_borrowed(t);
+            }
+        });
+        Stream { peg, inner }
     }
 
-    /// Update a value into this sink.
-    ///
-    /// The execution of the combinators "hanging" off this sink is (thread safe) and
-    /// synchronous. In other words, there is nothing in xi itself that will still be
-    /// "to do" once the `update()` call returns.
+    /// Emit the event at a zero based position, and then end.
     ///
-    /// Each value is wrapped in an `Option` towards subscribers of the streams.
+    /// The events before are dropped, and the subscription to this stream is dropped once
+    /// the event has propagated. If this stream ends before the position, the resulting
+    /// stream ends without any event.
     ///
     /// ```
     /// let sink = xi::Stream::sink();
-    /// let stream = sink.stream();
     ///
-    /// stream.subscribe(|v| {
-    ///     // v is Some(0), Some(1), None
-    /// });
+    /// // the third field of a record
+    /// let third = sink.stream().element_at(2);
     ///
-    /// sink.update(0);
-    /// sink.update(1);
-    /// sink.end();
+    /// let coll = third.collect();
+    ///
+    /// sink.update("id");
+    /// sink.update("name");
+    /// sink.update("email");
+    ///
+    /// assert!(third.is_ended());
+    /// assert_eq!(coll.wait(), vec!["email"]);
     /// ```
-    pub fn update(&self, next: T) {
-        self.inner.lock().update_and_imitate(Some(next));
+    pub fn element_at(&self, index: usize) -> Stream<T> {
+        let inner = SafeInner::new(MemoryMode::NoMemory, None);
+        // the subscription is held by the peg of the resulting stream.
+        let (pegged, peg) = Pegged::new_pair(vec![]);
+        let pegged = Arc::new(pegged);
+        let inner_clone = inner.clone();
+        let pegged_clone = pegged.clone();
+        let mut skip = index;
+        let sub = self.internal_subscribe(move |t| {
+            if t.is_some() && skip > 0 {
+                skip -= 1;
+                return;
+            }
+            let mut lock = inner_clone.lock();
+            if lock.is_ended() {
+                return;
+            }
+            if t.is_some() {
+                lock.update_borrowed(t);
+            }
+            lock.update_borrowed(None);
+            drop(lock);
+            let pegged = pegged_clone.clone();
+            // can't drop the subscription while in its own listener.
+            defer(move || {
+                let subs = pegged.with_value(|subs| subs.map(std::mem::take));
+                drop(subs);
+            });
+        });
+        if inner.lock().is_ended() {
+            // ended while subscribing, on a remembered value or an ended stream.
+            drop(sub);
+        } else {
+            let mut sub = Some(sub);
+            pegged.with_value(|subs| {
+                if let Some(subs) = subs {
+                    subs.extend(sub.take());
+                }
+            });
+        }
+        Stream { peg, inner }
     }
 
-    /// End the stream of events. Consumes the instance since no more values are to go into it.
+    /// On every event in the trigger stream, emit the last value of this stream.
     ///
-    /// Subscribers will se a `None` value.
+    /// Nothing is emitted until this stream has a value. The resulting stream ends when
+    /// the trigger ends. Unlike [`sample_combine()`](struct.Stream.html#method.sample_combine)
+    /// the trigger is the other stream, and its events are not part of the output.
     ///
-    /// Every stream hanging directly off this sink will also end. The exception is streams
-    /// combining input from multiple source streams.
-    pub fn end(self) {
-        self.inner.lock().update_and_imitate(None);
-    }
-}
-
-/// The collector instance collects values from a stream. Created by
-/// [`Stream::collect()`](struct.Stream.html#method.collect).
-pub struct Collector<T> {
-    #[allow(dead_code)]
-    peg: Peg,
-    #[allow(clippy::type_complexity)]
-    state: Arc<(Mutex<(bool, Option<Vec<T>>)>, Condvar)>,
-}
-
-impl<T> Collector<T> {
-    /// Stall the thread and wait for the stream to end.
-    pub fn wait(self) -> Vec<T> {
-        let mut lock = self.state.0.lock().unwrap();
-        while !lock.0 {
-            lock = self.state.1.wait(lock).unwrap();
-        }
-        lock.1.take().unwrap()
+    /// ```
+    /// use xi::Stream;
+    ///
+    /// let values = Stream::sink();
+    /// let clock = Stream::sink();
+    ///
+    /// let sampled = values.stream().emit_latest_on(&clock.stream());
+    ///
+    /// let coll = sampled.collect();
+    ///
+    /// clock.update(());    // lost, because no value yet
+    /// values.update("foo"); // doesn't emit
+    /// clock.update(());
+    /// values.update("bar");
+    /// values.end();         // latest is "bar" forever
+    /// clock.update(());
+    /// clock.update(());
+    /// clock.end();
+    ///
+    /// assert_eq!(coll.wait(), vec!["foo", "bar", "bar"]);
+    /// ```
+    pub fn emit_latest_on<U>(&self, trigger: &Stream<U>) -> Stream<T>
+    where
+        T: Clone,
+    {
+        let inner = SafeInner::new(MemoryMode::NoMemory, None);
+        let inner_clone = inner.clone();
+        let rem = self.remember_mode(MemoryMode::KeepAfterEnd);
+        let peg = trigger.internal_subscribe(move |u| {
+            if u.is_some() {
+                let t = rem.inner.lock().peek_memory().clone();
+                if t.is_some() {
+                    inner_clone.lock().update_owned(t);
+                }
+            } else {
+                inner_clone.lock().update_owned(None);
+            }
+        });
+        Stream { peg, inner }
     }
 
-    /// Take whatever is there, without the stream ending, and stop collecting.
+    /// Produce a stream that ends when some other stream ends.
+    ///
+    /// Events of the other stream are ignored, only its end matters. Also available as
+    /// [`take_until_end_of()`](struct.Stream.html#method.take_until_end_of). To end on the
+    /// first event of the other stream, see
+    /// [`take_until()`](struct.Stream.html#method.take_until).
+    ///
+    /// ```
+    /// use xi::Stream;
+    ///
+    /// let sink1 = Stream::sink();
+    /// let sink2 = Stream::sink();
+    ///
+    /// // ending shows values of sink1, but ends when sink2 does.
+    /// let ending = sink1.stream().end_when(&sink2.stream());
+    ///
+    /// let coll = ending.collect();
+    ///
+    /// sink1.update(0);
+    /// sink2.update("yo");
+    /// sink1.update(1);
+    /// sink2.end();
+    /// sink1.update(2); // collector never sees this value
+    ///
+    /// assert_eq!(coll.wait(), [0, 1]);
+    /// ```
+    pub fn end_when<U>(&self, other: &Stream<U>) -> Stream<T> {
+        let inner = SafeInner::new(MemoryMode::NoMemory, None);
+        let inner_clone1 = inner.clone();
+        let inner_clone2 = inner.clone();
+        let peg1 = other.internal_subscribe(move |o| {
+            if o.is_none() {
+                inner_clone1.lock().update_borrowed(None);
+            }
+        });
+        let peg2 = self.internal_subscribe(move |t| {
+            inner_clone2.lock().update_borrowed(t);
+        });
+        let peg = Peg::many(vec![peg1, peg2]);
+        Stream { peg, inner }
+    }
+
+    /// Pair each event with its zero based index, like `Iterator::enumerate`.
+    ///
+    /// The count starts when `enumerate()` is called, and is not shared with other streams
+    /// derived from the same source. The end event doesn't count.
+    ///
+    /// The count belongs to the resulting stream, not to its subscribers: like every other
+    /// operator, the stream hands the same event to all of them. A subscriber arriving after
+    /// `n` events starts at index `n`. For a count from 0 per subscriber, call `enumerate()`
+    /// once per subscriber.
+    ///
+    /// ```
+    /// let sink = xi::Stream::sink();
+    ///
+    /// // every other event
+    /// let every_other = sink.stream().enumerate().filter(|(i, _)| i % 2 == 0);
+    ///
+    /// let coll = every_other.collect();
+    ///
+    /// sink.update("a");
+    /// sink.update("b");
+    /// sink.update("c");
+    /// sink.end();
+    ///
+    /// assert_eq!(coll.wait(), vec![(0, "a"), (2, "c")]);
+    /// ```
+    pub fn enumerate(&self) -> Stream<(usize, T)>
+    where
+        T: Clone,
+    {
+        self.enumerate_from(0)
+    }
+
+    /// Pair each event with its index, counting from `start`.
+    ///
+    /// Like [`enumerate()`](struct.Stream.html#method.enumerate), for indexes that aren't
+    /// zero based, such as line numbers.
+    ///
+    /// ```
+    /// let sink = xi::Stream::sink();
+    ///
+    /// let numbered = sink.stream().enumerate_from(1);
+    ///
+    /// let coll = numbered.collect();
+    ///
+    /// sink.update("first line");
+    /// sink.update("second line");
+    /// sink.end();
+    ///
+    /// assert_eq!(coll.wait(), vec![(1, "first line"), (2, "second line")]);
+    /// ```
+    pub fn enumerate_from(&self, start: usize) -> Stream<(usize, T)>
+    where
+        T: Clone,
+    {
+        self.zip_with_index_and_value(move |i, t| (start + i, t.clone()))
+    }
+
+    /// Filter out a subset of the events in the stream.
+    ///
+    /// ```
+    /// let sink = xi::Stream::sink();
+    ///
+    /// // keep even numbers
+    /// let filtered = sink.stream().filter(|v| v % 2 == 0);
+    ///
+    /// let coll = filtered.collect();
+    ///
+    /// sink.update(0);
+    /// sink.update(1);
+    /// sink.update(2);
+    /// sink.end();
+    ///
+    /// assert_eq!(coll.wait(), vec![0, 2]);
+    /// ```
+    pub fn filter<F>(&self, mut f: F) -> Stream<T>
+    where
+        F: FnMut(&T) -> bool + Send + 'static,
+    {
+        let inner = SafeInner::new(MemoryMode::NoMemory, None);
+        let inner_clone = inner.clone();
+        let peg = self.internal_subscribe(move |t| {
+            if let Some(t) = t {
+                if f(t) {
+                    inner_clone.lock().update_borrowed(Some(t));
+                }
+            } else {
+                inner_clone.lock().update_borrowed(t);
+            }
+        });
+        Stream { peg, inner }
+    }
+
+    /// Map and filter the events in one go, keeping the values for which the function
+    /// returns `Some`.
+    ///
+    /// This is one operation in the tree, where `map()` into an `Option` followed by
+    /// `filter()` and unwrapping would be three. The end event always passes through.
+    ///
+    /// ```
+    /// let sink = xi::Stream::sink();
+    ///
+    /// // keep events that parse as numbers
+    /// let parsed = sink.stream().filter_map(|v: &&str| v.parse::<u32>().ok());
+    ///
+    /// let coll = parsed.collect();
+    ///
+    /// sink.update("1");
+    /// sink.update("foo");
+    /// sink.update("42");
+    /// sink.end();
+    ///
+    /// assert_eq!(coll.wait(), vec![1, 42]);
+    /// ```
+    pub fn filter_map<U, F>(&self, mut f: F) -> Stream<U>
+    where
+        F: FnMut(&T) -> Option<U> + Send + 'static,
+    {
+        let inner = SafeInner::new(MemoryMode::NoMemory, None);
+        let inner_clone = inner.clone();
+        let peg = self.internal_subscribe(move |t| {
+            if let Some(t) = t {
+                if let Some(u) = f(t) {
+                    inner_clone.lock().update_owned(Some(u));
+                }
+            } else {
+                inner_clone.lock().update_owned(None);
+            }
+        });
+        Stream { peg, inner }
+    }
+
+    /// Filter with a predicate that has mutable access to a state.
+    ///
+    /// The state is owned by the stream, which saves wrapping a counter or similar in a
+    /// `Mutex` to capture it in the closure to [`filter()`](struct.Stream.html#method.filter).
+    ///
+    /// ```
+    /// let sink = xi::Stream::sink();
+    ///
+    /// // every third event
+    /// let filtered = sink.stream().filter_with_state(0, |n, _| {
+    ///     *n += 1;
+    ///     *n % 3 == 0
+    /// });
+    ///
+    /// let coll = filtered.collect();
+    ///
+    /// for v in 0..7 {
+    ///     sink.update(v);
+    /// }
+    /// sink.end();
+    ///
+    /// assert_eq!(coll.wait(), vec![2, 5]);
+    /// ```
+    pub fn filter_with_state<S, F>(&self, mut state: S, mut f: F) -> Stream<T>
+    where
+        S: Send + 'static,
+        F: FnMut(&mut S, &T) -> bool + Send + 'static,
+    {
+        self.filter(move |t| f(&mut state, t))
+    }
+
+    /// Emit the first event, and then end.
+    ///
+    /// Like [`take(1)`](struct.Stream.html#method.take), but the resulting stream ends
+    /// straight after the first event rather than on the next, and the subscription to this
+    /// stream is dropped once the update has propagated. If this stream ends without any
+    /// event, so does the resulting stream. The same as
+    /// [`element_at(0)`](struct.Stream.html#method.element_at).
+    ///
+    /// ```
+    /// let sink = xi::Stream::sink();
+    ///
+    /// let first = sink.stream().first();
+    ///
+    /// let coll = first.collect();
+    ///
+    /// sink.update(0);
+    ///
+    /// assert!(first.is_ended());
+    /// assert_eq!(coll.wait(), vec![0]);
+    /// ```
+    pub fn first(&self) -> Stream<T> {
+        self.element_at(0)
+    }
+
+    /// Stall the calling thread until the first event, and return it. Returns `None` if
+    /// the stream ends without an event.
+    ///
+    /// The stream is only listened to until the first event. A remembered value counts as
+    /// the first event.
+    ///
+    /// ```
+    /// let sink = xi::Stream::sink();
+    /// // remembered, in case the response arrives before we start waiting.
+    /// let stream = sink.stream().remember();
+    ///
+    /// std::thread::spawn(move || {
+    ///   sink.update("response");
+    /// });
+    ///
+    /// assert_eq!(stream.first_value(), Some("response"));
+    /// ```
+    #[allow(clippy::type_complexity)]
+    pub fn first_value(&self) -> Option<T>
+    where
+        T: Clone,
+    {
+        let pair: Arc<(Mutex<Option<Option<T>>>, Condvar)> =
+            Arc::new((Mutex::new(None), Condvar::new()));
+        let pair2 = pair.clone();
+        let _sub = self.internal_subscribe(move |t| {
+            let mut lock = pair2.0.lock().unwrap();
+            if lock.is_none() {
+                *lock = Some(t.cloned());
+                pair2.1.notify_all();
+            }
+        });
+        let mut lock = pair.0.lock().unwrap();
+        loop {
+            if let Some(first) = lock.take() {
+                return first;
+            }
+            lock = pair.1.wait(lock).unwrap();
+        }
+    }
+
+    /// Separate the first event from the rest.
+    ///
+    /// The first stream emits the first event and then ends. The second stream emits all
+    /// events after the first, and ends when the source ends. This is the same as
+    /// [`split_at(1)`](struct.Stream.html#method.split_at) and
+    /// [`split_first()`](struct.Stream.html#method.split_first).
+    ///
+    /// ```
+    /// let sink = xi::Stream::sink();
+    ///
+    /// let (first, rest) = sink.stream().first_and_rest();
+    ///
+    /// let coll1 = first.collect();
+    /// let coll2 = rest.collect();
+    ///
+    /// sink.update("header");
+    /// sink.update("body1");
+    /// sink.update("body2");
+    /// sink.end();
+    ///
+    /// assert_eq!(coll1.wait(), vec!["header"]);
+    /// assert_eq!(coll2.wait(), vec!["body1", "body2"]);
+    /// ```
+    pub fn first_and_rest(&self) -> (Stream<T>, Stream<T>) {
+        self.split_at(1)
+    }
+
+    /// Map each event to a stream, and flatten the streams out sequentially.
+    ///
+    /// The same as `map(f).flatten()`, without the stream of streams in between. Each new
+    /// stream "interrupts" the previous one. The end of a mapped stream does not end the
+    /// resulting stream, only the end of this stream does. See
+    /// [`flatten()`](struct.Stream.html#method.flatten).
+    ///
+    /// ```
+    /// use xi::{Stream, Sink};
+    ///
+    /// let sink: Sink<u32> = Stream::sink();
+    /// let tens: Sink<u32> = Stream::sink();
+    ///
+    /// let tens_stream = tens.stream();
+    /// let flat = sink.stream().flat_map(move |v| tens_stream.map_to(*v));
+    ///
+    /// let coll = flat.collect();
+    ///
+    /// sink.update(1);
+    /// tens.update(0);
+    /// tens.update(0);
+    /// sink.update(2); // interrupts the stream mapping to 1
+    /// tens.update(0);
+    /// sink.end();
+    ///
+    /// assert_eq!(coll.wait(), vec![1, 1, 2]);
+    /// ```
+    pub fn flat_map<U, F>(&self, mut f: F) -> Stream<U>
+    where
+        F: FnMut(&T) -> Stream<U> + Send + 'static,
+    {
+        let inner = SafeInner::new(MemoryMode::NoMemory, None);
+        let inner_clone = inner.clone();
+        let mut ipeg = None;
+        let peg = self.internal_subscribe(move |t| {
+            if let Some(t) = t {
+                let inner_clone = inner_clone.clone();
+                ipeg = Some(f(t).internal_subscribe(move |u| {
+                    if let Some(u) = u {
+                        inner_clone.lock().update_borrowed(Some(u));
+                    } else {
+                        // inner stream end does nothing to outer
+                    }
+                }));
+            } else {
+                ipeg.take();
+                inner_clone.lock().update_borrowed(None);
+            }
+        });
+        Stream { peg, inner }
+    }
+
+    /// Map each event to a stream, and flatten the streams out concurrently.
+    ///
+    /// The same as `map(f).flatten_concurrently()`, without the stream of streams in
+    /// between. See [`flatten_concurrently()`](struct.Stream.html#method.flatten_concurrently).
+    ///
+    /// ```
+    /// use xi::{Stream, Sink};
+    ///
+    /// let sink: Sink<u32> = Stream::sink();
+    /// let tens: Sink<u32> = Stream::sink();
+    ///
+    /// let tens_stream = tens.stream();
+    /// let flat = sink.stream().flat_map_concurrent(move |v| tens_stream.map_to(*v));
+    ///
+    /// let coll = flat.collect();
+    ///
+    /// sink.update(1);
+    /// tens.update(0);
+    /// sink.update(2); // both streams are kept
+    /// tens.update(0);
+    /// sink.end();
+    ///
+    /// assert_eq!(coll.wait(), vec![1, 1, 2]);
+    /// ```
+    pub fn flat_map_concurrent<U, F>(&self, mut f: F) -> Stream<U>
+    where
+        F: FnMut(&T) -> Stream<U> + Send + 'static,
+    {
+        let inner = SafeInner::new(MemoryMode::NoMemory, None);
+        let inner_clone = inner.clone();
+        let peg = self.internal_subscribe(move |t| {
+            if let Some(t) = t {
+                let inner_clone = inner_clone.clone();
+                let ipeg = f(t).internal_subscribe(move |u| {
+                    if let Some(u) = u {
+                        inner_clone.lock().update_borrowed(Some(u));
+                    } else {
+                        // inner stream end does nothing to outer
+                    }
+                });
+                ipeg.keep_mode(); // we drop ipeg, but keep listening
+            } else {
+                inner_clone.lock().update_borrowed(None);
+            }
+        });
+        Stream { peg, inner }
+    }
+
+    /// Combine events from the past, with new events to produce an output.
+    ///
+    /// This is roughly equivalent to a "fold" or "reduce" over an array. For each event we
+    /// emit the latest state out. The seed value is emitted straight away.
+    ///
+    /// The result is always a "memory" stream. Use [`scan()`](struct.Stream.html#method.scan)
+    /// to not emit the seed.
+    ///
+    /// ```
+    /// let sink = xi::Stream::sink();
+    ///
+    /// let folded = sink.stream()
+    ///     .fold(40.5, |prev, next| prev + (*next as f32) / 2.0);
+    ///
+    /// let coll = folded.collect();
+    ///
+    /// sink.update(0);
+    /// sink.update(1);
+    /// sink.update(2);
+    /// sink.end();
+    ///
+    /// assert_eq!(coll.wait(), vec![40.5, 40.5, 41.0, 42.0]);
+    /// ```
+    pub fn fold<U, F>(&self, seed: U, mut f: F) -> Stream<U>
+    where
+        U: 'static,
+        F: FnMut(U, &T) -> U + Send + 'static,
+    {
+        let inner = SafeInner::new(MemoryMode::KeepUntilEnd, Some(seed));
+        let inner_clone = inner.clone();
+        let peg = self.internal_subscribe(move |t| {
+            if let Some(t) = t {
+                let mut lock = inner_clone.lock();
+                if let Some(prev) = lock.take_memory() {
+                    let next = f(prev, t);
+                    lock.update_owned(Some(next));
+                } else {
+                    panic!("fold without a previous value");
+                }
+            } else {
+                inner_clone.lock().update_owned(None);
+            }
+        });
+        Stream { peg, inner }
+    }
+
+    /// Fold with the zero based index of each event.
+    ///
+    /// Like [`fold()`](struct.Stream.html#method.fold), the seed is emitted straight away and
+    /// the result is a "memory" stream.
+    ///
+    /// ```
+    /// let sink = xi::Stream::sink();
+    ///
+    /// // evaluate the polynomial with coefficients 1, 2, 3 at x = 10
+    /// let folded = sink.stream()
+    ///     .fold_indexed(0, |acc, i, c| acc + c * 10_u32.pow(i as u32));
+    ///
+    /// let coll = folded.collect();
+    ///
+    /// sink.update(1);
+    /// sink.update(2);
+    /// sink.update(3);
+    /// sink.end();
+    ///
+    /// assert_eq!(coll.wait(), vec![0, 1, 21, 321]);
+    /// ```
+    pub fn fold_indexed<U, F>(&self, seed: U, mut f: F) -> Stream<U>
+    where
+        U: 'static,
+        F: FnMut(U, usize, &T) -> U + Send + 'static,
+    {
+        let mut index = 0;
+        self.fold(seed, move |acc, t| {
+            let u = f(acc, index, t);
+            index += 1;
+            u
+        })
+    }
+
+    /// Call a function for every event of this stream and a number of other streams.
+    ///
+    /// This subscribes to each of the streams, like a
+    /// [`subscribe()`](struct.Stream.html#method.subscribe) of their
+    /// [`merge()`](struct.Stream.html#method.merge), but without building the merged
+    /// stream. The function is not called for the ends. The returned subscription
+    /// unsubscribes from all the streams.
+    ///
+    /// ```
+    /// use std::sync::{Arc, Mutex};
+    ///
+    /// let clicks = xi::Stream::sink();
+    /// let keys = xi::Stream::sink();
+    ///
+    /// let seen = Arc::new(Mutex::new(vec![]));
+    /// let seen_clone = seen.clone();
+    ///
+    /// let sub = clicks.stream().for_each_concurrent(vec![keys.stream()], move |v| {
+    ///     seen_clone.lock().unwrap().push(*v);
+    /// });
+    ///
+    /// clicks.update("click");
+    /// keys.update("key");
+    /// sub.unsubscribe();
+    /// clicks.update("lost");
+    ///
+    /// assert_eq!(*seen.lock().unwrap(), vec!["click", "key"]);
+    /// ```
+    pub fn for_each_concurrent<F>(&self, streams: Vec<Stream<T>>, f: F) -> Subscription
+    where
+        F: FnMut(&T) + Send + 'static,
+    {
+        let f = Arc::new(Mutex::new(f));
+        let pegs = std::iter::once(self)
+            .chain(streams.iter())
+            .map(|stream| {
+                let f = f.clone();
+                let sub = stream.subscribe_unchecked(move |t| {
+                    if let Some(t) = t {
+                        (f.lock().unwrap())(t);
+                    }
+                });
+                sub.into_peg()
+            })
+            .collect();
+        Subscription::new(Peg::many(pegs))
+    }
+
+    /// Hold back events until another stream emits, then release the latest of them.
+    ///
+    /// Before `go` emits its first event, events are not passed on, but the latest one is
+    /// kept. When `go` emits, the kept event (if any) is emitted and from then on events
+    /// pass straight through. The result is a "memory" stream, so a late subscriber gets
+    /// the latest event.
+    ///
+    /// Many streams can share one `go` stream to be revealed at the same time.
+    ///
+    /// ```
+    /// let sink = xi::Stream::sink();
+    /// let go = xi::Stream::sink();
+    ///
+    /// let gated = sink.stream().gate_initial(&go.stream());
+    ///
+    /// let coll = gated.collect();
+    ///
+    /// sink.update(0); // held back
+    /// sink.update(1); // replaces 0
+    /// go.update(());  // releases 1
+    /// sink.update(2);
+    /// sink.end();
+    ///
+    /// assert_eq!(coll.wait(), vec![1, 2]);
+    /// ```
+    pub fn gate_initial<U>(&self, go: &Stream<U>) -> Stream<T>
+    where
+        T: Clone,
+    {
+        let inner = SafeInner::new(MemoryMode::KeepUntilEnd, None);
+        // whether the gate is open, and the latest event while it isn't.
+        let state: Arc<Mutex<(bool, Option<T>)>> = Arc::new(Mutex::new((false, None)));
+        let peg1 = {
+            let inner_clone = inner.clone();
+            let state = state.clone();
+            self.internal_subscribe(move |t| {
+                if let Some(t) = t {
+                    let open = {
+                        let mut lock = state.lock().unwrap();
+                        if !lock.0 {
+                            lock.1 = Some(t.clone());
+                        }
+                        lock.0
+                    };
+                    if open {
+                        inner_clone.lock().update_owned(Some(t.clone()));
+                    }
+                } else {
+                    inner_clone.lock().update_owned(None);
+                }
+            })
+        };
+        let peg2 = {
+            let inner_clone = inner.clone();
+            go.internal_subscribe(move |u| {
+                if u.is_some() {
+                    let latest = {
+                        let mut lock = state.lock().unwrap();
+                        if lock.0 {
+                            return;
+                        }
+                        lock.0 = true;
+                        lock.1.take()
+                    };
+                    if latest.is_some() {
+                        inner_clone.lock().update_owned(latest);
+                    }
+                }
+            })
+        };
+        let peg = Peg::many(vec![peg1, peg2]);
+        Stream { peg, inner }
+    }
+
+    /// Internal imitate for imitator.
+    fn imitate(&self, imitator: SafeInner<T>) -> Peg
+    where
+        T: Clone,
+    {
+        self.internal_subscribe(move |t| {
+            let imitator_clone = imitator.clone();
+            if t.is_some() {
+                let t_clone = t.cloned();
+                defer(move || {
+                    // this is one clone too many. if we could use
+                    // Box<FnOnce> on stable, we would do that instead
+                    let t = t_clone.clone();
+                    imitator_clone.lock().update_owned(t.clone());
+                });
+            } else {
+                imitator_clone.lock().update_owned(None);
+            }
+        })
+    }
+
+    /// Split the stream into one child stream per key.
+    ///
+    /// The first event with a new key emits the key together with a new child stream, and
+    /// then goes to the child, as do all later events with that key. The children remember
+    /// their latest event, so a subscriber arriving late to a child still gets the event
+    /// that opened it. When this stream ends, all the children end before the grouped
+    /// stream does.
+    ///
+    /// The children are fed through the subscription of the grouped stream, and stop
+    /// receiving events once the grouped stream is dropped.
+    ///
+    /// ```
+    /// use std::sync::{Arc, Mutex};
+    ///
+    /// let sink = xi::Stream::sink();
+    ///
+    /// let grouped = sink.stream().group_by(|(conn, _): &(u32, &str)| *conn);
+    ///
+    /// let children = Arc::new(Mutex::new(vec![]));
+    /// let children_clone = children.clone();
+    /// grouped.subscribe(move |g| if let Some((conn, child)) = g {
+    ///     children_clone.lock().unwrap().push((*conn, child.collect()));
+    /// });
+    ///
+    /// sink.update((1, "hello"));
+    /// sink.update((2, "hi"));
+    /// sink.update((1, "bye"));
+    /// sink.end();
+    ///
+    /// let children: Vec<_> = children.lock().unwrap().drain(..).collect();
+    /// let lens: Vec<_> = children.into_iter().map(|(c, coll)| (c, coll.wait().len())).collect();
+    ///
+    /// assert_eq!(lens, vec![(1, 2), (2, 1)]);
+    /// ```
+    pub fn group_by<K, F>(&self, mut f: F) -> Stream<(K, Stream<T>)>
+    where
+        T: Clone,
+        K: Eq + Hash + Clone + 'static,
+        F: FnMut(&T) -> K + Send + 'static,
+    {
+        let inner = SafeInner::new(MemoryMode::NoMemory, None);
+        let inner_clone = inner.clone();
+        let mut groups: HashMap<K, SafeInner<T>> = HashMap::new();
+        let peg = self.internal_subscribe(move |t| {
+            if let Some(t) = t {
+                let k = f(t);
+                let group = if let Some(group) = groups.get(&k) {
+                    group.clone()
+                } else {
+                    let group = SafeInner::new(MemoryMode::KeepUntilEnd, None);
+                    groups.insert(k.clone(), group.clone());
+                    let child = Stream {
+                        peg: Peg::new_fake(),
+                        inner: group.clone(),
+                    };
+                    inner_clone.lock().update_owned(Some((k, child)));
+                    group
+                };
+                group.lock().update_owned(Some(t.clone()));
+            } else {
+                for (_, group) in groups.drain() {
+                    group.lock().update_owned(None);
+                }
+                inner_clone.lock().update_owned(None);
+            }
+        });
+        Stream { peg, inner }
+    }
+
+    /// Merge events from a bunch of streams in strict rotation.
+    ///
+    /// Events are emitted from the first stream, then the second, and so on, starting over
+    /// after the last. A stream that is ahead of its turn has its event held back. At most
+    /// one event is held per stream, so a newer event of a stream replaces its held one,
+    /// the same as events before a stream's turn in
+    /// [`concat()`](struct.Stream.html#method.concat) are lost rather than queued. Ended
+    /// streams are skipped, once any held event has had its turn.
+    ///
+    /// The resulting stream ends when all streams have ended, after the held events, and
+    /// an empty vec gives a stream that is ended straight away.
+    ///
+    /// ```
+    /// use xi::Stream;
+    ///
+    /// let sink1 = Stream::sink();
+    /// let sink2 = Stream::sink();
+    ///
+    /// let turns = Stream::interleave(vec![sink1.stream(), sink2.stream()]);
+    ///
+    /// let coll = turns.collect();
+    ///
+    /// sink1.update("a1");
+    /// sink1.update("a2"); // held back for sink2 to go first
+    /// sink1.update("a3"); // replaces a2
+    /// sink2.update("b1");
+    /// sink2.end();
+    /// sink1.update("a4");
+    /// sink1.end();
+    ///
+    /// assert_eq!(coll.wait(), vec!["a1", "b1", "a3", "a4"]);
+    /// ```
+    pub fn interleave(streams: Vec<Stream<T>>) -> Stream<T>
+    where
+        T: Clone,
+    {
+        let inner = SafeInner::new(MemoryMode::NoMemory, None);
+        if streams.is_empty() {
+            inner.lock().update_owned(None);
+        }
+        let rotation = Arc::new(Mutex::new(Rotation::new(streams.len())));
+        let pegs: Vec<_> = streams
+            .iter()
+            .enumerate()
+            .map(|(i, stream)| {
+                let inner_clone = inner.clone();
+                let rotation = rotation.clone();
+                stream.internal_subscribe(move |t| {
+                    let mut rotation = rotation.lock().unwrap();
+                    rotation.hold(i, t.cloned());
+                    // emit under the rotation lock, to keep the order across threads.
+                    rotation.release(&inner_clone);
+                })
+            })
+            .collect();
+        let peg = Peg::many(pegs);
+        Stream { peg, inner }
+    }
+
+    /// Join events of this and another stream by key.
+    ///
+    /// Events that haven't been matched are held per key on each side. When an event arrives
+    /// with a key that is held on the other side, the pair is emitted and the key is
+    /// cleared. If an event arrives with a key already held on its own side, the latest event
+    /// replaces the held one.
+    ///
+    /// With `max_pending`, each side holds at most that many unmatched events, evicting the
+    /// oldest. The resulting stream ends when both streams have ended, dropping anything
+    /// unmatched.
+    ///
+    /// ```
+    /// use xi::Stream;
+    ///
+    /// let orders = Stream::sink();
+    /// let payments = Stream::sink();
+    ///
+    /// let joined = orders.stream().join_by_key(
+    ///     &payments.stream(),
+    ///     |o: &(u32, &str)| o.0,
+    ///     |p: &(u32, f32)| p.0,
+    ///     None,
+    /// );
+    ///
+    /// let coll = joined.collect();
+    ///
+    /// orders.update((1, "apple"));
+    /// orders.update((2, "pear"));
+    /// payments.update((2, 1.5));
+    /// payments.update((3, 9.0));
+    /// orders.update((3, "plum"));
+    /// orders.end();
+    /// payments.end();
+    ///
+    /// assert_eq!(coll.wait(), vec![
+    ///     ((2, "pear"), (2, 1.5)),
+    ///     ((3, "plum"), (3, 9.0)),
+    /// ]);
+    /// ```
+    pub fn join_by_key<U, K, FT, FU>(
+        &self,
+        other: &Stream<U>,
+        mut key_self: FT,
+        mut key_other: FU,
+        max_pending: Option<usize>,
+    ) -> Stream<(T, U)>
+    where
+        T: Clone,
+        U: Clone,
+        K: Eq + Hash + Clone + 'static,
+        FT: FnMut(&T) -> K + Send + 'static,
+        FU: FnMut(&U) -> K + Send + 'static,
+    {
+        let inner = SafeInner::new(MemoryMode::NoMemory, None);
+        let state = Arc::new(Mutex::new((
+            Pending::new(max_pending),
+            Pending::new(max_pending),
+        )));
+        let active = Arc::new(AtomicUsize::new(2));
+        let peg1 = {
+            let inner_clone = inner.clone();
+            let state = state.clone();
+            let active = active.clone();
+            self.internal_subscribe(move |t| {
+                if let Some(t) = t {
+                    let k = key_self(t);
+                    let matched = {
+                        let mut lock = state.lock().unwrap();
+                        let matched = lock.1.remove(&k);
+                        if matched.is_none() {
+                            lock.0.insert(k, t.clone());
+                        }
+                        matched
+                    };
+                    if let Some(u) = matched {
+                        inner_clone.lock().update_owned(Some((t.clone(), u)));
+                    }
+                } else if active.fetch_sub(1, Ordering::SeqCst) == 1 {
+                    {
+                        let mut lock = state.lock().unwrap();
+                        lock.0.clear();
+                        lock.1.clear();
+                    }
+                    inner_clone.lock().update_owned(None);
+                }
+            })
+        };
+        let peg2 = {
+            let inner_clone = inner.clone();
+            other.internal_subscribe(move |u| {
+                if let Some(u) = u {
+                    let k = key_other(u);
+                    let matched = {
+                        let mut lock = state.lock().unwrap();
+                        let matched = lock.0.remove(&k);
+                        if matched.is_none() {
+                            lock.1.insert(k, u.clone());
+                        }
+                        matched
+                    };
+                    if let Some(t) = matched {
+                        inner_clone.lock().update_owned(Some((t, u.clone())));
+                    }
+                } else if active.fetch_sub(1, Ordering::SeqCst) == 1 {
+                    {
+                        let mut lock = state.lock().unwrap();
+                        lock.0.clear();
+                        lock.1.clear();
+                    }
+                    inner_clone.lock().update_owned(None);
+                }
+            })
+        };
+        let peg = Peg::many(vec![peg1, peg2]);
+        Stream { peg, inner }
+    }
+
+    /// Keep this stream subscribed to its parents until the trigger stream emits or ends,
+    /// also when nothing else holds on to it.
+    ///
+    /// Normally a stream is unsubscribed from its parents once the last instance of it is
+    /// dropped. This pins the subscription, to run a "fire and forget" pipeline. When the
+    /// trigger emits or ends, the resulting stream ends and the pin is released.
+    ///
+    /// The trigger stream itself must be kept alive as usual.
+    ///
+    /// ```
+    /// use std::sync::{Arc, Mutex};
+    /// use xi::Stream;
+    ///
+    /// let sink = Stream::sink();
+    /// let stop = Stream::sink();
+    ///
+    /// let seen = Arc::new(Mutex::new(vec![]));
+    /// let seen_clone = seen.clone();
+    ///
+    /// // nothing holds on to the mapped stream.
+    /// sink.stream()
+    ///     .map(|v| v * 2)
+    ///     .keep_alive_until(&stop.stream())
+    ///     .subscribe(move |v| if let Some(v) = v {
+    ///         seen_clone.lock().unwrap().push(*v);
+    ///     });
+    ///
+    /// sink.update(1);
+    /// sink.update(2);
+    /// stop.update(());
+    /// sink.update(3);
+    ///
+    /// assert_eq!(*seen.lock().unwrap(), vec![2, 4]);
+    /// ```
+    pub fn keep_alive_until<U>(&self, trigger: &Stream<U>) -> Stream<T> {
+        let inner = SafeInner::new(MemoryMode::NoMemory, None);
+        let inner_clone = inner.clone();
+        let peg = self.internal_subscribe(move |t| {
+            inner_clone.lock().update_borrowed(t);
+        });
+        let mut pinned = Some(peg.clone());
+        let inner_clone = inner.clone();
+        // the trigger subscription, released once it has fired.
+        let slot: Arc<Mutex<Option<Peg>>> = Arc::new(Mutex::new(None));
+        let slot_clone = slot.clone();
+        let tpeg = trigger.internal_subscribe(move |_| {
+            if let Some(pinned) = pinned.take() {
+                inner_clone.lock().update_borrowed(None);
+                pinned.unpeg();
+                let slot = slot_clone.clone();
+                // can't unsubscribe while in its own listener.
+                defer(move || {
+                    let tpeg = slot.lock().unwrap().take();
+                    if let Some(tpeg) = tpeg {
+                        tpeg.unpeg();
+                    }
+                });
+            }
+        });
+        tpeg.keep_mode(); // we drop tpeg, but keep listening
+        if inner.lock().is_ended() {
+            // fired while subscribing
+            tpeg.unpeg();
+        } else {
+            *slot.lock().unwrap() = Some(tpeg);
+        }
+        Stream { peg, inner }
+    }
+
+    /// Emits the last seen event when the stream closes.
+    ///
+    /// For a stream with memory, the remembered value is delivered when `last()` subscribes,
+    /// and counts as seen.
+    ///
+    /// ```
+    /// let sink = xi::Stream::sink();
+    ///
+    /// let coll = sink.stream().last().collect();
+    ///
+    /// sink.update(0);
+    /// sink.update(1);
+    /// sink.update(2);
+    /// sink.end();
+    ///
+    /// assert_eq!(coll.wait(), vec![2]);
+    /// ```
+    pub fn last(&self) -> Stream<T>
+    where
+        T: Clone,
+    {
+        let inner = SafeInner::new(MemoryMode::NoMemory, None);
+        let inner_clone = inner.clone();
+        let last = Mutex::new(None);
+        let peg = self.internal_subscribe(move |t| {
+            let mut lock = last.lock().unwrap();
+            if t.is_some() {
+                *lock = t.cloned();
+            } else {
+                let mut ilock = inner_clone.lock();
+                if let Some(l) = lock.take() {
+                    ilock.update_owned(Some(l));
+                }
+                ilock.update_owned(None);
+            }
+        });
+        Stream { peg, inner }
+    }
+
+    /// Emits a value extracted from the last seen event when the stream closes.
+    ///
+    /// Unlike [`last()`](struct.Stream.html#method.last) this doesn't require `T: Clone`,
+    /// only the extracted value is kept.
+    ///
+    /// ```
+    /// use xi::{Sink, Stream};
+    ///
+    /// // not Clone
+    /// struct Reading(u32);
+    ///
+    /// let sink: Sink<Reading> = Stream::sink();
+    ///
+    /// let coll = sink.stream().last_by(|r| r.0).collect();
+    ///
+    /// sink.update(Reading(0));
+    /// sink.update(Reading(1));
+    /// sink.end();
+    ///
+    /// assert_eq!(coll.wait(), vec![1]);
+    /// ```
+    pub fn last_by<U, F>(&self, mut f: F) -> Stream<U>
+    where
+        U: 'static,
+        F: FnMut(&T) -> U + Send + 'static,
+    {
+        let inner = SafeInner::new(MemoryMode::NoMemory, None);
+        let inner_clone = inner.clone();
+        let mut last = None;
+        let peg = self.internal_subscribe(move |t| {
+            if let Some(t) = t {
+                last = Some(f(t));
+            } else {
+                let mut ilock = inner_clone.lock();
+                if let Some(l) = last.take() {
+                    ilock.update_owned(Some(l));
+                }
+                ilock.update_owned(None);
+            }
+        });
+        Stream { peg, inner }
+    }
+
+    /// Keep the latest value of the stream in a mailbox, to be polled.
+    ///
+    /// Each poll takes the value out of the mailbox, so a poll returns `None` if nothing new
+    /// has arrived since the last poll. Values arriving between polls overwrite each other.
+    /// This suits a frame loop that wants "what changed since I last looked".
+    ///
+    /// ```
+    /// let sink = xi::Stream::sink();
+    ///
+    /// let mailbox = sink.stream().mailbox();
+    ///
+    /// sink.update(0);
+    /// sink.update(1);
+    ///
+    /// assert_eq!(mailbox.peek(), Some(1));
+    /// assert_eq!(mailbox.poll(), Some(1));
+    /// assert_eq!(mailbox.poll(), None); // nothing new
+    ///
+    /// sink.update(2);
+    /// assert_eq!(mailbox.poll(), Some(2));
+    /// ```
+    pub fn mailbox(&self) -> Mailbox<T>
+    where
+        T: Clone,
+    {
+        Mailbox::new(self)
+    }
+
+    /// Transform events.
+    ///
+    /// ```
+    /// let sink = xi::Stream::sink();
+    ///
+    /// let mapped = sink.stream().map(|v| format!("yo {}", v));
+    ///
+    /// let coll = mapped.collect();
+    ///
+    /// sink.update(41);
+    /// sink.update(42);
+    /// sink.end();
+    ///
+    /// assert_eq!(coll.wait(),
+    ///     vec!["yo 41".to_string(), "yo 42".to_string()]);
+    /// ```
+    pub fn map<U, F>(&self, mut f: F) -> Stream<U>
+    where
+        U: 'static,
+        F: FnMut(&T) -> U + Send + 'static,
+    {
+        let inner = SafeInner::new(MemoryMode::NoMemory, None);
+        let inner_clone = inner.clone();
+        let peg = self.internal_subscribe(move |t| {
+            if let Some(t) = t {
+                let u = f(t);
+                inner_clone.lock().update_owned(Some(u));
+            } else {
+                inner_clone.lock().update_owned(None);
+            }
+        });
+        Stream { peg, inner }
+    }
+
+    /// Optionally emit a final event when the stream ends.
+    ///
+    /// Events pass through unchanged. On the end, `f` is called, and if it returns a value
+    /// that is emitted before the end.
+    ///
+    /// ```
+    /// let sink = xi::Stream::sink();
+    ///
+    /// let framed = sink.stream().map_end(|| Some("</list>"));
+    ///
+    /// let coll = framed.collect();
+    ///
+    /// sink.update("<item/>");
+    /// sink.end();
+    ///
+    /// assert_eq!(coll.wait(), vec!["<item/>", "</list>"]);
+    /// ```
+    pub fn map_end<F>(&self, mut f: F) -> Stream<T>
+    where
+        F: FnMut() -> Option<T> + Send + 'static,
+    {
+        let inner = SafeInner::new(MemoryMode::NoMemory, None);
+        let inner_clone = inner.clone();
+        let peg = self.internal_subscribe(move |t| {
+            let mut lock = inner_clone.lock();
+            if t.is_some() {
+                lock.update_borrowed(t);
+            } else {
+                if let Some(last) = f() {
+                    lock.update_owned(Some(last));
+                }
+                lock.update_owned(None);
+            }
+        });
+        Stream { peg, inner }
+    }
+
+    /// Transform events, reusing the last result when the key of an event is the same as
+    /// that of the previous event.
+    ///
+    /// `key` is called for every event, `f` only when the key changes. For an expensive
+    /// function of part of the event.
+    ///
+    /// ```
+    /// let sink = xi::Stream::sink();
+    ///
+    /// // (user id, click count)
+    /// let greetings = sink.stream().map_memo(
+    ///     |(id, _)| *id,
+    ///     |(id, _)| format!("Hello user {}", id), // expensive lookup
+    /// );
+    ///
+    /// let coll = greetings.collect();
+    ///
+    /// sink.update((1, 0));
+    /// sink.update((1, 1)); // reuses the greeting
+    /// sink.update((2, 0));
+    /// sink.end();
+    ///
+    /// assert_eq!(coll.wait(), vec!["Hello user 1", "Hello user 1", "Hello user 2"]);
+    /// ```
+    pub fn map_memo<K, U, FK, F>(&self, mut key: FK, mut f: F) -> Stream<U>
+    where
+        K: PartialEq + Send + 'static,
+        U: Clone + Send + 'static,
+        FK: FnMut(&T) -> K + Send + 'static,
+        F: FnMut(&T) -> U + Send + 'static,
+    {
+        let mut last: Option<(K, U)> = None;
+        self.map(move |t| {
+            let k = key(t);
+            match last.as_ref() {
+                Some((lk, u)) if *lk == k => u.clone(),
+                _ => {
+                    let u = f(t);
+                    last = Some((k, u.clone()));
+                    u
+                }
+            }
+        })
+    }
+
+    /// For every event, emit a static value.
+    ///
+    /// ```
+    /// let sink = xi::Stream::sink();
+    ///
+    /// let mapped = sink.stream().map_to(42.0);
+    ///
+    /// let coll = mapped.collect();
+    ///
+    /// sink.update(0);
+    /// sink.update(1);
+    /// sink.end();
+    ///
+    /// assert_eq!(coll.wait(), vec![42.0, 42.0]);
+    /// ```
+    pub fn map_to<U>(&self, u: U) -> Stream<U>
+    where
+        U: Clone + Send + 'static,
+    {
+        self.map(move |_| u.clone())
+    }
+
+    /// Transform events with a function that has mutable access to a state.
+    ///
+    /// Where [`scan()`](struct.Stream.html#method.scan) emits the state itself, this emits
+    /// whatever the function returns, and the state stays private to the stream.
+    ///
+    /// ```
+    /// let sink = xi::Stream::sink();
+    ///
+    /// // difference to the previous event
+    /// let deltas = sink.stream().map_with_state(0, |prev, v| {
+    ///     let delta = v - *prev;
+    ///     *prev = *v;
+    ///     delta
+    /// });
+    ///
+    /// let coll = deltas.collect();
+    ///
+    /// sink.update(1);
+    /// sink.update(4);
+    /// sink.update(3);
+    /// sink.end();
+    ///
+    /// assert_eq!(coll.wait(), vec![1, 3, -1]);
+    /// ```
+    pub fn map_with_state<S, U, F>(&self, mut state: S, mut f: F) -> Stream<U>
+    where
+        S: Send + 'static,
+        U: 'static,
+        F: FnMut(&mut S, &T) -> U + Send + 'static,
+    {
+        self.map(move |t| f(&mut state, t))
+    }
+
+    /// Emit events that beat the highest key seen so far.
+    ///
+    /// The first event is always emitted. After that, an event is only emitted when its key
+    /// is strictly greater than the best key so far. Ties are not emitted.
+    ///
+    /// ```
+    /// let sink = xi::Stream::sink();
+    ///
+    /// let best = sink.stream().max_by_key(|v: &(&str, u32)| v.1);
+    ///
+    /// let coll = best.collect();
+    ///
+    /// sink.update(("a", 3));
+    /// sink.update(("b", 1));
+    /// sink.update(("c", 5));
+    /// sink.update(("d", 5)); // tie, not emitted
+    /// sink.end();
+    ///
+    /// assert_eq!(coll.wait(), vec![("a", 3), ("c", 5)]);
+    /// ```
+    pub fn max_by_key<K, F>(&self, f: F) -> Stream<T>
+    where
+        K: PartialOrd + 'static,
+        F: FnMut(&T) -> K + Send + 'static,
+    {
+        self.record_by_key(f, |next, best| next > best)
+    }
+
+    /// Emit events that beat the lowest key seen so far.
+    ///
+    /// The first event is always emitted. After that, an event is only emitted when its key
+    /// is strictly less than the best key so far. Ties are not emitted.
+    ///
+    /// ```
+    /// let sink = xi::Stream::sink();
+    ///
+    /// let best = sink.stream().min_by_key(|v: &f32| *v);
+    ///
+    /// let coll = best.collect();
+    ///
+    /// sink.update(3.0);
+    /// sink.update(4.0);
+    /// sink.update(2.5);
+    /// sink.end();
+    ///
+    /// assert_eq!(coll.wait(), vec![3.0, 2.5]);
+    /// ```
+    pub fn min_by_key<K, F>(&self, f: F) -> Stream<T>
+    where
+        K: PartialOrd + 'static,
+        F: FnMut(&T) -> K + Send + 'static,
+    {
+        self.record_by_key(f, |next, best| next < best)
+    }
+
+    /// Internal for max_by_key/min_by_key.
+    fn record_by_key<K, F>(&self, mut f: F, beats: fn(&K, &K) -> bool) -> Stream<T>
+    where
+        K: PartialOrd + 'static,
+        F: FnMut(&T) -> K + Send + 'static,
+    {
+        let inner = SafeInner::new(MemoryMode::NoMemory, None);
+        let inner_clone = inner.clone();
+        let mut best: Option<K> = None;
+        let peg = self.internal_subscribe(move |t| {
+            if let Some(t) = t {
+                let k = f(t);
+                let improved = match best.as_ref() {
+                    Some(b) => beats(&k, b),
+                    None => true,
+                };
+                if improved {
+                    best = Some(k);
+                    inner_clone.lock().update_borrowed(Some(t));
+                }
+            } else {
+                inner_clone.lock().update_borrowed(None);
+            }
+        });
+        Stream { peg, inner }
+    }
+
+    /// Merge events from a bunch of streams to one stream.
+    ///
+    /// ```
+    /// use xi::Stream;
+    ///
+    /// let sink1 = Stream::sink();
+    /// let sink2 = Stream::sink();
+    ///
+    /// let merged = Stream::merge(vec![
+    ///     sink1.stream(),
+    ///     sink2.stream()
+    /// ]);
+    ///
+    /// let coll = merged.collect();
+    ///
+    /// sink1.update(0);
+    /// sink2.update(10);
+    /// sink1.update(1);
+    /// sink2.update(11);
+    /// sink1.end();
+    /// sink2.end();
+    ///
+    /// assert_eq!(coll.wait(), vec![0, 10, 1, 11]);
+    /// ```
+    pub fn merge(streams: Vec<Stream<T>>) -> Stream<T> {
+        let inner = SafeInner::new(MemoryMode::NoMemory, None);
+        let inner_clone = inner.clone();
+        let active = Arc::new(AtomicUsize::new(streams.len()));
+        let pegs: Vec<_> = streams
+            .into_iter()
+            .map(|stream| {
+                let inner_clone = inner_clone.clone();
+                let active = active.clone();
+                stream.internal_subscribe(move |t| {
+                    if t.is_some() {
+                        inner_clone.lock().update_borrowed(t);
+                    } else if active.fetch_sub(1, Ordering::SeqCst) == 1 {
+                        // all streams are ended. close the merged one
+                        inner_clone.lock().update_borrowed(None);
+                    }
+                })
+            })
+            .collect();
+        let peg = Peg::many(pegs);
+        Stream { peg, inner }
+    }
+
+    /// Merge events from two streams, giving priority to the first.
+    ///
+    /// When both streams emit as a result of the same
+    /// [`Sink::update()`](struct.Sink.html#method.update), the events of `a` are always
+    /// forwarded before the events of `b`, regardless of the order the streams were
+    /// created in. This is done by holding back the events of `b` until the update has
+    /// propagated through the tree, which is also why `T` must be `Clone`.
+    ///
+    /// Like [`merge()`](struct.Stream.html#method.merge), the resulting stream ends when
+    /// both streams have ended.
+    ///
+    /// ```
+    /// use xi::Stream;
+    ///
+    /// let sink = Stream::sink();
+    ///
+    /// // b is subscribed to the sink before a
+    /// let b = sink.stream().map(|v| v * 10);
+    /// let a = sink.stream().map(|v| v + 1);
+    ///
+    /// let merged = Stream::merge_left_first(a, b);
+    ///
+    /// let coll = merged.collect();
+    ///
+    /// sink.update(1);
+    /// sink.update(2);
+    /// sink.end();
+    ///
+    /// assert_eq!(coll.wait(), vec![2, 10, 3, 20]);
+    /// ```
+    pub fn merge_left_first(a: Stream<T>, b: Stream<T>) -> Stream<T>
+    where
+        T: Clone,
+    {
+        let inner = SafeInner::new(MemoryMode::NoMemory, None);
+        let active = Arc::new(AtomicUsize::new(2));
+        let peg_a = {
+            let inner_clone = inner.clone();
+            let active = active.clone();
+            a.internal_subscribe(move |t| {
+                if t.is_some() {
+                    inner_clone.lock().update_borrowed(t);
+                } else if active.fetch_sub(1, Ordering::SeqCst) == 1 {
+                    inner_clone.lock().update_borrowed(None);
+                }
+            })
+        };
+        let peg_b = {
+            let inner_clone = inner.clone();
+            b.internal_subscribe(move |t| {
+                let inner_clone = inner_clone.clone();
+                let active = active.clone();
+                let mut t = t.cloned();
+                let is_end = t.is_none();
+                defer(move || {
+                    if !is_end {
+                        inner_clone.lock().update_owned(t.take());
+                    } else if active.fetch_sub(1, Ordering::SeqCst) == 1 {
+                        inner_clone.lock().update_owned(None);
+                    }
+                });
+            })
+        };
+        let peg = Peg::many(vec![peg_a, peg_b]);
+        Stream { peg, inner }
+    }
+
+    /// Merge events from this and another stream.
+    ///
+    /// The same as [`merge()`](struct.Stream.html#method.merge) of the two streams, for use
+    /// in a chain of operations. The resulting stream ends when both streams have ended.
+    ///
+    /// ```
+    /// let clicks = xi::Stream::sink();
+    /// let keys = xi::Stream::sink();
+    ///
+    /// let enter = keys.stream().filter(|k| *k == "enter").map_to("submit");
+    ///
+    /// let submits = clicks.stream()
+    ///     .filter(|c| *c == "button")
+    ///     .map_to("submit")
+    ///     .merge_with(&enter)
+    ///     .map(|s| s.to_uppercase());
+    ///
+    /// let coll = submits.collect();
+    ///
+    /// clicks.update("button");
+    /// keys.update("a");
+    /// keys.update("enter");
+    /// clicks.end();
+    /// keys.end();
+    ///
+    /// assert_eq!(coll.wait(), vec!["SUBMIT", "SUBMIT"]);
+    /// ```
+    pub fn merge_with(&self, other: &Stream<T>) -> Stream<T> {
+        let inner = SafeInner::new(MemoryMode::NoMemory, None);
+        let active = Arc::new(AtomicUsize::new(2));
+        let pegs = [self, other].map(|stream| {
+            let inner_clone = inner.clone();
+            let active = active.clone();
+            stream.internal_subscribe(move |t| {
+                if t.is_some() {
+                    inner_clone.lock().update_borrowed(t);
+                } else if active.fetch_sub(1, Ordering::SeqCst) == 1 {
+                    inner_clone.lock().update_borrowed(None);
+                }
+            })
+        });
+        let peg = Peg::many(pegs.into());
+        Stream { peg, inner }
+    }
+
+    /// Merge events from a bunch of streams, transformed by a function that is also given
+    /// the index of the stream each event comes from.
+    ///
+    /// Like [`merge()`](struct.Stream.html#method.merge) followed by a
+    /// [`map()`](struct.Stream.html#method.map), where the function knows the source of the
+    /// event. The resulting stream ends when all streams have ended.
+    ///
+    /// ```
+    /// use xi::Stream;
+    ///
+    /// let left = Stream::sink();
+    /// let right = Stream::sink();
+    ///
+    /// let moves = Stream::multi_map(vec![left.stream(), right.stream()], |i, steps| {
+    ///     if i == 0 { -steps } else { *steps }
+    /// });
+    ///
+    /// let coll = moves.collect();
+    ///
+    /// left.update(2);
+    /// right.update(3);
+    /// left.end();
+    /// right.end();
+    ///
+    /// assert_eq!(coll.wait(), vec![-2, 3]);
+    /// ```
+    pub fn multi_map<U, F>(streams: Vec<Stream<T>>, f: F) -> Stream<U>
+    where
+        U: 'static,
+        F: FnMut(usize, &T) -> U + Send + 'static,
+    {
+        let inner = SafeInner::new(MemoryMode::NoMemory, None);
+        let active = Arc::new(AtomicUsize::new(streams.len()));
+        // shared by the subscriptions, which may be updated from different threads.
+        let f = Arc::new(Mutex::new(f));
+        let pegs: Vec<_> = streams
+            .into_iter()
+            .enumerate()
+            .map(|(i, stream)| {
+                let inner_clone = inner.clone();
+                let active = active.clone();
+                let f = f.clone();
+                stream.internal_subscribe(move |t| {
+                    if let Some(t) = t {
+                        let u = (f.lock().unwrap())(i, t);
+                        inner_clone.lock().update_owned(Some(u));
+                    } else if active.fetch_sub(1, Ordering::SeqCst) == 1 {
+                        inner_clone.lock().update_owned(None);
+                    }
+                })
+            })
+            .collect();
+        let peg = Peg::many(pegs);
+        Stream { peg, inner }
+    }
+
+    /// Tag every event with a key.
+    ///
+    /// Useful to name streams before merging them.
+    ///
+    /// ```
+    /// use xi::Stream;
+    ///
+    /// let sink1 = Stream::sink();
+    /// let sink2 = Stream::sink();
+    ///
+    /// let merged = Stream::merge(vec![
+    ///     sink1.stream().observe_with_key("left"),
+    ///     sink2.stream().observe_with_key("right"),
+    /// ]);
+    ///
+    /// let coll = merged.collect();
+    ///
+    /// sink1.update(0);
+    /// sink2.update(1);
+    /// sink1.end();
+    /// sink2.end();
+    ///
+    /// assert_eq!(coll.wait(), vec![("left", 0), ("right", 1)]);
+    /// ```
+    pub fn observe_with_key<K>(&self, key: K) -> Stream<(K, T)>
+    where
+        T: Clone,
+        K: Clone + Send + 'static,
+    {
+        self.map(move |t| (key.clone(), t.clone()))
+    }
+
+    /// Remember a placeholder value until the first event.
+    ///
+    /// The result is a memory stream holding `placeholder` until the first event arrives,
+    /// after which it works like [`remember()`](struct.Stream.html#method.remember). A
+    /// subscriber added before the first event receives the placeholder, one added after
+    /// receives the latest event. The placeholder is never emitted again.
+    ///
+    /// Unlike [`start_with()`](struct.Stream.html#method.start_with), the remembered value
+    /// follows the events.
+    ///
+    /// ```
+    /// #[derive(Debug, Clone, PartialEq)]
+    /// enum Reading {
+    ///     Unknown,
+    ///     Celsius(i32),
+    /// }
+    ///
+    /// let sink = xi::Stream::sink();
+    ///
+    /// let reading = sink.stream().or_value_until_first(Reading::Unknown);
+    ///
+    /// let early = reading.collect();
+    ///
+    /// sink.update(Reading::Celsius(21));
+    ///
+    /// let late = reading.collect();
+    ///
+    /// assert_eq!(early.take(), vec![Reading::Unknown, Reading::Celsius(21)]);
+    /// assert_eq!(late.take(), vec![Reading::Celsius(21)]);
+    /// ```
+    pub fn or_value_until_first(&self, placeholder: T) -> Stream<T>
+    where
+        T: Clone,
+    {
+        let inner = SafeInner::new(MemoryMode::KeepUntilEnd, Some(placeholder));
+        let inner_clone = inner.clone();
+        let peg = self.internal_subscribe(move |t| {
+            let t = t.cloned();
+            inner_clone.lock().update_owned(t);
+        });
+        Stream { peg, inner }
+    }
+
+    /// Pair up each event with the one before it.
+    ///
+    /// Emits `(previous, current)` for every event but the first. The previous event is let
+    /// go of when the stream ends. The result is not a "memory" stream.
+    ///
+    /// ```
+    /// let sink = xi::Stream::sink();
+    ///
+    /// let deltas = sink.stream().pairwise().map(|(prev, cur)| cur - prev);
+    ///
+    /// let coll = deltas.collect();
+    ///
+    /// sink.update(1);
+    /// sink.update(4);
+    /// sink.update(9);
+    /// sink.end();
+    ///
+    /// assert_eq!(coll.wait(), vec![3, 5]);
+    /// ```
+    pub fn pairwise(&self) -> Stream<(T, T)>
+    where
+        T: Clone,
+    {
+        self.pairwise_from(None)
+    }
+
+    /// Pair up each event with the one before it, starting with a seed as the previous.
+    ///
+    /// Like [`pairwise()`](struct.Stream.html#method.pairwise), but also emits for the first
+    /// event, paired with `seed`.
+    ///
+    /// ```
+    /// let sink = xi::Stream::sink();
+    ///
+    /// let pairs = sink.stream().pairwise_seeded(0);
+    ///
+    /// let coll = pairs.collect();
+    ///
+    /// sink.update(1);
+    /// sink.update(4);
+    /// sink.end();
+    ///
+    /// assert_eq!(coll.wait(), vec![(0, 1), (1, 4)]);
+    /// ```
+    pub fn pairwise_seeded(&self, seed: T) -> Stream<(T, T)>
+    where
+        T: Clone,
+    {
+        self.pairwise_from(Some(seed))
+    }
+
+    /// Internal pairwise with an optional previous value.
+    fn pairwise_from(&self, mut prev: Option<T>) -> Stream<(T, T)>
+    where
+        T: Clone,
+    {
+        let inner = SafeInner::new(MemoryMode::NoMemory, None);
+        let inner_clone = inner.clone();
+        let peg = self.internal_subscribe(move |t| {
+            if let Some(t) = t {
+                let pair = prev.replace(t.clone()).map(|p| (p, t.clone()));
+                if pair.is_some() {
+                    inner_clone.lock().update_owned(pair);
+                }
+            } else {
+                prev = None;
+                inner_clone.lock().update_owned(None);
+            }
+        });
+        Stream { peg, inner }
+    }
+
+    /// Split the stream in two by a predicate.
+    ///
+    /// Events for which `f` returns true go to the first stream, the others to the second.
+    /// The streams share one subscription to the source, so `f` is called once per event.
+    /// Both streams end when the source ends. Each stream can be dropped, or detached by a
+    /// [`SubscriptionSet`](struct.SubscriptionSet.html), without affecting the other.
+    ///
+    /// ```
+    /// let sink = xi::Stream::sink();
+    ///
+    /// let (even, odd) = sink.stream().partition(|v| v % 2 == 0);
+    ///
+    /// let coll1 = even.collect();
+    /// let coll2 = odd.collect();
+    ///
+    /// sink.update(0);
+    /// sink.update(1);
+    /// sink.update(2);
+    /// sink.end();
+    ///
+    /// assert_eq!(coll1.wait(), vec![0, 2]);
+    /// assert_eq!(coll2.wait(), vec![1]);
+    /// ```
+    pub fn partition<F>(&self, mut f: F) -> (Stream<T>, Stream<T>)
+    where
+        F: FnMut(&T) -> bool + Send + 'static,
+    {
+        self.split_with(move |t, halves| {
+            if let Some(t) = t {
+                if f(t) {
+                    halves.first(Some(t));
+                } else {
+                    halves.second(Some(t));
+                }
+            } else {
+                halves.first(None);
+                halves.second(None);
+            }
+        })
+    }
+
+    /// Follow whichever stream emits first, and drop the others.
+    ///
+    /// The first stream to emit an event, a value or an end, wins. The subscriptions to all
+    /// other streams are dropped straight away, and from then on the stream only forwards
+    /// the events of the winner, including its end. An empty vec gives a stream that is
+    /// ended straight away.
+    ///
+    /// ```
+    /// use xi::Stream;
+    ///
+    /// let sink1 = Stream::sink();
+    /// let sink2 = Stream::sink();
+    ///
+    /// let race = Stream::race(vec![sink1.stream(), sink2.stream()]);
+    ///
+    /// let coll = race.collect();
+    ///
+    /// sink2.update(10); // sink2 wins
+    /// sink1.update(0);
+    /// sink2.update(11);
+    /// sink1.end();
+    /// sink2.end();
+    ///
+    /// assert_eq!(coll.wait(), vec![10, 11]);
+    /// ```
+    pub fn race(streams: Vec<Stream<T>>) -> Stream<T> {
+        let inner = SafeInner::new(MemoryMode::NoMemory, None);
+        if streams.is_empty() {
+            inner.lock().update_borrowed(None);
+        }
+        // the subscriptions by index, held by the peg of the resulting stream.
+        let (pegged, peg) = Pegged::new_pair(Vec::<Option<Peg>>::new());
+        let pegged = Arc::new(pegged);
+        let winner: Arc<Mutex<Option<usize>>> = Arc::new(Mutex::new(None));
+        for (idx, stream) in streams.into_iter().enumerate() {
+            if winner.lock().unwrap().is_some() {
+                // a remembered value already decided it
+                break;
+            }
+            let inner_clone = inner.clone();
+            let pegged_clone = pegged.clone();
+            let winner = winner.clone();
+            let sub = stream.internal_subscribe(move |t| {
+                let first = {
+                    let mut lock = winner.lock().unwrap();
+                    match *lock {
+                        Some(w) if w != idx => return,
+                        Some(_) => false,
+                        None => {
+                            *lock = Some(idx);
+                            true
+                        }
+                    }
+                };
+                if first {
+                    let losers: Vec<Peg> = pegged_clone.with_value(|subs| {
+                        subs.map(|subs| {
+                            subs.iter_mut()
+                                .enumerate()
+                                .filter(|(i, _)| *i != idx)
+                                .filter_map(|(_, s)| s.take())
+                                .collect()
+                        })
+                        .unwrap_or_default()
+                    });
+                    // drop outside the lock
+                    drop(losers);
+                }
+                inner_clone.lock().update_borrowed(t);
+            });
+            let mut sub = Some(sub);
+            pegged.with_value(|subs| {
+                if let Some(subs) = subs {
+                    subs.push(sub.take());
+                }
+            });
+        }
+        Stream { peg, inner }
+    }
+
+    /// Make a stream in memory mode. Each value is remembered for future subscribers.
+    ///
+    /// ```
+    /// let sink = xi::Stream::sink();
+    ///
+    /// let rem = sink.stream().remember();
+    ///
+    /// sink.update(0);
+    /// sink.update(1);
+    ///
+    /// // receives last "remembered" value
+    /// let coll = rem.collect();
+    ///
+    /// sink.update(2);
+    /// sink.end();
+    ///
+    /// assert_eq!(coll.wait(), vec![1, 2]);
+    /// ```
+    pub fn remember(&self) -> Stream<T>
+    where
+        T: Clone,
+    {
+        self.remember_mode(MemoryMode::KeepUntilEnd)
+    }
+
+    /// Internal remember where we can chose "mode"
+    fn remember_mode(&self, mode: MemoryMode) -> Stream<T>
+    where
+        T: Clone,
+    {
+        let inner = SafeInner::new(mode, None);
+        let inner_clone = inner.clone();
+        let peg = self.internal_subscribe(move |t| {
+            let t = t.cloned();
+            inner_clone.lock().update_owned(t);
+        });
+        Stream { peg, inner }
+    }
+
+    /// On every event in this stream, combine with the last value of the other stream.
+    ///
+    /// To only emit the last value of a stream when another stream emits, see
+    /// [`emit_latest_on()`](struct.Stream.html#method.emit_latest_on).
+    ///
+    /// ```
+    /// use xi::Stream;
+    ///
+    /// let sink1 = Stream::sink();
+    /// let sink2 = Stream::sink();
+    ///
+    /// let comb = sink1.stream().sample_combine(&sink2.stream());
+    ///
+    /// let coll = comb.collect();
+    ///
+    /// sink1.update(0);     // lost, because no value in sink2
+    /// sink2.update("foo"); // doesn't trigger combine
+    /// sink1.update(1);
+    /// sink1.update(2);
+    /// sink2.update("bar");
+    /// sink2.end();         // sink2 is "bar" forever
+    /// sink1.update(3);
+    /// sink1.end();
+    ///
+    /// assert_eq!(coll.wait(),
+    ///   vec![(1, "foo"), (2, "foo"), (3, "bar")])
+    /// ```
+    pub fn sample_combine<U>(&self, other: &Stream<U>) -> Stream<(T, U)>
+    where
+        T: Clone,
+        U: Clone,
+    {
+        let inner = SafeInner::new(MemoryMode::NoMemory, None);
+        let inner_clone = inner.clone();
+        let rem = other.remember_mode(MemoryMode::KeepAfterEnd);
+        let peg = self.internal_subscribe(move |t| {
+            if let Some(t) = t {
+                let rlock = rem.inner.lock();
+                if let Some(u) = rlock.peek_memory().as_ref() {
+                    // we have both t and u
+                    let v = (t.clone(), u.clone());
+                    inner_clone.lock().update_owned(Some(v));
+                }
+            } else {
+                inner_clone.lock().update_borrowed(None);
+            }
+        });
+        Stream { peg, inner }
+    }
+
+    /// Combine events from the past, with new events to produce an output, without
+    /// emitting the seed.
+    ///
+    /// Like [`fold()`](struct.Stream.html#method.fold), but the result is not a "memory"
+    /// stream and only emits the accumulated value for each incoming event. To start from
+    /// the first event, see [`scan_from_first()`](struct.Stream.html#method.scan_from_first).
+    ///
+    /// ```
+    /// let sink = xi::Stream::sink();
+    ///
+    /// let scanned = sink.stream()
+    ///     .scan(40.5, |prev, next| prev + (*next as f32) / 2.0);
+    ///
+    /// let coll = scanned.collect();
+    ///
+    /// sink.update(0);
+    /// sink.update(1);
+    /// sink.update(2);
+    /// sink.end();
+    ///
+    /// assert_eq!(coll.wait(), vec![40.5, 41.0, 42.0]);
+    /// ```
+    pub fn scan<U, F>(&self, seed: U, mut f: F) -> Stream<U>
+    where
+        U: 'static,
+        F: FnMut(U, &T) -> U + Send + 'static,
+    {
+        let inner = SafeInner::new(MemoryMode::NoMemory, None);
+        let inner_clone = inner.clone();
+        let mut acc = Some(seed);
+        let peg = self.internal_subscribe(move |t| {
+            if let Some(t) = t {
+                let next = f(acc.take().expect("scan without a previous value"), t);
+                let next = acc.get_or_insert(next);
+                inner_clone.lock().update_borrowed(Some(next));
+            } else {
+                inner_clone.lock().update_borrowed(None);
+            }
+        });
+        Stream { peg, inner }
+    }
+
+    /// Transform events with a state, emitting only when the function returns `Some`.
+    ///
+    /// The state is updated for every event.
+    ///
+    /// ```
+    /// let sink = xi::Stream::sink();
+    ///
+    /// // emit every third event, with a count
+    /// let scanned = sink.stream().scan_and_filter(0, |count, v: &&str| {
+    ///     *count += 1;
+    ///     if *count % 3 == 0 {
+    ///         Some(format!("{} {}", count, v))
+    ///     } else {
+    ///         None
+    ///     }
+    /// });
+    ///
+    /// let coll = scanned.collect();
+    ///
+    /// for v in &["a", "b", "c", "d", "e", "f", "g"] {
+    ///     sink.update(*v);
+    /// }
+    /// sink.end();
+    ///
+    /// assert_eq!(coll.wait(), vec!["3 c".to_string(), "6 f".to_string()]);
+    /// ```
+    pub fn scan_and_filter<S, U, F>(&self, seed: S, f: F) -> Stream<U>
+    where
+        S: Send + 'static,
+        U: 'static,
+        F: FnMut(&mut S, &T) -> Option<U> + Send + 'static,
+    {
+        self.scan_emit_all(seed, f)
+    }
+
+    /// Transform events with a state, emitting zero, one or many values per event.
+    ///
+    /// The function is given the mutable state and the event, and returns the values to
+    /// emit, as anything that can be iterated over.
+    ///
+    /// ```
+    /// let sink = xi::Stream::sink();
+    ///
+    /// // emit running total, repeated as many times as the event value
+    /// let scanned = sink.stream().scan_emit_all(0, |sum, v| {
+    ///     *sum += v;
+    ///     std::iter::repeat(*sum).take(*v)
+    /// });
+    ///
+    /// let coll = scanned.collect();
+    ///
+    /// sink.update(1);
+    /// sink.update(0);
+    /// sink.update(2);
+    /// sink.end();
+    ///
+    /// assert_eq!(coll.wait(), vec![1, 3, 3]);
+    /// ```
+    pub fn scan_emit_all<S, U, I, F>(&self, seed: S, mut f: F) -> Stream<U>
+    where
+        S: Send + 'static,
+        U: 'static,
+        I: IntoIterator<Item = U>,
+        F: FnMut(&mut S, &T) -> I + Send + 'static,
+    {
+        let inner = SafeInner::new(MemoryMode::NoMemory, None);
+        let inner_clone = inner.clone();
+        let mut state = seed;
+        let peg = self.internal_subscribe(move |t| {
+            if let Some(t) = t {
+                // the user's iterator runs without the output locked.
+                let us: Vec<U> = f(&mut state, t).into_iter().collect();
+                let mut lock = inner_clone.lock();
+                for u in us {
+                    lock.update_owned(Some(u));
+                }
+            } else {
+                inner_clone.lock().update_owned(None);
+            }
+        });
+        Stream { peg, inner }
+    }
+
+    /// Combine events from the past, with new events to produce an output, starting from
+    /// the first event instead of a seed.
+    ///
+    /// The first event is turned into the accumulated value by `init`, and every following
+    /// event is combined with it by `f`. Where [`fold()`](struct.Stream.html#method.fold)
+    /// emits its seed synchronously, this emits nothing until the first event. The result
+    /// is not a "memory" stream.
+    ///
+    /// ```
+    /// let sink = xi::Stream::sink();
+    ///
+    /// // running total
+    /// let total = sink.stream().scan_from_first(|v| *v, |acc, v| acc + v);
+    ///
+    /// let coll = total.collect();
+    ///
+    /// sink.update(1);
+    /// sink.update(2);
+    /// sink.update(3);
+    /// sink.end();
+    ///
+    /// assert_eq!(coll.wait(), vec![1, 3, 6]);
+    /// ```
+    pub fn scan_from_first<U, FI, F>(&self, mut init: FI, mut f: F) -> Stream<U>
+    where
+        U: 'static,
+        FI: FnMut(&T) -> U + Send + 'static,
+        F: FnMut(U, &T) -> U + Send + 'static,
+    {
+        let inner = SafeInner::new(MemoryMode::NoMemory, None);
+        let inner_clone = inner.clone();
+        let mut acc: Option<U> = None;
+        let peg = self.internal_subscribe(move |t| {
+            if let Some(t) = t {
+                let next = match acc.take() {
+                    Some(prev) => f(prev, t),
+                    None => init(t),
+                };
+                let next = acc.get_or_insert(next);
+                inner_clone.lock().update_borrowed(Some(next));
+            } else {
+                inner_clone.lock().update_borrowed(None);
+            }
+        });
+        Stream { peg, inner }
+    }
+
+    /// Project out a part of each event, and only emit when that part changes.
+    ///
+    /// This is `map()` followed by `dedupe()` in one operation. The stream keeps the memory
+    /// mode of this stream, so selecting from a remembered stream gives a remembered
+    /// stream, starting out with the projection of the remembered value.
+    ///
+    /// ```
+    /// #[derive(Clone)]
+    /// struct State {
+    ///     name: String,
+    ///     clicks: u32,
+    /// }
+    ///
+    /// let sink = xi::Stream::sink();
+    /// let state = sink.stream().remember();
+    ///
+    /// sink.update(State { name: "foo".to_string(), clicks: 0 });
+    ///
+    /// let name = state.select(|s| s.name.clone());
+    /// assert!(name.has_memory());
+    ///
+    /// let coll = name.collect();
+    ///
+    /// sink.update(State { name: "foo".to_string(), clicks: 1 });
+    /// sink.update(State { name: "bar".to_string(), clicks: 1 });
+    /// sink.end();
+    ///
+    /// assert_eq!(coll.wait(), vec!["foo".to_string(), "bar".to_string()]);
+    /// ```
+    pub fn select<U, F>(&self, mut f: F) -> Stream<U>
+    where
+        U: Clone + PartialEq + 'static,
+        F: FnMut(&T) -> U + Send + 'static,
+    {
+        let mode = self.inner.lock().memory_mode();
+        let inner = SafeInner::new(mode, None);
+        let inner_clone = inner.clone();
+        let mut prev: Option<U> = None;
+        let peg = self.internal_subscribe(move |t| {
+            if let Some(t) = t {
+                let u = f(t);
+                if prev.as_ref() != Some(&u) {
+                    prev = Some(u.clone());
+                    inner_clone.lock().update_owned(Some(u));
+                }
+            } else {
+                prev.take();
+                inner_clone.lock().update_owned(None);
+            }
+        });
+        Stream { peg, inner }
+    }
+
+    /// Tag every event with a process wide, monotonically increasing sequence number.
+    ///
+    /// The number is taken when the event passes this stream. All sequenced streams share
+    /// the same counter, so events collected from different streams can be put back in the
+    /// order they happened. The number orders events, but carries no timing meaning.
+    ///
+    /// ```
+    /// let sink = xi::Stream::sink();
+    ///
+    /// let even = sink.stream().filter(|v| v % 2 == 0).sequenced().collect();
+    /// let odd = sink.stream().filter(|v| v % 2 == 1).sequenced().collect();
+    ///
+    /// sink.update(0);
+    /// sink.update(1);
+    /// sink.update(2);
+    /// sink.end();
+    ///
+    /// let mut all = even.wait();
+    /// all.extend(odd.wait());
+    /// all.sort_by_key(|(seq, _)| *seq);
+    ///
+    /// let values: Vec<_> = all.into_iter().map(|(_, v)| v).collect();
+    /// assert_eq!(values, vec![0, 1, 2]);
+    /// ```
+    pub fn sequenced(&self) -> Stream<(u64, T)>
+    where
+        T: Clone,
+    {
+        self.map(|t| (SEQUENCE.fetch_add(1, Ordering::Relaxed), t.clone()))
+    }
+
+    /// Transform events, sharing one transformed stream between all callers using the
+    /// same tag on this stream.
+    ///
+    /// The first call with a tag creates the stream like [`map()`](struct.Stream.html#method.map),
+    /// and registers it with this stream. Later calls with the same tag, on this stream or a
+    /// clone of it, return the registered stream and drop their `f`. That way independent
+    /// parts of a program deriving the same thing from a source run `f` once per event,
+    /// rather than once per part.
+    ///
+    /// The registration doesn't keep the transformed stream alive. Once every clone of it,
+    /// and every stream derived from it, is dropped, the next call with the tag creates it
+    /// anew. Panics if a tag is used again with another type of event.
+    ///
+    /// ```
+    /// use std::sync::atomic::{AtomicUsize, Ordering};
+    /// use std::sync::Arc;
+    ///
+    /// let sink = xi::Stream::sink();
+    /// let source = sink.stream();
+    ///
+    /// let calls = Arc::new(AtomicUsize::new(0));
+    ///
+    /// let parsed: Vec<_> = (0..3).map(|_| {
+    ///     let calls = calls.clone();
+    ///     source.shared_map("parsed", move |s: &&str| {
+    ///         calls.fetch_add(1, Ordering::SeqCst);
+    ///         s.parse::<u32>().unwrap()
+    ///     })
+    /// }).collect();
+    ///
+    /// let colls: Vec<_> = parsed.iter().map(|p| p.collect()).collect();
+    ///
+    /// sink.update("42");
+    /// sink.end();
+    ///
+    /// assert_eq!(calls.load(Ordering::SeqCst), 1);
+    ///
+    /// for coll in colls {
+    ///     assert_eq!(coll.wait(), vec![42]);
+    /// }
+    /// ```
+    pub fn shared_map<U, F>(&self, tag: &'static str, f: F) -> Stream<U>
+    where
+        U: Send + 'static,
+        F: FnMut(&T) -> U + Send + 'static,
+    {
+        // the registration doesn't keep the mapped stream alive, since the mapped stream
+        // keeps this one alive.
+        let registered = |lock: &Inner<T>| {
+            let (inner, peg) = lock
+                .shared(tag)?
+                .downcast_ref::<(WeakInner<U>, WeakPeg)>()
+                .expect("shared_map() tag used with another type");
+            let mut peg = peg.upgrade()?;
+            peg.add_related(self.peg.clone());
+            let inner = inner.upgrade()?;
+            Some(Stream { peg, inner })
+        };
+        if let Some(s) = registered(&self.inner.lock()) {
+            return s;
+        }
+        // map() locks this stream, it must be created outside the lock.
+        let mapped = self.map(f);
+        let mut lock = self.inner.lock();
+        if let Some(s) = registered(&lock) {
+            // another thread got there first
+            drop(lock);
+            return s;
+        }
+        if !lock.is_ended() {
+            let weak = (mapped.inner.downgrade(), mapped.peg.downgrade());
+            lock.insert_shared(tag, Box::new(weak));
+        }
+        mapped
+    }
+
+    /// Ignore events until another stream emits.
+    ///
+    /// Once `notifier` has emitted, whatever the value, all events pass through. The end of
+    /// this stream passes through regardless, while the end of the notifier doesn't matter.
+    /// The subscription to the notifier is dropped after its first event.
+    ///
+    /// Together with [`take_until()`](struct.Stream.html#method.take_until) this picks out a
+    /// window of events, `stream.skip_until(&start).take_until(&stop)`.
+    ///
+    /// ```
+    /// use xi::Stream;
+    ///
+    /// let sink = Stream::sink();
+    /// let start = Stream::sink();
+    ///
+    /// let started = sink.stream().skip_until(&start.stream());
+    ///
+    /// let coll = started.collect();
+    ///
+    /// sink.update(0); // skipped
+    /// start.update(());
+    /// sink.update(1);
+    /// sink.update(2);
+    /// sink.end();
+    ///
+    /// assert_eq!(coll.wait(), [1, 2]);
+    /// ```
+    pub fn skip_until<U>(&self, notifier: &Stream<U>) -> Stream<T> {
+        let inner = SafeInner::new(MemoryMode::NoMemory, None);
+        let inner_clone = inner.clone();
+        let open = Arc::new(AtomicBool::new(false));
+        // the notifier subscription, held by the subscription to this stream.
+        let slot: Arc<Mutex<Option<Peg>>> = Arc::new(Mutex::new(None));
+        let notifier_peg = {
+            let open = open.clone();
+            let slot = Arc::downgrade(&slot);
+            notifier.internal_subscribe(move |u| {
+                if u.is_some() && !open.swap(true, Ordering::SeqCst) {
+                    let slot = slot.clone();
+                    // can't drop the subscription while in its own listener.
+                    defer(move || {
+                        if let Some(slot) = slot.upgrade() {
+                            let peg = slot.lock().unwrap().take();
+                            drop(peg);
+                        }
+                    });
+                }
+            })
+        };
+        if !open.load(Ordering::SeqCst) {
+            *slot.lock().unwrap() = Some(notifier_peg);
+        }
+        let peg = self.internal_subscribe(move |t| {
+            // holds on to the notifier subscription
+            let _held = &slot;
+            if t.is_none() || open.load(Ordering::SeqCst) {
+                inner_clone.lock().update_borrowed(t);
+            }
+        });
+        Stream { peg, inner }
+    }
+
+    /// Split the stream in two at a position.
+    ///
+    /// The first `n` events go to the first stream, which ends after the `n`th event. All
+    /// subsequent events go to the second stream. Both streams end when the source ends.
+    /// The streams share one subscription to the source, and either can be dropped without
+    /// affecting the other.
+    ///
+    /// ```
+    /// let sink = xi::Stream::sink();
+    ///
+    /// let (header, body) = sink.stream().split_at(2);
+    ///
+    /// let coll1 = header.collect();
+    /// let coll2 = body.collect();
+    ///
+    /// sink.update(0);
+    /// sink.update(1); // header ends here
+    /// sink.update(2);
+    /// sink.update(3);
+    /// sink.end();
+    ///
+    /// assert_eq!(coll1.wait(), vec![0, 1]);
+    /// assert_eq!(coll2.wait(), vec![2, 3]);
+    /// ```
+    pub fn split_at(&self, n: usize) -> (Stream<T>, Stream<T>) {
+        let mut seen = 0;
+        let (first, second) = self.split_with(move |t, halves| {
+            if let Some(t) = t {
+                if seen < n {
+                    seen += 1;
+                    halves.first(Some(t));
+                    if seen == n {
+                        halves.first(None);
+                    }
+                } else {
+                    halves.second(Some(t));
+                }
+            } else {
+                halves.first(None);
+                halves.second(None);
+            }
+        });
+        if n == 0 {
+            // nothing goes to the first stream
+            first.inner.lock().update_borrowed(None);
+        }
+        (first, second)
+    }
+
+    /// Separate the first event from the rest.
+    ///
+    /// The first stream emits the first event and then ends. The second stream emits all
+    /// events after the first, and ends when the source ends, also when that is straight
+    /// after the first. This is the same as
+    /// [`first_and_rest()`](struct.Stream.html#method.first_and_rest), which suits
+    /// protocols where a header configures the handling of the payloads that follow.
+    ///
+    /// ```
+    /// let sink = xi::Stream::sink();
+    ///
+    /// let (header, payload) = sink.stream().split_first();
+    ///
+    /// let coll1 = header.collect();
+    /// let coll2 = payload.collect();
+    ///
+    /// sink.update("v1");
+    /// sink.end();
+    ///
+    /// assert_eq!(coll1.wait(), vec!["v1"]);
+    /// assert_eq!(coll2.wait(), Vec::<&str>::new());
+    /// ```
+    pub fn split_first(&self) -> (Stream<T>, Stream<T>) {
+        self.split_at(1)
+    }
+
+    /// Split the stream in two through one shared subscription, routing the events with
+    /// `f`. Each half has a peg of its own, and they hold the shared subscription between
+    /// them, so either half can be dropped or detached without affecting the other.
+    fn split_with<F>(&self, mut f: F) -> (Stream<T>, Stream<T>)
+    where
+        F: FnMut(Option<&T>, &Halves<T>) + 'static,
+    {
+        let first = SafeInner::new(MemoryMode::NoMemory, None);
+        let second = SafeInner::new(MemoryMode::NoMemory, None);
+        let (first_pegged, mut first_peg) = Pegged::new_pair(first.clone());
+        let (second_pegged, mut second_peg) = Pegged::new_pair(second.clone());
+        let halves = Halves(first_pegged, second_pegged);
+        let sub = self.internal_subscribe(move |t| f(t, &halves));
+        first_peg.add_related(sub.clone());
+        second_peg.add_related(sub);
+        (
+            Stream {
+                peg: first_peg,
+                inner: first,
+            },
+            Stream {
+                peg: second_peg,
+                inner: second,
+            },
+        )
+    }
+
+    /// Prepend a start value to the stream. The result is a memory stream.
+    ///
+    /// ```
+    /// let sink = xi::Stream::sink();
+    ///
+    /// sink.update(0); // lost
+    ///
+    /// let started = sink.stream().start_with(1);
+    ///
+    /// let coll = started.collect(); // receives 1 and following
+    ///
+    /// sink.update(2);
+    /// sink.end();
+    ///
+    /// assert_eq!(coll.wait(), vec![1, 2]);
+    /// ```
+    pub fn start_with(&self, start: T) -> Stream<T> {
+        let inner = SafeInner::new(MemoryMode::KeepUntilEnd, Some(start));
+        let inner_clone = inner.clone();
+        let peg = self.internal_subscribe(move |t| {
+            inner_clone.lock().update_borrowed(t);
+        });
+        Stream { peg, inner }
+    }
+
+    /// Take a number of events, then end the stream.
+    ///
+    /// ```
+    /// let sink = xi::Stream::sink();
+    ///
+    /// let take2 = sink.stream().take(2);
+    ///
+    /// let coll = take2.collect();
+    ///
+    /// sink.update(0);
+    /// sink.update(1); // take2 ends here
+    /// sink.update(2);
+    ///
+    /// assert_eq!(coll.wait(), vec![0, 1]);
+    /// ```
+    pub fn take(&self, amount: usize) -> Stream<T> {
+        let mut todo = amount + 1;
+        self.take_while(move |_| {
+            todo = todo.saturating_sub(1);
+            todo > 0
+        })
+    }
+
+    /// Emit the final `n` events when the stream ends.
+    ///
+    /// Holds on to at most `n` events, and on the end emits them in order, followed by the
+    /// end. With an `n` of 0, the stream just ends with the source.
+    ///
+    /// ```
+    /// let sink = xi::Stream::sink();
+    ///
+    /// let tail = sink.stream().take_last(2);
+    ///
+    /// let coll = tail.collect();
+    ///
+    /// sink.update(0);
+    /// sink.update(1);
+    /// sink.update(2);
+    /// sink.end();
+    ///
+    /// assert_eq!(coll.wait(), vec![1, 2]);
+    /// ```
+    pub fn take_last(&self, n: usize) -> Stream<T>
+    where
+        T: Clone,
+    {
+        let inner = SafeInner::new(MemoryMode::NoMemory, None);
+        let inner_clone = inner.clone();
+        let mut tail = VecDeque::new();
+        let peg = self.internal_subscribe(move |t| {
+            if let Some(t) = t {
+                if n == 0 {
+                    return;
+                }
+                if tail.len() == n {
+                    tail.pop_front();
+                }
+                tail.push_back(t.clone());
+            } else {
+                let mut lock = inner_clone.lock();
+                for t in tail.drain(..) {
+                    lock.update_owned(Some(t));
+                }
+                lock.update_owned(None);
+            }
+        });
+        Stream { peg, inner }
+    }
+
+    /// Forward events until another stream emits, or ends.
+    ///
+    /// The first event of `notifier` ends the stream, whatever its value. This suits
+    /// lifetimes, where the notifier fires when whatever the stream is for goes away.
+    ///
+    /// ```
+    /// use xi::Stream;
+    ///
+    /// let sink = Stream::sink();
+    /// let destroyed = Stream::sink();
+    ///
+    /// let alive = sink.stream().take_until(&destroyed.stream());
+    ///
+    /// let coll = alive.collect();
+    ///
+    /// sink.update(0);
+    /// sink.update(1);
+    /// destroyed.update(());
+    /// sink.update(2); // collector never sees this value
+    ///
+    /// assert_eq!(coll.wait(), [0, 1]);
+    /// ```
+    pub fn take_until<U>(&self, notifier: &Stream<U>) -> Stream<T> {
+        let inner = SafeInner::new(MemoryMode::NoMemory, None);
+        // the subscriptions are held by the peg of the resulting stream, and let go of
+        // once it has ended.
+        let (pegged, peg) = Pegged::new_pair(vec![]);
+        let pegged = Arc::new(pegged);
+        let inner_clone1 = inner.clone();
+        let pegged_clone1 = pegged.clone();
+        let peg1 = notifier.internal_subscribe(move |_| {
+            let mut lock = inner_clone1.lock();
+            if lock.is_ended() {
+                return;
+            }
+            lock.update_borrowed(None);
+            drop(lock);
+            release_deferred(&pegged_clone1);
+        });
+        let inner_clone2 = inner.clone();
+        let pegged_clone2 = pegged.clone();
+        let peg2 = self.internal_subscribe(move |t| {
+            let mut lock = inner_clone2.lock();
+            if lock.is_ended() {
+                return;
+            }
+            lock.update_borrowed(t);
+            drop(lock);
+            if t.is_none() {
+                release_deferred(&pegged_clone2);
+            }
+        });
+        hold_subs(&pegged, &inner, vec![peg1, peg2]);
+        Stream { peg, inner }
+    }
+
+    /// Take events from the stream until some other stream ends.
+    ///
+    /// This is the same as [`end_when()`](struct.Stream.html#method.end_when), named from the
+    /// perspective of the caller: keep emitting until the lifecycle of `other` is over.
+    /// Events of the other stream are ignored, only its end matters. To end on the first
+    /// event of the other stream, see [`take_until()`](struct.Stream.html#method.take_until).
+    ///
+    /// ```
+    /// use xi::Stream;
+    ///
+    /// let sink1 = Stream::sink();
+    /// let sink2 = Stream::sink();
+    ///
+    /// let taken = sink1.stream().take_until_end_of(&sink2.stream());
+    ///
+    /// let coll = taken.collect();
+    ///
+    /// sink1.update(0);
+    /// sink2.update("not an end");
+    /// sink1.update(1);
+    /// sink2.end();
+    /// sink1.update(2); // collector never sees this value
+    ///
+    /// assert_eq!(coll.wait(), [0, 1]);
+    /// ```
+    pub fn take_until_end_of<U>(&self, other: &Stream<U>) -> Stream<T> {
+        self.end_when(other)
+    }
+
+    /// Take events from the stream as long as a condition holds true.
+    ///
+    /// ```
+    /// let sink = xi::Stream::sink();
+    ///
+    /// // take events as long as they are even
+    /// let take = sink.stream().take_while(|v| *v % 2 == 0);
+    ///
+    /// let coll = take.collect();
+    ///
+    /// sink.update(0);
+    /// sink.update(2);
+    /// sink.update(3); // take ends here
+    /// sink.update(4);
+    ///
+    /// assert_eq!(coll.wait(), vec![0, 2]);
+    /// ```
+    pub fn take_while<F>(&self, mut f: F) -> Stream<T>
+    where
+        F: FnMut(&T) -> bool + Send + 'static,
+    {
+        let inner = SafeInner::new(MemoryMode::NoMemory, None);
+        let inner_clone = inner.clone();
+        let peg = self.internal_subscribe(move |t| {
+            if let Some(t) = t {
+                if f(t) {
+                    inner_clone.lock().update_borrowed(Some(t));
+                } else {
+                    inner_clone.lock().update_borrowed(None);
+                }
+            } else {
+                inner_clone.lock().update_borrowed(t);
+            }
+        });
+        Stream { peg, inner }
+    }
+
+    /// Call a function for each event, and pass the event on unchanged.
+    ///
+    /// Unlike [`subscribe()`](struct.Stream.html#method.subscribe) this sits inline in a
+    /// chain of operations, which is handy for logging or metrics, like
+    /// [`Iterator::inspect`]. Events are passed on by reference, so there's no `Clone`
+    /// requirement. See [`tap_complete()`](struct.Stream.html#method.tap_complete) to observe
+    /// the end, and [`tap_events()`](struct.Stream.html#method.tap_events) for both.
+    ///
+    /// ```
+    /// use std::sync::{Arc, Mutex};
+    ///
+    /// let sink = xi::Stream::sink();
+    ///
+    /// let seen = Arc::new(Mutex::new(vec![]));
+    /// let seen_clone = seen.clone();
+    ///
+    /// let doubled = sink.stream()
+    ///     .tap(move |v| seen_clone.lock().unwrap().push(*v))
+    ///     .map(|v| v * 2);
+    ///
+    /// let coll = doubled.collect();
+    ///
+    /// sink.update(1);
+    /// sink.update(2);
+    /// sink.end();
+    ///
+    /// assert_eq!(coll.wait(), vec![2, 4]);
+    /// assert_eq!(*seen.lock().unwrap(), vec![1, 2]);
+    /// ```
+    pub fn tap<F>(&self, mut f: F) -> Stream<T>
+    where
+        F: FnMut(&T) + Send + 'static,
+    {
+        let inner = SafeInner::new(MemoryMode::NoMemory, None);
+        let inner_clone = inner.clone();
+        let peg = self.internal_subscribe(move |t| {
+            if let Some(t) = t {
+                f(t);
+            }
+            inner_clone.lock().update_borrowed(t);
+        });
+        Stream { peg, inner }
+    }
+
+    /// Call a function when the stream ends, and pass all events on unchanged.
+    ///
+    /// The function is called before the end is passed on.
+    ///
+    /// ```
+    /// use std::sync::atomic::{AtomicBool, Ordering};
+    /// use std::sync::Arc;
+    ///
+    /// let sink = xi::Stream::sink();
+    ///
+    /// let ended = Arc::new(AtomicBool::new(false));
+    /// let ended_clone = ended.clone();
+    ///
+    /// let stream = sink.stream().tap_complete(move || ended_clone.store(true, Ordering::SeqCst));
+    ///
+    /// let coll = stream.collect();
+    ///
+    /// sink.update(1);
+    /// assert!(!ended.load(Ordering::SeqCst));
+    ///
+    /// sink.end();
+    /// assert!(ended.load(Ordering::SeqCst));
+    ///
+    /// assert_eq!(coll.wait(), vec![1]);
+    /// ```
+    pub fn tap_complete<F>(&self, mut f: F) -> Stream<T>
+    where
+        F: FnMut() + Send + 'static,
+    {
+        let inner = SafeInner::new(MemoryMode::NoMemory, None);
+        let inner_clone = inner.clone();
+        let peg = self.internal_subscribe(move |t| {
+            if t.is_none() {
+                f();
+            }
+            inner_clone.lock().update_borrowed(t);
+        });
+        Stream { peg, inner }
+    }
+
+    /// Call a function for each event, including the end, and pass the event on unchanged.
+    ///
+    /// Combines [`tap()`](struct.Stream.html#method.tap) and
+    /// [`tap_complete()`](struct.Stream.html#method.tap_complete), with the end as `None`,
+    /// like a subscriber sees it.
+    ///
+    /// ```
+    /// use std::sync::{Arc, Mutex};
+    ///
+    /// // not Clone
+    /// #[derive(Debug)]
+    /// struct Reading(u32);
+    ///
+    /// let sink = xi::Stream::sink();
+    ///
+    /// let log = Arc::new(Mutex::new(vec![]));
+    /// let log_clone = log.clone();
+    ///
+    /// let values = sink.stream()
+    ///     .tap_events(move |r: Option<&Reading>| log_clone.lock().unwrap().push(format!("{:?}", r)))
+    ///     .map(|r| r.0);
+    ///
+    /// let coll = values.collect();
+    ///
+    /// sink.update(Reading(1));
+    /// sink.end();
+    ///
+    /// assert_eq!(coll.wait(), vec![1]);
+    /// assert_eq!(*log.lock().unwrap(), vec!["Some(Reading(1))", "None"]);
+    /// ```
+    pub fn tap_events<F>(&self, mut f: F) -> Stream<T>
+    where
+        F: FnMut(Option<&T>) + Send + 'static,
+    {
+        let inner = SafeInner::new(MemoryMode::NoMemory, None);
+        let inner_clone = inner.clone();
+        let peg = self.internal_subscribe(move |t| {
+            f(t);
+            inner_clone.lock().update_borrowed(t);
+        });
+        Stream { peg, inner }
+    }
+
+    /// Call a function for each event, and pass the event on unchanged.
+    ///
+    /// This is the same as [`tap()`](struct.Stream.html#method.tap), named after
+    /// [`Iterator::inspect`].
+    ///
+    /// ```
+    /// use std::sync::{Arc, Mutex};
+    ///
+    /// let sink = xi::Stream::sink();
+    ///
+    /// let seen = Arc::new(Mutex::new(vec![]));
+    /// let seen_clone = seen.clone();
+    ///
+    /// let coll = sink.stream().inspect(move |v| seen_clone.lock().unwrap().push(*v)).collect();
+    ///
+    /// sink.update(1);
+    /// sink.end();
+    ///
+    /// assert_eq!(coll.wait(), vec![1]);
+    /// assert_eq!(*seen.lock().unwrap(), vec![1]);
+    /// ```
+    pub fn inspect<F>(&self, f: F) -> Stream<T>
+    where
+        F: FnMut(&T) + Send + 'static,
+    {
+        self.tap(f)
+    }
+
+    /// Call a function for each event, including the end, and pass the event on unchanged.
+    ///
+    /// This is the same as [`tap_events()`](struct.Stream.html#method.tap_events), to go
+    /// with [`inspect()`](struct.Stream.html#method.inspect).
+    ///
+    /// ```
+    /// use std::sync::{Arc, Mutex};
+    ///
+    /// let sink = xi::Stream::sink();
+    ///
+    /// let seen = Arc::new(Mutex::new(vec![]));
+    /// let seen_clone = seen.clone();
+    ///
+    /// let coll = sink.stream()
+    ///     .inspect_events(move |v: Option<&u32>| seen_clone.lock().unwrap().push(v.copied()))
+    ///     .collect();
+    ///
+    /// sink.update(1);
+    /// sink.end();
+    ///
+    /// assert_eq!(coll.wait(), vec![1]);
+    /// assert_eq!(*seen.lock().unwrap(), vec![Some(1), None]);
+    /// ```
+    pub fn inspect_events<F>(&self, f: F) -> Stream<T>
+    where
+        F: FnMut(Option<&T>) + Send + 'static,
+    {
+        self.tap_events(f)
+    }
+
+    /// Forward the events of this stream, and when it ends, those of the stream returned
+    /// by `f`.
+    ///
+    /// `f` is called once, when this stream ends. The resulting stream ends with the
+    /// stream returned by `f`. The instance method version of
+    /// [`Stream::concat()`](struct.Stream.html#method.concat), which chains naturally.
+    ///
+    /// ```
+    /// use xi::Stream;
+    ///
+    /// let sink1 = Stream::sink();
+    /// let sink2 = Stream::sink();
+    ///
+    /// let s2 = sink2.stream();
+    /// let both = sink1.stream().then_concat(move || s2.clone());
+    ///
+    /// let coll = both.collect();
+    ///
+    /// sink2.update(0); // not forwarded yet
+    /// sink1.update(1);
+    /// sink1.end();
+    /// sink2.update(2);
+    /// sink2.end();
+    ///
+    /// assert_eq!(coll.wait(), vec![1, 2]);
+    /// ```
+    pub fn then_concat<F>(&self, mut f: F) -> Stream<T>
+    where
+        F: FnMut() -> Stream<T> + Send + 'static,
+    {
+        let inner = SafeInner::new(MemoryMode::NoMemory, None);
+        // both subscriptions are held by the peg of the resulting stream.
+        let (pegged, peg) = Pegged::new_pair(vec![]);
+        let mut first = Some(self.clone());
+        let mut done = false;
+        let next: NextStream<T> = Arc::new(Mutex::new(move || {
+            if first.is_some() {
+                first.take()
+            } else if !done {
+                done = true;
+                Some(f())
+            } else {
+                None
+            }
+        }));
+        Stream::concat_next(next, inner.clone(), Arc::new(pegged));
+        Stream { peg, inner }
+    }
+
+    /// Stalls calling thread until the stream ends.
+    ///
+    /// ```
+    /// let sink = xi::Stream::sink();
+    /// let stream = sink.stream();
+    ///
+    /// std::thread::spawn(move || {
+    ///   sink.update(0);
+    ///   sink.update(1);
+    ///   sink.update(2);
+    ///   sink.end(); // this releases the wait
+    /// });
+    ///
+    /// stream.wait(); // wait for other thread
+    /// ```
+    #[allow(clippy::mutex_atomic)]
+    pub fn wait(&self) {
+        let pair = Arc::new((Mutex::new(false), Condvar::new()));
+        let pair2 = pair.clone();
+        let _sub = self.internal_subscribe(move |t| {
+            if t.is_none() {
+                let mut lock = pair2.0.lock().unwrap();
+                *lock = true;
+                pair2.1.notify_all();
+            }
+        });
+        let mut lock = pair.0.lock().unwrap();
+        while !*lock {
+            lock = pair.1.wait(lock).unwrap();
+        }
+    }
+
+    /// Stalls calling thread until `n` events have been dispatched, or the stream ends.
+    ///
+    /// Returns the number of events seen, which is less than `n` if the stream ended first.
+    /// Remembered values are delivered when subscribing, and count. The stream is only
+    /// listened to while waiting.
+    ///
+    /// ```
+    /// use xi::Stream;
+    ///
+    /// let numbers = Stream::from_iter(0..5);
+    ///
+    /// assert_eq!(numbers.wait_n(3), 3);
+    ///
+    /// // ends before 10 events
+    /// assert_eq!(numbers.wait_n(10), 5);
+    /// ```
+    pub fn wait_n(&self, n: usize) -> usize {
+        // events seen, and whether the stream ended.
+        let pair = Arc::new((Mutex::new((0, false)), Condvar::new()));
+        let pair2 = pair.clone();
+        let _sub = self.internal_subscribe(move |t| {
+            let mut lock = pair2.0.lock().unwrap();
+            if t.is_some() {
+                lock.0 += 1;
+            } else {
+                lock.1 = true;
+            }
+            pair2.1.notify_all();
+        });
+        let mut lock = pair.0.lock().unwrap();
+        while lock.0 < n && !lock.1 {
+            lock = pair.1.wait(lock).unwrap();
+        }
+        lock.0.min(n)
+    }
+
+    /// Stalls calling thread until an event matches a predicate, and returns it. Returns
+    /// `None` if the stream ends first.
+    ///
+    /// Remembered values are delivered when subscribing, and are tested like any other
+    /// event. The stream is only listened to while waiting.
+    ///
+    /// ```
+    /// use xi::Stream;
+    ///
+    /// let numbers = Stream::from_iter(1..10);
+    ///
+    /// assert_eq!(numbers.wait_until(|v| v % 4 == 0), Some(4));
+    ///
+    /// // ends without a match
+    /// assert_eq!(numbers.wait_until(|v| *v > 10), None);
+    /// ```
+    #[allow(clippy::type_complexity)]
+    pub fn wait_until<F>(&self, mut f: F) -> Option<T>
+    where
+        T: Clone,
+        F: FnMut(&T) -> bool + Send + 'static,
+    {
+        // the matching event, or None for the end, once either arrives.
+        let pair: Arc<(Mutex<Option<Option<T>>>, Condvar)> =
+            Arc::new((Mutex::new(None), Condvar::new()));
+        let pair2 = pair.clone();
+        let _sub = self.internal_subscribe(move |t| {
+            let mut lock = pair2.0.lock().unwrap();
+            if lock.is_some() {
+                return;
+            }
+            match t {
+                Some(t) if !f(t) => (),
+                _ => {
+                    *lock = Some(t.cloned());
+                    pair2.1.notify_all();
+                }
+            }
+        });
+        let mut lock = pair.0.lock().unwrap();
+        loop {
+            if let Some(found) = lock.take() {
+                return found;
+            }
+            lock = pair.1.wait(lock).unwrap();
+        }
+    }
+
+    /// Emit sliding windows of `size` events, advancing `step` events between windows.
+    ///
+    /// Each window is emitted once it is full, as a vec of copies. With a `step` smaller
+    /// than `size` windows overlap, and with a larger `step` events between windows are
+    /// skipped. A partially filled window is not emitted on end. Panics if `size` or `step`
+    /// is 0.
+    ///
+    /// ```
+    /// let sink = xi::Stream::sink();
+    ///
+    /// // moving average over 3 events
+    /// let averages = sink.stream()
+    ///     .window_count(3, 1)
+    ///     .map(|w| w.iter().sum::<f32>() / 3.0);
+    ///
+    /// let coll = averages.collect();
+    ///
+    /// for v in &[1.0, 2.0, 3.0, 4.0, 8.0] {
+    ///     sink.update(*v);
+    /// }
+    /// sink.end();
+    ///
+    /// assert_eq!(coll.wait(), vec![2.0, 3.0, 5.0]);
+    /// ```
+    pub fn window_count(&self, size: usize, step: usize) -> Stream<Vec<T>>
+    where
+        T: Clone,
+    {
+        assert!(size > 0, "window_count() with a size of 0");
+        assert!(step > 0, "window_count() with a step of 0");
+        let inner = SafeInner::new(MemoryMode::NoMemory, None);
+        let inner_clone = inner.clone();
+        let mut window = VecDeque::new();
+        // events to skip before the next window, when step is larger than size.
+        let mut skip = 0;
+        let peg = self.internal_subscribe(move |t| {
+            if let Some(t) = t {
+                if skip > 0 {
+                    skip -= 1;
+                    return;
+                }
+                window.push_back(t.clone());
+                if window.len() == size {
+                    let v: Vec<T> = window.iter().cloned().collect();
+                    if step >= size {
+                        window.clear();
+                        skip = step - size;
+                    } else {
+                        window.drain(..step);
+                    }
+                    inner_clone.lock().update_owned(Some(v));
+                }
+            } else {
+                window.clear();
+                inner_clone.lock().update_owned(None);
+            }
+        });
+        Stream { peg, inner }
+    }
+
+    /// Use events from a fallback stream if this stream ends without emitting anything.
+    ///
+    /// If this stream emits at least one event, the fallback is ignored. If it ends without
+    /// emitting, the resulting stream starts listening to the fallback and emits its events,
+    /// ending when the fallback ends.
+    ///
+    /// ```
+    /// use xi::Stream;
+    ///
+    /// let sink1 = Stream::sink();
+    /// let sink2 = Stream::sink();
+    ///
+    /// let defaulted = sink1.stream().with_default_if_empty(&sink2.stream());
+    ///
+    /// let coll = defaulted.collect();
+    ///
+    /// sink2.update(0); // lost, sink1 hasn't ended
+    /// sink1.end();     // switch to sink2
+    /// sink2.update(1);
+    /// sink2.end();
+    ///
+    /// assert_eq!(coll.wait(), vec![1]);
+    /// ```
+    pub fn with_default_if_empty(&self, fallback: &Stream<T>) -> Stream<T> {
+        let inner = SafeInner::new(MemoryMode::NoMemory, None);
+        let inner_clone = inner.clone();
+        let fallback = fallback.clone();
+        let mut emitted = false;
+        // the subscriptions are held by the peg of the resulting stream.
+        let (pegged, peg) = Pegged::new_pair(vec![]);
+        let pegged = Arc::new(pegged);
+        let pegged_clone = pegged.clone();
+        let sub = self.internal_subscribe(move |t| {
+            if let Some(t) = t {
+                emitted = true;
+                inner_clone.lock().update_borrowed(Some(t));
+            } else if emitted {
+                inner_clone.lock().update_borrowed(None);
+            } else {
+                // the fallback may share a source with this stream, which is locked until
+                // the end has propagated.
+                let inner = inner_clone.clone();
+                let fallback = fallback.clone();
+                let pegged = pegged_clone.clone();
+                defer(move || {
+                    let inner_clone = inner.clone();
+                    let sub = fallback.internal_subscribe(move |f| {
+                        inner_clone.lock().update_borrowed(f);
+                    });
+                    hold_subs(&pegged, &inner, vec![sub]);
+                });
+            }
+        });
+        hold_subs(&pegged, &inner, vec![sub]);
+        Stream { peg, inner }
+    }
+
+    /// Wrap every event in `Some`.
+    ///
+    /// Useful to merge with a stream of `None` where an absent value is an event itself.
+    /// See [`unwrap_option()`](struct.Stream.html#method.unwrap_option) for the reverse.
+    ///
+    /// ```
+    /// let sink = xi::Stream::sink();
+    ///
+    /// let wrapped = sink.stream().wrap_in_option();
+    ///
+    /// let coll = wrapped.collect();
+    ///
+    /// sink.update(1);
+    /// sink.end();
+    ///
+    /// assert_eq!(coll.wait(), vec![Some(1)]);
+    /// ```
+    pub fn wrap_in_option(&self) -> Stream<Option<T>>
+    where
+        T: Clone,
+    {
+        self.map(|t| Some(t.clone()))
+    }
+
+    /// Pair up the events of this and another stream in lockstep.
+    ///
+    /// The first event of this stream is paired with the first event of the other, the
+    /// second with the second and so on. Events waiting for their counterpart are queued,
+    /// and so the queues are bounded by how far one stream is ahead of the other.
+    ///
+    /// The resulting stream ends when a stream has ended and has no queued events left,
+    /// since nothing more can be paired. Queued events of the other stream are dropped.
+    ///
+    /// This is [`zip2()`](struct.Stream.html#method.zip2) as a method. For more streams, see
+    /// [`zip3()`](struct.Stream.html#method.zip3) up to `zip8()`.
+    ///
+    /// ```
+    /// use xi::Stream;
+    ///
+    /// let sink1 = Stream::sink();
+    /// let sink2 = Stream::sink();
+    ///
+    /// let zipped = sink1.stream().zip(&sink2.stream());
+    ///
+    /// let coll = zipped.collect();
+    ///
+    /// sink1.update(0);
+    /// sink1.update(1);
+    /// sink2.update("foo"); // paired with 0
+    /// sink2.update("bar"); // paired with 1
+    /// sink2.update("baz"); // waiting for a counterpart
+    /// sink1.end();
+    ///
+    /// assert_eq!(coll.wait(), vec![(0, "foo"), (1, "bar")]);
+    /// ```
+    pub fn zip<U>(&self, other: &Stream<U>) -> Stream<(T, U)>
+    where
+        T: Clone,
+        U: Clone,
+    {
+        Stream::zip2(self, other)
+    }
+
+    /// Combine the events of this and another stream in lockstep using a function.
+    ///
+    /// Pairs up events like [`zip()`](struct.Stream.html#method.zip), and ends the same
+    /// way.
+    ///
+    /// ```
+    /// use xi::Stream;
+    ///
+    /// let sink1 = Stream::sink();
+    /// let sink2 = Stream::sink();
+    ///
+    /// let summed = sink1.stream().zip_with(&sink2.stream(), |a, b| a + b);
+    ///
+    /// let coll = summed.collect();
+    ///
+    /// sink1.update(1);
+    /// sink1.update(2);
+    /// sink1.end();         // 2 is still waiting for a counterpart
+    /// sink2.update(10);
+    /// sink2.update(20);    // the resulting stream ends after this
+    /// sink2.update(30);
+    ///
+    /// assert_eq!(coll.wait(), vec![11, 22]);
+    /// ```
+    pub fn zip_with<U, V, F>(&self, other: &Stream<U>, f: F) -> Stream<V>
+    where
+        T: Clone,
+        U: Clone,
+        V: 'static,
+        F: FnMut(&T, &U) -> V + Send + 'static,
+    {
+        let inner = SafeInner::new(MemoryMode::NoMemory, None);
+        let f = Arc::new(Mutex::new(f));
+        // queued events of this and the other stream, whether they have ended, and whether
+        // the zip is done.
+        #[allow(clippy::type_complexity)]
+        let state: Arc<Mutex<(VecDeque<T>, VecDeque<U>, bool, bool, bool)>> = Arc::new(Mutex::new(
+            (VecDeque::new(), VecDeque::new(), false, false, false),
+        ));
+        // the subscriptions are held by the peg of the resulting stream, and released once
+        // nothing more can be paired.
+        let (pegged, peg) = Pegged::new_pair(vec![]);
+        let pegged = Arc::new(pegged);
+        let peg1 = {
+            let inner_clone = inner.clone();
+            let f = f.clone();
+            let state = state.clone();
+            let pegged = pegged.clone();
+            self.internal_subscribe(move |t| {
+                let (pair, done) = {
+                    let mut lock = state.lock().unwrap();
+                    if lock.4 {
+                        return;
+                    }
+                    let (pair, done) = if let Some(t) = t {
+                        let pair = lock.1.pop_front().map(|u| (t.clone(), u));
+                        if pair.is_none() {
+                            lock.0.push_back(t.clone());
+                        }
+                        (pair, lock.3 && lock.1.is_empty())
+                    } else {
+                        lock.2 = true;
+                        (None, lock.0.is_empty())
+                    };
+                    if done {
+                        lock.0.clear();
+                        lock.1.clear();
+                        lock.4 = true;
+                    }
+                    (pair, done)
+                };
+                let mut lock = inner_clone.lock();
+                if let Some((t, u)) = pair {
+                    let v = (f.lock().unwrap())(&t, &u);
+                    lock.update_owned(Some(v));
+                }
+                if done {
+                    lock.update_owned(None);
+                    release_deferred(&pegged);
+                }
+            })
+        };
+        let peg2 = {
+            let inner_clone = inner.clone();
+            let pegged = pegged.clone();
+            other.internal_subscribe(move |u| {
+                let (pair, done) = {
+                    let mut lock = state.lock().unwrap();
+                    if lock.4 {
+                        return;
+                    }
+                    let (pair, done) = if let Some(u) = u {
+                        let pair = lock.0.pop_front().map(|t| (t, u.clone()));
+                        if pair.is_none() {
+                            lock.1.push_back(u.clone());
+                        }
+                        (pair, lock.2 && lock.0.is_empty())
+                    } else {
+                        lock.3 = true;
+                        (None, lock.1.is_empty())
+                    };
+                    if done {
+                        lock.0.clear();
+                        lock.1.clear();
+                        lock.4 = true;
+                    }
+                    (pair, done)
+                };
+                let mut lock = inner_clone.lock();
+                if let Some((t, u)) = pair {
+                    let v = (f.lock().unwrap())(&t, &u);
+                    lock.update_owned(Some(v));
+                }
+                if done {
+                    lock.update_owned(None);
+                    release_deferred(&pegged);
+                }
+            })
+        };
+        hold_subs(&pegged, &inner, vec![peg1, peg2]);
+        Stream { peg, inner }
+    }
+
+    /// Transform events with their zero based index in the stream.
+    ///
+    /// ```
+    /// let sink = xi::Stream::sink();
+    ///
+    /// let indexed = sink.stream().zip_with_index_and_value(|i, v| format!("{}: {}", i, v));
+    ///
+    /// let coll = indexed.collect();
+    ///
+    /// sink.update("a");
+    /// sink.update("b");
+    /// sink.end();
+    ///
+    /// assert_eq!(coll.wait(), vec!["0: a".to_string(), "1: b".to_string()]);
+    /// ```
+    pub fn zip_with_index_and_value<U, F>(&self, mut f: F) -> Stream<U>
+    where
+        U: 'static,
+        F: FnMut(usize, &T) -> U + Send + 'static,
+    {
+        let mut index = 0;
+        self.map(move |t| {
+            let u = f(index, t);
+            index += 1;
+            u
+        })
+    }
+}
+
+impl<T> Stream<Stream<T>> {
+    //
+
+    /// Flatten out a stream of streams, sequentially.
+    ///
+    /// For each new stream, unsubscribe from the previous, and subscribe to the new. The new
+    /// stream "interrupts" the previous stream.
+    ///
+    /// ```
+    /// use xi::{Stream, Sink};
+    ///
+    /// let sink1: Sink<Stream<u32>> = Stream::sink();
+    /// let sink2: Sink<u32> = Stream::sink();
+    /// let sink3: Sink<u32> = Stream::sink();
+    ///
+    /// let flat = sink1.stream().flatten();
+    ///
+    /// let coll = flat.collect();
+    ///
+    /// sink2.update(0); // lost
+    ///
+    /// sink1.update(sink2.stream());
+    /// sink2.update(1);
+    /// sink2.update(2);
+    /// sink2.end(); // does not end sink1
+    ///
+    /// sink3.update(10); // lost
+    ///
+    /// sink1.update(sink3.stream());
+    /// sink3.update(11);
+    ///
+    /// sink1.end();
+    ///
+    /// assert_eq!(coll.wait(), vec![1, 2, 11]);
+    /// ```
+    pub fn flatten(&self) -> Stream<T> {
+        self.flat_map(Stream::clone)
+    }
+
+    /// Flatten out a stream of streams, concurrently.
+    ///
+    /// For each new stream, keep the previous, and subscribe to the new.
+    ///
+    /// ```
+    /// use xi::{Stream, Sink};
+    ///
+    /// let sink1: Sink<Stream<u32>> = Stream::sink();
+    /// let sink2: Sink<u32> = Stream::sink();
+    /// let sink3: Sink<u32> = Stream::sink();
+    ///
+    /// let flat = sink1.stream().flatten_concurrently();
+    ///
+    /// let coll = flat.collect();
+    ///
+    /// sink2.update(0); // lost
+    ///
+    /// sink1.update(sink2.stream());
+    /// sink2.update(1);
+    /// sink2.update(2);
+    ///
+    /// sink3.update(10); // lost
+    ///
+    /// sink1.update(sink3.stream());
+    /// sink3.update(11);
+    /// sink2.update(3);
+    /// sink3.update(12);
+    ///
+    /// sink1.end();
+    ///
+    /// assert_eq!(coll.wait(), vec![1, 2, 11, 3, 12]);
+    /// ```
+    pub fn flatten_concurrently(&self) -> Stream<T> {
+        self.flat_map_concurrent(Stream::clone)
+    }
+
+    /// Flatten out a stream of streams, concurrently up to a limit.
+    ///
+    /// At most `n` inner streams are listened to at the same time. When a new stream
+    /// arrives and `n` streams are already active, the new stream waits until one of the
+    /// active streams ends. Events of a waiting stream are lost until it becomes active.
+    ///
+    /// Panics if `n` is 0.
+    ///
+    /// ```
+    /// use xi::{Stream, Sink};
+    ///
+    /// let sink1: Sink<Stream<u32>> = Stream::sink();
+    /// let sink2: Sink<u32> = Stream::sink();
+    /// let sink3: Sink<u32> = Stream::sink();
+    ///
+    /// let flat = sink1.stream().flatten_with_max_concurrency(1);
+    ///
+    /// let coll = flat.collect();
+    ///
+    /// sink1.update(sink2.stream());
+    /// sink1.update(sink3.stream()); // waits for sink2 to end
+    ///
+    /// sink2.update(1);
+    /// sink3.update(10); // lost
+    /// sink2.end();      // sink3 becomes active
+    /// sink3.update(11);
+    ///
+    /// sink1.end();
+    ///
+    /// assert_eq!(coll.wait(), vec![1, 11]);
+    /// ```
+    pub fn flatten_with_max_concurrency(&self, n: usize) -> Stream<T> {
+        assert!(n > 0, "flatten_with_max_concurrency() with a limit of 0");
+        let inner = SafeInner::new(MemoryMode::NoMemory, None);
+        let inner_clone = inner.clone();
+        // (active, waiting)
+        let state = Arc::new(Mutex::new((0, VecDeque::new())));
+        // the subscriptions are held by the peg of the resulting stream.
+        let (pegged, peg) = Pegged::new_pair(vec![]);
+        let pegged = Arc::new(pegged);
+        let pegged_clone = pegged.clone();
+        let sub = self.internal_subscribe(move |ts| {
+            if let Some(ts) = ts {
+                let start = {
+                    let mut lock = state.lock().unwrap();
+                    if lock.0 < n {
+                        lock.0 += 1;
+                        true
+                    } else {
+                        lock.1.push_back(ts.clone());
+                        false
+                    }
+                };
+                if start {
+                    let (inner, state) = (inner_clone.clone(), state.clone());
+                    Self::subscribe_limited(ts, inner, state, pegged_clone.clone());
+                }
+            } else {
+                state.lock().unwrap().1.clear();
+                inner_clone.lock().update_borrowed(None);
+                release_deferred(&pegged_clone);
+            }
+        });
+        hold_subs(&pegged, &inner, vec![sub]);
+        Stream { peg, inner }
+    }
+
+    /// Flatten out a stream of streams that signal their end with a value, rather than by
+    /// ending.
+    ///
+    /// Like [`flatten()`](struct.Stream.html#method.flatten), each new stream "interrupts"
+    /// the previous one. An inner stream emitting `tombstone` is treated as ended: the
+    /// tombstone is not passed on, and the inner stream is no longer listened to. As for
+    /// an inner stream ending, the resulting stream carries on until this stream ends.
+    ///
+    /// ```
+    /// use xi::{Stream, Sink};
+    ///
+    /// let sink1: Sink<Stream<&str>> = Stream::sink();
+    /// let sink2: Sink<&str> = Stream::sink();
+    ///
+    /// let flat = sink1.stream().flatten_with_tombstone("EOF");
+    ///
+    /// let coll = flat.collect();
+    ///
+    /// sink1.update(sink2.stream());
+    /// sink2.update("a");
+    /// sink2.update("EOF");
+    /// sink2.update("b"); // lost
+    ///
+    /// sink1.update(Stream::from_iter(vec!["c", "EOF", "d"]));
+    ///
+    /// sink1.end();
+    ///
+    /// assert_eq!(coll.wait(), vec!["a", "c"]);
+    /// ```
+    #[allow(clippy::type_complexity)]
+    pub fn flatten_with_tombstone(&self, tombstone: T) -> Stream<T>
+    where
+        T: PartialEq,
+    {
+        let inner = SafeInner::new(MemoryMode::NoMemory, None);
+        let inner_clone = inner.clone();
+        let tombstone = Arc::new(tombstone);
+        // the subscription to the current inner stream, and a count of inner streams.
+        let slot: Arc<Mutex<(u64, Option<Peg>)>> = Arc::new(Mutex::new((0, None)));
+        let peg = self.internal_subscribe(move |ts| {
+            if let Some(ts) = ts {
+                let gen = {
+                    let mut lock = slot.lock().unwrap();
+                    lock.0 += 1;
+                    lock.0
+                };
+                let closed = Arc::new(AtomicBool::new(false));
+                let ipeg = {
+                    let inner_clone = inner_clone.clone();
+                    let tombstone = tombstone.clone();
+                    let closed = closed.clone();
+                    let slot = Arc::downgrade(&slot);
+                    ts.internal_subscribe(move |tv| {
+                        if closed.load(Ordering::SeqCst) {
+                            return;
+                        }
+                        match tv {
+                            Some(tv) if *tv == *tombstone => {
+                                closed.store(true, Ordering::SeqCst);
+                                let slot = slot.clone();
+                                // can't drop the subscription while in its own listener.
+                                defer(move || {
+                                    if let Some(slot) = slot.upgrade() {
+                                        let peg = {
+                                            let mut lock = slot.lock().unwrap();
+                                            if lock.0 == gen {
+                                                lock.1.take()
+                                            } else {
+                                                None
+                                            }
+                                        };
+                                        drop(peg);
+                                    }
+                                });
+                            }
+                            Some(tv) => inner_clone.lock().update_borrowed(Some(tv)),
+                            None => {
+                                // inner stream end does nothing to outer
+                            }
+                        }
+                    })
+                };
+                // a remembered tombstone closes the stream while subscribing.
+                let ipeg = if closed.load(Ordering::SeqCst) {
+                    None
+                } else {
+                    Some(ipeg)
+                };
+                let prev = std::mem::replace(&mut slot.lock().unwrap().1, ipeg);
+                drop(prev);
+            } else {
+                let prev = slot.lock().unwrap().1.take();
+                drop(prev);
+                inner_clone.lock().update_borrowed(None);
+            }
+        });
+        Stream { peg, inner }
+    }
+
+    /// Internal for flatten_with_max_concurrency. Listens to the stream and starts the next
+    /// waiting one once it ends.
+    #[allow(clippy::type_complexity)]
+    fn subscribe_limited(
+        stream: &Stream<T>,
+        inner: SafeInner<T>,
+        state: Arc<Mutex<(usize, VecDeque<Stream<T>>)>>,
+        pegged: Arc<Pegged<Vec<Peg>>>,
+    ) {
+        let inner_clone = inner.clone();
+        let pegged_clone = pegged.clone();
+        let ipeg = stream.internal_subscribe(move |tv| {
+            if let Some(tv) = tv {
+                inner_clone.lock().update_borrowed(Some(tv));
+            } else {
+                let next = {
+                    let mut lock = state.lock().unwrap();
+                    let next = lock.1.pop_front();
+                    if next.is_none() {
+                        lock.0 -= 1;
+                    }
+                    next
+                };
+                if let Some(next) = next {
+                    // the next stream may share a source with this one, which is locked
+                    // until the end has propagated.
+                    let mut next = Some(next);
+                    let inner = inner_clone.clone();
+                    let state = state.clone();
+                    let pegged = pegged_clone.clone();
+                    defer(move || {
+                        if let Some(next) = next.take() {
+                            let (inner, state) = (inner.clone(), state.clone());
+                            Self::subscribe_limited(&next, inner, state, pegged.clone());
+                        }
+                    });
+                }
+            }
+        });
+        hold_subs(&pegged, &inner, vec![ipeg]);
+    }
+}
+
+impl<T> Stream<Vec<Stream<T>>> {
+    //
+
+    /// Merge all the streams of every emitted vec into one stream.
+    ///
+    /// Each vec of streams is subscribed to as it arrives, and all the streams are listened
+    /// to concurrently, like
+    /// [`flatten_concurrently()`](struct.Stream.html#method.flatten_concurrently) does for
+    /// single streams. The resulting stream ends once the outer stream and all the inner
+    /// streams have ended.
+    ///
+    /// ```
+    /// use xi::{Stream, Sink};
+    ///
+    /// let batches: Sink<Vec<Stream<u32>>> = Stream::sink();
+    /// let sink1 = Stream::sink();
+    /// let sink2 = Stream::sink();
+    ///
+    /// let merged = batches.stream().merge_all_from_inner();
+    ///
+    /// let coll = merged.collect();
+    ///
+    /// batches.update(vec![sink1.stream(), sink2.stream()]);
+    /// batches.end(); // inner streams are still listened to
+    ///
+    /// sink1.update(1);
+    /// sink2.update(2);
+    /// sink1.end();
+    /// sink2.end(); // all ended
+    ///
+    /// assert_eq!(coll.wait(), vec![1, 2]);
+    /// ```
+    pub fn merge_all_from_inner(&self) -> Stream<T> {
+        let inner = SafeInner::new(MemoryMode::NoMemory, None);
+        let inner_clone = inner.clone();
+        // the outer stream counts as one until it ends
+        let active = Arc::new(AtomicUsize::new(1));
+        let end_one = |active: &AtomicUsize, inner: &SafeInner<T>| {
+            if active.fetch_sub(1, Ordering::SeqCst) == 1 {
+                inner.lock().update_borrowed(None);
+            }
+        };
+        let peg = self.internal_subscribe(move |vs| {
+            if let Some(vs) = vs {
+                for stream in vs {
+                    active.fetch_add(1, Ordering::SeqCst);
+                    let inner_clone = inner_clone.clone();
+                    let active = active.clone();
+                    let ipeg = stream.internal_subscribe(move |t| {
+                        if t.is_some() {
+                            inner_clone.lock().update_borrowed(t);
+                        } else {
+                            end_one(&active, &inner_clone);
+                        }
+                    });
+                    ipeg.keep_mode(); // we drop ipeg, but keep listening
+                }
+            } else {
+                end_one(&active, &inner_clone);
+            }
+        });
+        Stream { peg, inner }
+    }
+}
+
+impl Stream<f64> {
+    //
+
+    /// Dedupe stream of floating point values, treating values within `epsilon` of the last
+    /// emitted value as the same.
+    ///
+    /// NaN is always treated as different, and so is always emitted, as is the value
+    /// following a NaN. See [`dedupe_with()`](struct.Stream.html#method.dedupe_with).
+    ///
+    /// ```
+    /// let sink = xi::Stream::sink();
+    ///
+    /// let deduped = sink.stream().dedupe_by_tolerance(0.1);
+    ///
+    /// let coll = deduped.collect();
+    ///
+    /// sink.update(1.0);
+    /// sink.update(1.05);
+    /// sink.update(1.2);
+    /// sink.end();
+    ///
+    /// assert_eq!(coll.wait(), vec![1.0, 1.2]);
+    /// ```
+    pub fn dedupe_by_tolerance(&self, epsilon: f64) -> Stream<f64> {
+        self.dedupe_with(move |a, b| (a - b).abs() <= epsilon)
+    }
+}
+
+impl Stream<bool> {
+    //
+
+    /// Invert a stream of flags.
+    ///
+    /// Like all boolean combinators, the result only emits on transitions and remembers
+    /// the current flag.
+    ///
+    /// ```
+    /// let sink = xi::Stream::sink();
+    ///
+    /// let inverted = sink.stream().not();
+    ///
+    /// let coll = inverted.collect();
+    ///
+    /// sink.update(true);
+    /// sink.update(true);
+    /// sink.update(false);
+    /// sink.end();
+    ///
+    /// assert_eq!(coll.wait(), vec![false, true]);
+    /// ```
+    pub fn not(&self) -> Stream<bool> {
+        self.map(|b| !b).dedupe().remember()
+    }
+
+    /// Combine this and another stream of flags into one that is `true` when both are.
+    ///
+    /// See [`all_of()`](struct.Stream.html#method.all_of).
+    ///
+    /// ```
+    /// let sink1 = xi::Stream::sink();
+    /// let sink2 = xi::Stream::sink();
+    ///
+    /// let both = sink1.stream().and(&sink2.stream());
+    ///
+    /// let coll = both.collect();
+    ///
+    /// sink1.update(true); // no value in sink2 yet
+    /// sink2.update(false);
+    /// sink2.update(true);
+    /// sink1.update(false);
+    /// sink1.end();
+    /// sink2.end();
+    ///
+    /// assert_eq!(coll.wait(), vec![false, true, false]);
+    /// ```
+    pub fn and(&self, other: &Stream<bool>) -> Stream<bool> {
+        Stream::all_of(vec![self.clone(), other.clone()])
+    }
+
+    /// Combine this and another stream of flags into one that is `true` when any is.
+    ///
+    /// See [`any_of()`](struct.Stream.html#method.any_of).
+    ///
+    /// ```
+    /// let sink1 = xi::Stream::sink();
+    /// let sink2 = xi::Stream::sink();
+    ///
+    /// let either = sink1.stream().or(&sink2.stream());
+    ///
+    /// let coll = either.collect();
+    ///
+    /// sink1.update(false);
+    /// sink2.update(false);
+    /// sink2.update(true);
+    /// sink1.update(true);
+    /// sink1.end();
+    /// sink2.end();
+    ///
+    /// assert_eq!(coll.wait(), vec![false, true]);
+    /// ```
+    pub fn or(&self, other: &Stream<bool>) -> Stream<bool> {
+        Stream::any_of(vec![self.clone(), other.clone()])
+    }
+
+    /// Combine a number of streams of flags into one that is `true` when all of them are.
+    ///
+    /// The resulting stream emits once all incoming streams have had an initial value,
+    /// and after that only when the combined flag changes. It has memory, so a late
+    /// subscriber receives the current flag. It ends when all incoming streams have ended.
+    ///
+    /// An empty vec gives a stream that is `true`.
+    ///
+    /// ```
+    /// use xi::Stream;
+    ///
+    /// let enabled = Stream::sink();
+    /// let visible = Stream::sink();
+    ///
+    /// let active = Stream::all_of(vec![
+    ///     enabled.stream().remember(),
+    ///     visible.stream().remember(),
+    /// ]);
+    ///
+    /// enabled.update(true);
+    /// visible.update(true);
+    ///
+    /// // a late subscriber gets the current flag
+    /// let coll = active.collect();
+    ///
+    /// visible.update(false);
+    /// enabled.end();
+    /// visible.end();
+    ///
+    /// assert_eq!(coll.wait(), vec![true, false]);
+    /// ```
+    pub fn all_of(streams: Vec<Stream<bool>>) -> Stream<bool> {
+        if streams.is_empty() {
+            return Stream::of(true);
+        }
+        Stream::combine_flags(streams, |flags| flags.iter().all(|b| *b))
+    }
+
+    /// Combine a number of streams of flags into one that is `true` when any of them is.
+    ///
+    /// Emits and remembers like [`all_of()`](struct.Stream.html#method.all_of). An empty
+    /// vec gives a stream that is `false`.
+    ///
+    /// ```
+    /// use xi::Stream;
+    ///
+    /// let a = Stream::sink();
+    /// let b = Stream::sink();
+    ///
+    /// let any = Stream::any_of(vec![a.stream(), b.stream()]);
+    ///
+    /// let coll = any.collect();
+    ///
+    /// a.update(false);
+    /// b.update(true);
+    /// a.update(true); // still true, no emission
+    /// b.update(false);
+    /// a.update(false);
+    /// a.end();
+    /// b.end();
+    ///
+    /// assert_eq!(coll.wait(), vec![true, false]);
+    /// ```
+    pub fn any_of(streams: Vec<Stream<bool>>) -> Stream<bool> {
+        if streams.is_empty() {
+            return Stream::of(false);
+        }
+        Stream::combine_flags(streams, |flags| flags.iter().any(|b| *b))
+    }
+
+    /// Combine the latest values of the streams once all have one, deduped and remembered.
+    fn combine_flags(streams: Vec<Stream<bool>>, f: fn(&[bool]) -> bool) -> Stream<bool> {
+        let inner = SafeInner::new(MemoryMode::NoMemory, None);
+        let latest: Arc<Mutex<Vec<Option<bool>>>> = Arc::new(Mutex::new(vec![None; streams.len()]));
+        let alive = Arc::new(AtomicUsize::new(streams.len()));
+        let pegs: Vec<_> = streams
+            .iter()
+            .enumerate()
+            .map(|(idx, stream)| {
+                let inner_clone = inner.clone();
+                let latest = latest.clone();
+                let alive = alive.clone();
+                stream.internal_subscribe(move |t| {
+                    if let Some(t) = t {
+                        let v = {
+                            let mut latest = latest.lock().unwrap();
+                            latest[idx] = Some(*t);
+                            let flags: Option<Vec<bool>> = latest.iter().cloned().collect();
+                            flags.map(|flags| f(&flags))
+                        };
+                        if v.is_some() {
+                            inner_clone.lock().update_owned(v);
+                        }
+                    } else if alive.fetch_sub(1, Ordering::SeqCst) == 1 {
+                        inner_clone.lock().update_owned(None);
+                    }
+                })
+            })
+            .collect();
+        let peg = Peg::many(pegs);
+        Stream { peg, inner }.dedupe().remember()
+    }
+}
+
+impl<T, E> Stream<Result<T, E>> {
+    //
+
+    /// Group the `Ok` values between errors.
+    ///
+    /// `Ok` values are collected until an `Err` arrives. The collected values are then
+    /// emitted together with the error, and collecting starts over. Consecutive errors give
+    /// chunks with an empty vec.
+    ///
+    /// When the stream ends, values collected since the last error are emitted with `None`
+    /// as error. If no values were collected, e.g. when the stream ends right after an
+    /// error, there is no final chunk.
+    ///
+    /// ```
+    /// let sink = xi::Stream::sink();
+    ///
+    /// let chunked = sink.stream().chunks_between_errors();
+    ///
+    /// let coll = chunked.collect();
+    ///
+    /// sink.update(Ok(1));
+    /// sink.update(Ok(2));
+    /// sink.update(Err("bad"));
+    /// sink.update(Err("worse"));
+    /// sink.update(Ok(3));
+    /// sink.end();
+    ///
+    /// assert_eq!(coll.wait(), vec![
+    ///     (vec![1, 2], Some("bad")),
+    ///     (vec![], Some("worse")),
+    ///     (vec![3], None),
+    /// ]);
+    /// ```
+    pub fn chunks_between_errors(&self) -> Stream<(Vec<T>, Option<E>)>
+    where
+        T: Clone,
+        E: Clone,
+    {
+        let inner = SafeInner::new(MemoryMode::NoMemory, None);
+        let inner_clone = inner.clone();
+        let mut chunk = vec![];
+        let peg = self.internal_subscribe(move |r| match r {
+            Some(Ok(t)) => chunk.push(t.clone()),
+            Some(Err(e)) => {
+                let v = (std::mem::take(&mut chunk), Some(e.clone()));
+                inner_clone.lock().update_owned(Some(v));
+            }
+            None => {
+                let mut lock = inner_clone.lock();
+                if !chunk.is_empty() {
+                    lock.update_owned(Some((std::mem::take(&mut chunk), None)));
+                }
+                lock.update_owned(None);
+            }
+        });
+        Stream { peg, inner }
+    }
+}
+
+impl<T> Stream<Arc<T>> {
+    //
+
+    /// Transform events by reference to the value in the `Arc`.
+    ///
+    /// ```
+    /// use std::sync::Arc;
+    ///
+    /// let sink = xi::Stream::sink();
+    ///
+    /// let lengths = sink.stream().map_inner(|s: &String| s.len());
+    ///
+    /// let coll = lengths.collect();
+    ///
+    /// sink.update(Arc::new("hello".to_string()));
+    /// sink.end();
+    ///
+    /// assert_eq!(coll.wait(), vec![5]);
+    /// ```
+    pub fn map_inner<U, F>(&self, mut f: F) -> Stream<U>
+    where
+        U: 'static,
+        F: FnMut(&T) -> U + Send + 'static,
+    {
+        self.map(move |t| f(t))
+    }
+
+    /// Dedupe stream by pointer, dropping events that are the same allocation as the last
+    /// emitted one.
+    ///
+    /// Comparing pointers is cheap, and is the right thing where a new value is always
+    /// put in a new `Arc`, such as state snapshots. Equal values in different allocations
+    /// are not deduped.
+    ///
+    /// ```
+    /// use std::sync::Arc;
+    ///
+    /// let sink = xi::Stream::sink();
+    ///
+    /// let deduped = sink.stream().dedupe_ptr();
+    ///
+    /// let coll = deduped.collect();
+    ///
+    /// let state = Arc::new(1);
+    /// sink.update(state.clone());
+    /// sink.update(state.clone()); // same snapshot
+    /// sink.update(Arc::new(1));   // new snapshot, equal content
+    /// sink.end();
+    ///
+    /// assert_eq!(coll.wait().len(), 2);
+    /// ```
+    pub fn dedupe_ptr(&self) -> Stream<Arc<T>> {
+        self.dedupe_with(|a, b| Arc::ptr_eq(a, b))
+    }
+
+    /// Clone the values out of the `Arc`, for where an owned value is needed.
+    ///
+    /// ```
+    /// use std::sync::Arc;
+    ///
+    /// let sink = xi::Stream::sink();
+    ///
+    /// let owned = sink.stream().cloned_inner();
+    ///
+    /// let coll = owned.collect();
+    ///
+    /// sink.update(Arc::new(vec![1, 2]));
+    /// sink.end();
+    ///
+    /// assert_eq!(coll.wait(), vec![vec![1, 2]]);
+    /// ```
+    pub fn cloned_inner(&self) -> Stream<T>
+    where
+        T: Clone,
+    {
+        self.map(|t| T::clone(t))
+    }
+}
+
+impl<T> Stream<Option<T>> {
+    //
+
+    /// Drop the `None` events, and unwrap the `Some`.
+    ///
+    /// ```
+    /// let sink = xi::Stream::sink();
+    ///
+    /// let present = sink.stream().unwrap_option();
+    ///
+    /// let coll = present.collect();
+    ///
+    /// sink.update(Some(1));
+    /// sink.update(None);
+    /// sink.update(Some(2));
+    /// sink.end();
+    ///
+    /// assert_eq!(coll.wait(), vec![1, 2]);
+    /// ```
+    pub fn unwrap_option(&self) -> Stream<T> {
+        let inner = SafeInner::new(MemoryMode::NoMemory, None);
+        let inner_clone = inner.clone();
+        let peg = self.internal_subscribe(move |t| match t {
+            Some(Some(t)) => inner_clone.lock().update_borrowed(Some(t)),
+            Some(None) => (),
+            None => inner_clone.lock().update_borrowed(None),
+        });
+        Stream { peg, inner }
+    }
+}
+
+include!("./comb.rs");
+
+/// The two outputs of a split, as updated by the shared subscription.
+struct Halves<T: 'static>(Pegged<SafeInner<T>>, Pegged<SafeInner<T>>);
+
+impl<T> Halves<T> {
+    fn first(&self, t: Option<&T>) {
+        Halves::update(&self.0, t);
+    }
+
+    fn second(&self, t: Option<&T>) {
+        Halves::update(&self.1, t);
+    }
+
+    // a dropped half is unpegged, and gets no more events.
+    fn update(pegged: &Pegged<SafeInner<T>>, t: Option<&T>) {
+        pegged.with_value(|inner| {
+            if let Some(inner) = inner {
+                inner.lock().update_borrowed(t);
+            }
+        })
+    }
+}
+
+/// A subscriber bound to the thread that created it, see `subscribe_local()`.
+struct Local<F> {
+    f: Option<F>,
+    thread: std::thread::ThreadId,
+}
+
+impl<F> Drop for Local<F> {
+    fn drop(&mut self) {
+        // the subscriber isn't Send, so it must not be dropped on another thread.
+        if std::thread::current().id() != self.thread {
+            std::mem::forget(self.f.take());
+        }
+    }
+}
+
+// Gives the streams of a `concat()` one at a time, and `None` when there are no more.
+type NextStream<T> = Arc<Mutex<dyn FnMut() -> Option<Stream<T>>>>;
+
+// Release the subscriptions held for a stream once the current dispatch is done, since a
+// listener can't drop its own subscription.
+fn release_deferred(pegged: &Arc<Pegged<Vec<Peg>>>) {
+    let pegged = pegged.clone();
+    defer(move || {
+        let subs = pegged.with_value(|subs| subs.map(std::mem::take));
+        drop(subs);
+    });
+}
+
+// Hold subscriptions with the peg of a stream, or drop them straight away if the stream
+// already ended while subscribing.
+fn hold_subs<T>(pegged: &Pegged<Vec<Peg>>, inner: &SafeInner<T>, subs: Vec<Peg>) {
+    if inner.lock().is_ended() {
+        drop(subs);
+    } else {
+        let mut subs = Some(subs);
+        pegged.with_value(|held| {
+            if let (Some(held), Some(subs)) = (held, subs.take()) {
+                held.extend(subs);
+            }
+        });
+    }
+}
+
+/// The state of an `interleave()`: the held event of each stream, and whose turn it is.
+struct Rotation<T> {
+    held: Vec<Option<T>>,
+    ended: Vec<bool>,
+    turn: usize,
+}
+
+impl<T> Rotation<T> {
+    fn new(n: usize) -> Self {
+        Rotation {
+            held: (0..n).map(|_| None).collect(),
+            ended: vec![false; n],
+            turn: 0,
+        }
+    }
+
+    // hold an event of a stream, or its end, until its turn.
+    fn hold(&mut self, i: usize, t: Option<T>) {
+        if t.is_some() {
+            self.held[i] = t;
+        } else {
+            self.ended[i] = true;
+        }
+    }
+
+    // emit held events in turn, until a stream that is yet to emit has the turn.
+    fn release(&mut self, inner: &SafeInner<T>) {
+        let mut lock = inner.lock();
+        if lock.is_ended() {
+            return;
+        }
+        loop {
+            if let Some(t) = self.held[self.turn].take() {
+                lock.update_owned(Some(t));
+            } else if !self.ended[self.turn] {
+                return;
+            } else if self.ended.iter().all(|e| *e) && self.held.iter().all(Option::is_none) {
+                lock.update_owned(None);
+                return;
+            }
+            self.turn = (self.turn + 1) % self.held.len();
+        }
+    }
+}
+
+/// A sink is a producer of events. Created by [`Stream::sink()`](struct.Stream.html#method.sink).
+pub struct Sink<T: 'static> {
+    inner: SafeInner<T>,
+}
+impl<T> Sink<T> {
+    /// Create a new sink that in turn is used to stream events.
+    fn new() -> Self {
+        let inner = SafeInner::new(MemoryMode::NoMemory, None);
+        inner.lock().set_report_late();
+        Sink { inner }
+    }
+
+    /// Get a stream of events from this sink. One stream instance is created for each call,
+    /// and they all receive the events from the sink.
+    ///
+    /// ```
+    /// let sink = xi::Stream::sink();
+    ///
+    /// let stream1 = sink.stream();
+    /// let stream2 = sink.stream();
+    ///
+    /// let coll1 = stream1.collect();
+    /// let coll2 = stream1.collect();
+    ///
+    /// sink.update(42);
+    /// sink.end();
+    ///
+    /// assert_eq!(coll1.wait(), vec![42]);
+    /// assert_eq!(coll2.wait(), vec![42]);
+    /// ```
+    pub fn stream(&self) -> Stream<T> {
+        Stream {
+            peg: Peg::new_fake(),
+            inner: self.inner.clone(),
+        }
+    }
+
+    /// Update a value into this sink.
+    ///
+    /// The execution of the combinators "hanging" off this sink is (thread safe) and
+    /// synchronous. In other words, there is nothing in xi itself that will still be
+    /// "to do" once the `update()` call returns.
+    ///
+    /// Each value is wrapped in an `Option` towards subscribers of the streams.
+    ///
+    /// An update after the sink has ended is ignored, or handled according to
+    /// [`set_ended_update_policy()`](fn.set_ended_update_policy.html).
+    ///
+    /// ```
+    /// let sink = xi::Stream::sink();
+    /// let stream = sink.stream();
+    ///
+    /// stream.subscribe(|v| {
+    ///     // v is Some(0), Some(1), None
+    /// });
+    ///
+    /// sink.update(0);
+    /// sink.update(1);
+    /// sink.end();
+    /// ```
+    pub fn update(&self, next: T) {
+        if let Some(_in_flight) = InFlight::enter() {
+            self.inner.lock().update_owned(Some(next));
+            run_deferred();
+            check_misdispatch();
+            policy::check_ended_update();
+        } else if !policy::is_ignore() {
+            let mut lock = self.inner.lock();
+            if lock.is_ended() {
+                lock.late_update();
+            }
+            drop(lock);
+            policy::check_ended_update();
+        }
+    }
+
+    /// End the stream of events. Consumes the instance since no more values are to go into it.
+    ///
+    /// Subscribers will se a `None` value.
+    ///
+    /// Every stream hanging directly off this sink will also end. The exception is streams
+    /// combining input from multiple source streams.
+    pub fn end(self) {
+        self.end_shared();
+    }
+
+    /// End through a shared reference, for when the sink can't be consumed.
+    pub(crate) fn end_shared(&self) {
+        if let Some(_in_flight) = InFlight::enter() {
+            self.inner.lock().update_owned(None);
+            run_deferred();
+            check_misdispatch();
+            policy::check_ended_update();
+        }
+    }
+
+    /// An id for the events of this sink, unique in the process. The same as the
+    /// [`id()`](struct.Stream.html#method.id) of its streams.
+    pub fn id(&self) -> usize {
+        self.inner.lock().id()
+    }
+}
+
+/// The collector instance collects values from a stream. Created by
+/// [`Stream::collect()`](struct.Stream.html#method.collect).
+pub struct Collector<T> {
+    #[allow(dead_code)]
+    peg: Peg,
+    #[allow(clippy::type_complexity)]
+    state: Arc<(Mutex<(bool, Option<Vec<T>>)>, Condvar)>,
+    // number of collected events and whether the stream ended, readable without the lock.
+    hints: Arc<(AtomicUsize, AtomicBool)>,
+}
+
+impl<T> Collector<T> {
+    /// Stall the thread and wait for the stream to end.
+    pub fn wait(self) -> Vec<T> {
+        let mut lock = self.state.0.lock().unwrap();
+        while !lock.0 {
+            lock = self.state.1.wait(lock).unwrap();
+        }
+        lock.1.take().unwrap()
+    }
+
+    /// Take whatever is there, without the stream ending, and stop collecting.
     pub fn take(self) -> Vec<T> {
         let mut lock = self.state.0.lock().unwrap();
         lock.1.take().unwrap()
     }
-}
 
-impl<T> Clone for Stream<T> {
-    fn clone(&self) -> Self {
-        Stream {
-            peg: self.peg.clone(),
-            inner: self.inner.clone(),
+    /// The number of events collected so far.
+    ///
+    /// This doesn't take the lock the collecting subscriber uses, so it's cheap to poll
+    /// from another thread. The count is a relaxed read, and may lag behind an event being
+    /// collected on another thread at the same moment.
+    ///
+    /// ```
+    /// let sink = xi::Stream::sink();
+    /// let coll = sink.stream().collect();
+    ///
+    /// assert!(coll.is_empty());
+    ///
+    /// sink.update(0);
+    /// sink.update(1);
+    ///
+    /// assert_eq!(coll.len(), 2);
+    /// ```
+    pub fn len(&self) -> usize {
+        self.hints.0.load(Ordering::Relaxed)
+    }
+
+    /// Whether no events are collected so far. See [`len()`](#method.len).
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Whether the stream has ended, read like [`len()`](#method.len) without taking the
+    /// lock.
+    ///
+    /// ```
+    /// let sink = xi::Stream::sink();
+    /// let coll = sink.stream().collect();
+    ///
+    /// sink.update(0);
+    /// assert!(!coll.ended_flag());
+    ///
+    /// sink.end();
+    /// assert!(coll.ended_flag());
+    /// ```
+    pub fn ended_flag(&self) -> bool {
+        self.hints.1.load(Ordering::Relaxed)
+    }
+}
+
+impl<T> std::iter::FromIterator<T> for Stream<T> {
+    /// See [`Stream::from_iter()`](struct.Stream.html#method.from_iter).
+    fn from_iter<I: IntoIterator<Item = T>>(iter: I) -> Self {
+        Stream::from_iter(iter)
+    }
+}
+
+impl<T> Clone for Stream<T> {
+    fn clone(&self) -> Self {
+        Stream {
+            peg: self.peg.clone(),
+            inner: self.inner.clone(),
+        }
+    }
+}
+
+// not `mod test`, which would clash with the public `xi::test` module.
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::mpsc::sync_channel;
+
+    #[test]
+    fn test_sink_auto_traits() {
+        fn f<X: Sync + Send>(_: X) {}
+        let sink: Sink<u32> = Sink::new();
+        f(sink);
+    }
+
+    #[test]
+    fn test_stream_auto_traits() {
+        fn f<X: Sync + Send + Clone>(_: X) {}
+        struct Foo(); // not clonable, but Stream<Foo> should be
+        let sink: Sink<Foo> = Sink::new();
+        f(sink.stream());
+    }
+
+    #[test]
+    fn test_subscription_auto_traits() {
+        fn f<X: Sync + Send + Clone>(_: X) {}
+        let sink: Sink<u32> = Sink::new();
+        let sub = sink.stream().subscribe(|_| {});
+        f(sub);
+    }
+
+    #[test]
+    fn test_chained_maps() {
+        let sink: Sink<u32> = Sink::new();
+        // the risk is that the intermediary map drops the subscription
+        // and the entire chain stalls.
+        let map = sink.stream().map(|x| x + 1).map(|x| x * 2);
+        let coll = map.collect();
+        sink.update(0);
+        sink.update(1);
+        sink.update(2);
+        sink.end();
+        assert_eq!(coll.wait(), vec![2, 4, 6]);
+    }
+
+    #[test]
+    fn test_of() {
+        let stream = Stream::of(42);
+        let (tx, rx) = sync_channel(1);
+        stream.subscribe(move |x| tx.send(*x.unwrap()).unwrap());
+        assert_eq!(rx.recv().unwrap(), 42);
+    }
+
+    #[test]
+    fn test_imitate() {
+        let counter = bench_support::CloneCounter::new();
+        let sink: Sink<u32> = Sink::new();
+        let imit: Imitator<bench_support::Counted<u32>> = Imitator::new();
+        let counter_clone = counter.clone();
+        let map = sink.stream().map(move |x| counter_clone.wrap(x * 2));
+        let coll = imit.stream().map(|c| c.value).collect();
+        imit.imitate(&map);
+        sink.update(0);
+        sink.update(1);
+        sink.update(2);
+        sink.end();
+        assert_eq!(coll.wait(), vec![0, 2, 4]);
+        // cloned three times on the way through the imitator
+        assert_eq!(counter.count(), 9);
+    }
+
+    #[test]
+    fn test_fold_and_last() {
+        let sink: Sink<u32> = Sink::new();
+        // this potentially creates an edge case where
+        // last might hang on to the rc value that fold has in memory
+        let fold = sink
+            .stream()
+            .fold("|".to_string(), |p, c| format!("{} {}", p, c))
+            .last();
+        let coll = fold.collect();
+        sink.update(42);
+        sink.end();
+        assert_eq!(coll.wait(), vec!["| 42".to_string()]);
+    }
+
+    #[test]
+    fn test_fold_and_remember() {
+        let sink: Sink<u32> = Sink::new();
+        // this potentially creates an edge case where
+        // remember might hang on to the rc value that fold has in memory
+        let fold = sink
+            .stream()
+            .fold("|".to_string(), |p, c| format!("{} {}", p, c))
+            .remember();
+        let coll = fold.collect();
+        sink.update(42);
+        sink.end();
+        assert_eq!(coll.wait(), vec!["|".to_string(), "| 42".to_string()]);
+    }
+
+    #[test]
+    fn test_imitate_cycle() {
+        let imitator = Stream::imitator();
+
+        let fold = imitator
+            .stream()
+            .fold(1, |p, c| if *c < 10 { p + c } else { p })
+            .dedupe();
+
+        let sink = Stream::sink();
+
+        let merge = Stream::merge(vec![fold, sink.stream()]);
+        imitator.imitate(&merge);
+
+        let coll = merge.collect();
+
+        sink.update(1);
+        assert_eq!(coll.take(), vec![1, 2, 4, 8, 16]);
+    }
+
+    #[test]
+    fn test_combine() {
+        let sink1 = Stream::sink();
+        let sink2 = Stream::sink();
+
+        let comb = Stream::combine2(&sink1.stream(), &sink2.stream());
+
+        let coll = comb.collect();
+
+        sink1.update(0.0);
+        sink2.update(10);
+        sink1.update(1.0);
+        sink1.update(2.0);
+        sink2.update(11);
+        sink1.update(3.0);
+        sink1.end();
+        sink2.end();
+
+        assert_eq!(
+            coll.wait(),
+            vec![(0.0, 10), (1.0, 10), (2.0, 10), (2.0, 11), (3.0, 11)]
+        );
+    }
+
+    #[test]
+    fn test_try_subscribe_live() {
+        let sink = Stream::sink();
+        let (tx, rx) = sync_channel(3);
+        let sub = sink
+            .stream()
+            .try_subscribe(move |v| tx.send(v.cloned()).unwrap());
+        assert!(sub.is_ok());
+        sink.update(42);
+        sink.end();
+        assert_eq!(rx.recv().unwrap(), Some(42));
+        assert_eq!(rx.recv().unwrap(), None);
+    }
+
+    #[test]
+    fn test_try_subscribe_ended() {
+        let sink: Sink<u32> = Stream::sink();
+        let stream = sink.stream();
+        sink.end();
+        let called = Arc::new(AtomicUsize::new(0));
+        let called_clone = called.clone();
+        let res = stream.try_subscribe(move |_| {
+            called_clone.fetch_add(1, Ordering::SeqCst);
+        });
+        assert_eq!(res.err(), Some(StreamEnded));
+        // the listener was never registered, not even for the end event
+        assert_eq!(called.load(Ordering::SeqCst), 0);
+    }
+
+    #[test]
+    fn test_try_subscribe_ended_after_check() {
+        let sink: Sink<u32> = Stream::sink();
+        let stream = sink.stream();
+        assert!(!stream.is_ended());
+        // stream ends between the caller's check and try_subscribe
+        sink.end();
+        assert!(stream.try_subscribe(|_| {}).is_err());
+    }
+
+    #[test]
+    fn test_try_subscribe_race() {
+        for _ in 0..100 {
+            let sink: Sink<u32> = Stream::sink();
+            let stream = sink.stream();
+            let ends = Arc::new(AtomicUsize::new(0));
+            let handle = std::thread::spawn(move || sink.end());
+            let mut accepted = 0;
+            loop {
+                let ends = ends.clone();
+                let res = stream.try_subscribe(move |v| {
+                    if v.is_none() {
+                        ends.fetch_add(1, Ordering::SeqCst);
+                    }
+                });
+                match res {
+                    Ok(_) => accepted += 1,
+                    Err(StreamEnded) => break,
+                }
+            }
+            handle.join().unwrap();
+            // every accepted subscription was live and saw exactly one end
+            assert_eq!(ends.load(Ordering::SeqCst), accepted);
+        }
+    }
+
+    #[test]
+    fn test_count_down_zero() {
+        let sink = Stream::sink();
+        let coll = sink.stream().count_down(0).collect();
+        sink.update(1);
+        sink.update(2);
+        assert_eq!(coll.wait(), vec![]);
+    }
+
+    #[test]
+    fn test_subscription_set_restores_baseline() {
+        let sink: Sink<u32> = Stream::sink();
+        let other: Sink<u32> = Stream::sink();
+        let source = sink.stream();
+        let _existing = source.subscribe(|_| {});
+        let baseline = source.inner.lock().listener_count();
+        let other_baseline = other.stream().inner.lock().listener_count();
+
+        // the feature holds on to its streams, as if stored in a struct
+        let mut set = SubscriptionSet::new();
+        let mapped = source.map(|v| v * 2);
+        set.push(&mapped);
+        let filtered = source.filter(|v| v % 2 == 0);
+        set.push(&filtered);
+        let merged = Stream::merge(vec![filtered.clone(), other.stream()]);
+        set.push(&merged);
+        set.push(mapped.subscribe(|_| {}));
+        set.push(merged.subscribe(|_| {}));
+        set.push(source.subscribe(|_| {}));
+
+        assert_eq!(source.inner.lock().listener_count(), baseline + 3);
+        assert_eq!(
+            other.stream().inner.lock().listener_count(),
+            other_baseline + 1
+        );
+
+        drop(set);
+
+        assert_eq!(source.inner.lock().listener_count(), baseline);
+        assert_eq!(other.stream().inner.lock().listener_count(), other_baseline);
+        assert_eq!(mapped.inner.lock().listener_count(), 0);
+        assert_eq!(merged.inner.lock().listener_count(), 0);
+    }
+
+    #[test]
+    fn test_subscription_set_clear_idempotent() {
+        let sink: Sink<u32> = Stream::sink();
+        let source = sink.stream();
+        let coll = source.map(|v| v + 1).collect();
+        let mut set = SubscriptionSet::new();
+        set.push(source.subscribe(|_| {}));
+        set.clear();
+        assert!(set.is_empty());
+        set.clear();
+        // clearing the set doesn't affect anything not in it
+        sink.update(1);
+        assert_eq!(source.inner.lock().listener_count(), 1);
+        assert_eq!(coll.take(), vec![2]);
+    }
+
+    #[test]
+    fn test_unsubscribe() {
+        let sink: Sink<u32> = Stream::sink();
+        let (tx, rx) = std::sync::mpsc::channel();
+        let sub = sink
+            .stream()
+            .subscribe(move |v| tx.send(v.cloned()).unwrap());
+        sink.update(1);
+        sub.unsubscribe();
+        sink.update(2);
+        sink.end();
+        assert_eq!(rx.try_iter().collect::<Vec<_>>(), vec![Some(1)]);
+    }
+
+    // payload type that is deliberately not Clone
+    #[derive(Debug, PartialEq)]
+    struct NoClone(u32);
+
+    #[test]
+    fn test_of_no_clone() {
+        let stream = Stream::of(NoClone(42));
+        let (tx, rx) = sync_channel(1);
+        stream.subscribe(move |x| tx.send(x.unwrap().0).unwrap());
+        assert_eq!(rx.recv().unwrap(), 42);
+    }
+
+    #[test]
+    fn test_last_by_no_clone() {
+        let sink: Sink<NoClone> = Stream::sink();
+        let coll = sink.stream().last_by(|v| v.0 * 2).collect();
+        sink.update(NoClone(1));
+        sink.update(NoClone(2));
+        sink.end();
+        assert_eq!(coll.wait(), vec![4]);
+    }
+
+    #[test]
+    fn test_last_by_empty() {
+        let sink: Sink<NoClone> = Stream::sink();
+        let coll = sink.stream().last_by(|v| v.0).collect();
+        sink.end();
+        assert_eq!(coll.wait(), vec![]);
+    }
+
+    #[test]
+    fn test_dedupe_by_no_clone() {
+        let sink: Sink<NoClone> = Stream::sink();
+        let coll = sink.stream().dedupe_by(|v| v.0).map(|v| v.0).collect();
+        sink.update(NoClone(1));
+        sink.update(NoClone(1));
+        sink.update(NoClone(2));
+        sink.end();
+        assert_eq!(coll.wait(), vec![1, 2]);
+    }
+
+    #[test]
+    fn test_chunk_while_empty() {
+        let sink: Sink<u32> = Stream::sink();
+        let coll = sink.stream().chunk_while(|a, b| a == b).collect();
+        sink.end();
+        assert_eq!(coll.wait(), Vec::<Vec<u32>>::new());
+    }
+
+    #[test]
+    fn test_split_at_short_source() {
+        let sink = Stream::sink();
+        let (first, rest) = sink.stream().split_at(3);
+        let coll1 = first.collect();
+        let coll2 = rest.collect();
+        sink.update(0);
+        sink.end();
+        assert_eq!(coll1.wait(), vec![0]);
+        assert_eq!(coll2.wait(), vec![]);
+    }
+
+    #[test]
+    fn test_max_by_key_ties() {
+        let sink = Stream::sink();
+        let coll = sink.stream().max_by_key(|v: &u32| *v).collect();
+        for v in &[1, 1, 2, 2, 1, 3] {
+            sink.update(*v);
+        }
+        sink.end();
+        assert_eq!(coll.wait(), vec![1, 2, 3]);
+    }
+
+    #[test]
+    fn test_max_by_key_decreasing() {
+        let sink = Stream::sink();
+        let coll = sink.stream().max_by_key(|v: &u32| *v).collect();
+        for v in &[5, 4, 3, 2, 1] {
+            sink.update(*v);
+        }
+        sink.end();
+        assert_eq!(coll.wait(), vec![5]);
+    }
+
+    #[test]
+    fn test_min_by_key_decreasing() {
+        let sink = Stream::sink();
+        let coll = sink.stream().min_by_key(|v: &u32| *v).collect();
+        for v in &[5, 4, 4, 2] {
+            sink.update(*v);
+        }
+        sink.end();
+        assert_eq!(coll.wait(), vec![5, 4, 2]);
+    }
+
+    #[test]
+    fn test_subscribe_local_same_thread() {
+        let sink = Stream::sink();
+        let seen = std::rc::Rc::new(std::cell::Cell::new(0));
+        let seen_clone = seen.clone();
+        let _sub = sink.stream().subscribe_local(move |v| {
+            if let Some(v) = v {
+                seen_clone.set(*v);
+            }
+        });
+        sink.update(42);
+        assert_eq!(seen.get(), 42);
+    }
+
+    #[test]
+    fn test_subscribe_local_other_thread_panics() {
+        let sink = Stream::sink();
+        let _sub = sink.stream().subscribe_local(|_: Option<&u32>| {});
+        let res = std::thread::spawn(move || sink.update(1)).join();
+        assert!(res.is_err());
+    }
+
+    #[test]
+    fn test_mailbox_threaded() {
+        let sink = Stream::sink();
+        let mailbox = sink.stream().mailbox();
+        let handle = std::thread::spawn(move || {
+            for i in 0..10_000_u32 {
+                sink.update(i);
+            }
+        });
+        let mut last = None;
+        loop {
+            if let Some(v) = mailbox.poll() {
+                // never a stale value, or one that was already taken
+                if let Some(l) = last {
+                    assert!(v > l);
+                }
+                last = Some(v);
+                if v == 9_999 {
+                    break;
+                }
+            }
+        }
+        handle.join().unwrap();
+        assert_eq!(mailbox.poll(), None);
+    }
+
+    #[test]
+    fn test_mailbox_drop_unsubscribes() {
+        let sink: Sink<u32> = Stream::sink();
+        let stream = sink.stream();
+        let mailbox = stream.mailbox();
+        assert_eq!(stream.inner.lock().listener_count(), 1);
+        drop(mailbox);
+        assert_eq!(stream.inner.lock().listener_count(), 0);
+    }
+
+    #[test]
+    fn test_flatten_with_max_concurrency() {
+        let outer: Sink<Stream<u32>> = Stream::sink();
+        let a: Sink<u32> = Stream::sink();
+        let b: Sink<u32> = Stream::sink();
+        let c: Sink<u32> = Stream::sink();
+        let coll = outer.stream().flatten_with_max_concurrency(2).collect();
+        outer.update(a.stream());
+        outer.update(b.stream());
+        outer.update(c.stream()); // waiting
+        a.update(1);
+        b.update(2);
+        c.update(3); // lost
+        b.end(); // c starts
+        c.update(4);
+        a.update(5);
+        outer.end();
+        a.update(6);
+        assert_eq!(coll.wait(), vec![1, 2, 4, 5]);
+    }
+
+    #[test]
+    fn test_flatten_with_max_concurrency_ended_inner() {
+        let outer: Sink<Stream<u32>> = Stream::sink();
+        let a: Sink<u32> = Stream::sink();
+        let b: Sink<u32> = Stream::sink();
+        let ended = a.stream();
+        a.end();
+        let coll = outer.stream().flatten_with_max_concurrency(1).collect();
+        // an already ended stream frees its slot straight away
+        outer.update(ended);
+        outer.update(b.stream());
+        b.update(1);
+        outer.end();
+        assert_eq!(coll.wait(), vec![1]);
+    }
+
+    #[test]
+    fn test_flatten_concurrently_derived_inner_kept() {
+        let sink: Sink<u32> = Stream::sink();
+        let values: Sink<u32> = Stream::sink();
+        let values_stream = values.stream();
+        // the inner streams are only held by the flatten
+        let flat = sink
+            .stream()
+            .map(move |v| {
+                let v = *v;
+                values_stream.map(move |x| x * v)
+            })
+            .flatten_concurrently();
+        let coll = flat.collect();
+        sink.update(1);
+        sink.update(10);
+        values.update(2);
+        sink.end();
+        assert_eq!(coll.wait(), vec![2, 20]);
+    }
+
+    #[test]
+    fn test_dedupe_by_tolerance_drift() {
+        let sink = Stream::sink();
+        let coll = sink.stream().dedupe_by_tolerance(0.25).collect();
+        // each step is within tolerance of the previous, but not of the last emitted
+        for v in &[0.0, 0.1, 0.2, 0.3, 0.4, 0.5, 0.6] {
+            sink.update(*v);
+        }
+        sink.end();
+        assert_eq!(coll.wait(), vec![0.0, 0.3, 0.6]);
+    }
+
+    #[test]
+    fn test_dedupe_by_tolerance_nan() {
+        let sink = Stream::sink();
+        let coll = sink.stream().dedupe_by_tolerance(0.5).collect();
+        for v in &[1.0, f64::NAN, f64::NAN, 1.0, 1.1] {
+            sink.update(*v);
+        }
+        sink.end();
+        let res = coll.wait();
+        assert_eq!(res.len(), 4);
+        assert_eq!(res[0], 1.0);
+        assert!(res[1].is_nan());
+        assert!(res[2].is_nan());
+        assert_eq!(res[3], 1.0);
+    }
+
+    #[test]
+    fn test_with_default_if_empty_not_empty() {
+        let sink1 = Stream::sink();
+        let sink2 = Stream::sink();
+        let coll = sink1
+            .stream()
+            .with_default_if_empty(&sink2.stream())
+            .collect();
+        sink1.update(1);
+        sink1.end();
+        sink2.update(2);
+        assert_eq!(coll.wait(), vec![1]);
+    }
+
+    #[test]
+    fn test_with_default_if_empty_memory_fallback() {
+        let sink1: Sink<u32> = Stream::sink();
+        let coll = sink1
+            .stream()
+            .with_default_if_empty(&Stream::of(42))
+            .collect();
+        sink1.end();
+        assert_eq!(coll.take(), vec![42]);
+    }
+
+    #[test]
+    fn test_join_by_key_duplicates_latest_wins() {
+        let a = Stream::sink();
+        let b = Stream::sink();
+        let coll = a
+            .stream()
+            .join_by_key(&b.stream(), |v: &(u32, u32)| v.0, |v: &u32| *v, None)
+            .collect();
+        a.update((1, 10));
+        a.update((1, 11)); // replaces (1, 10)
+        b.update(1);
+        b.update(1); // no match held any more
+        a.update((1, 12)); // matches the held b
+        a.end();
+        b.end();
+        assert_eq!(coll.wait(), vec![((1, 11), 1), ((1, 12), 1)]);
+    }
+
+    #[test]
+    fn test_join_by_key_eviction() {
+        let a = Stream::sink();
+        let b = Stream::sink();
+        let coll = a
+            .stream()
+            .join_by_key(&b.stream(), |v: &u32| *v, |v: &u32| *v, Some(2))
+            .collect();
+        a.update(1);
+        a.update(2);
+        a.update(3); // evicts 1
+        b.update(1);
+        b.update(2);
+        b.update(3);
+        a.end();
+        b.end();
+        assert_eq!(coll.wait(), vec![(2, 2), (3, 3)]);
+    }
+
+    #[test]
+    fn test_join_by_key_ends_when_both_end() {
+        let a: Sink<u32> = Stream::sink();
+        let b: Sink<u32> = Stream::sink();
+        let joined = a.stream().join_by_key(&b.stream(), |v| *v, |v| *v, None);
+        let coll = joined.collect();
+        b.update(1);
+        b.end();
+        assert!(!joined.is_ended());
+        a.update(1);
+        a.end();
+        assert_eq!(coll.wait(), vec![(1, 1)]);
+    }
+
+    #[test]
+    fn test_last_of_remembered() {
+        let sink = Stream::sink();
+        let rem = sink.stream().remember();
+        sink.update(42);
+        let coll = rem.last().collect();
+        sink.end();
+        assert_eq!(coll.wait(), vec![42]);
+    }
+
+    #[test]
+    fn test_sequenced_branches() {
+        let sink = Stream::sink();
+        let source = sink.stream();
+        let all = source.collect();
+        let small = source.filter(|v| *v < 50).sequenced().collect();
+        let large = source.filter(|v| *v >= 50).sequenced().collect();
+        for i in 0..100_u32 {
+            sink.update((i * 37) % 100);
+        }
+        sink.end();
+        let mut merged = small.wait();
+        merged.extend(large.wait());
+        merged.sort_by_key(|(seq, _)| *seq);
+        let values: Vec<_> = merged.into_iter().map(|(_, v)| v).collect();
+        assert_eq!(values, all.wait());
+    }
+
+    #[test]
+    fn test_dedupe_repeated_duplicates() {
+        let sink = Stream::sink();
+        let deduped = sink.stream().dedupe();
+        let coll = deduped.collect();
+        sink.update(1);
+        sink.update(1);
+        sink.update(1);
+        sink.update(2);
+        sink.end();
+        assert_eq!(coll.wait(), vec![1, 2]);
+    }
+
+    #[test]
+    fn test_all_of_only_transitions() {
+        let a = Stream::sink();
+        let b = Stream::sink();
+        let c = Stream::sink();
+        let all = Stream::all_of(vec![
+            a.stream().remember(),
+            b.stream().remember(),
+            c.stream().remember(),
+        ]);
+        let any = Stream::any_of(vec![a.stream(), b.stream(), c.stream()]);
+        let coll_all = all.collect();
+        let coll_any = any.collect();
+        a.update(true);
+        b.update(true);
+        c.update(false); // all inputs have a value now
+        c.update(true);
+        a.update(true);
+        b.update(false);
+        a.update(false);
+        b.update(true);
+        c.update(false);
+        b.update(false);
+        a.end();
+        b.end();
+        c.end();
+        assert_eq!(coll_all.wait(), vec![false, true, false]);
+        assert_eq!(coll_any.wait(), vec![true, false]);
+    }
+
+    #[test]
+    fn test_all_of_late_subscriber() {
+        let a = Stream::sink();
+        let b = Stream::sink();
+        let both = a.stream().remember().and(&b.stream().remember());
+        a.update(true);
+        b.update(false);
+        assert!(both.has_memory());
+        let coll = both.collect();
+        assert_eq!(coll.take(), vec![false]);
+        assert_eq!(Stream::all_of(vec![]).collect().take(), vec![true]);
+        assert_eq!(Stream::any_of(vec![]).collect().take(), vec![false]);
+    }
+
+    #[test]
+    fn test_branch_if_end_and_order() {
+        let sink = Stream::sink();
+        let branched =
+            sink.stream()
+                .branch_if(|v: &u32| *v > 10, |big| big.map(|v| v * 100), |small| small);
+        let coll = branched.collect();
+        sink.update(1);
+        sink.update(20);
+        sink.update(3);
+        sink.end();
+        assert_eq!(coll.wait(), vec![1, 2000, 3]);
+    }
+
+    #[test]
+    fn test_filter_map_all_none_ends() {
+        let sink = Stream::sink();
+        let filtered = sink.stream().filter_map(|_: &u32| None::<u32>);
+        assert!(!filtered.has_memory());
+        let coll = filtered.collect();
+        sink.update(1);
+        sink.update(2);
+        sink.end();
+        assert_eq!(coll.wait(), Vec::<u32>::new());
+    }
+
+    #[test]
+    fn test_scan_no_seed_on_end() {
+        let sink: Sink<u32> = Stream::sink();
+        let scanned = sink.stream().scan(NoClone(0), |acc, v| NoClone(acc.0 + *v));
+        assert!(!scanned.has_memory());
+        let sum = scanned.last_by(|v| v.0);
+        let coll = sum.collect();
+        sink.end();
+        assert_eq!(coll.wait(), Vec::<u32>::new());
+    }
+
+    #[test]
+    fn test_imitator_reentrant_update_fifo() {
+        let log = Arc::new(Mutex::new(vec![]));
+        let sink1 = Stream::sink();
+        let sink2 = Arc::new(Stream::sink());
+        let stream1 = sink1.stream();
+        let stream2 = sink2.stream();
+        let imit_a = Stream::imitator();
+        let imit_b = Stream::imitator();
+        let imit_c = Stream::imitator();
+        // a feeds sink2 while the flush of sink1's update is running.
+        let sink2_clone = sink2.clone();
+        let log_a = log.clone();
+        let _sub_a = imit_a.stream().subscribe(move |v| {
+            if let Some(v) = v {
+                log_a.lock().unwrap().push(format!("a{}", v));
+                sink2_clone.update(v + 10);
+            }
+        });
+        let log_b = log.clone();
+        let _sub_b = imit_b.stream().subscribe(move |v| {
+            if let Some(v) = v {
+                log_b.lock().unwrap().push(format!("b{}", v));
+            }
+        });
+        let log_c = log.clone();
+        let _sub_c = imit_c.stream().subscribe(move |v| {
+            if let Some(v) = v {
+                log_c.lock().unwrap().push(format!("c{}", v));
+            }
+        });
+        imit_a.imitate(&stream1);
+        imit_b.imitate(&stream1);
+        imit_c.imitate(&stream2);
+        sink1.update(1);
+        sink1.update(2);
+        // c's events are queued behind b's.
+        assert_eq!(
+            *log.lock().unwrap(),
+            vec!["a1", "b1", "c11", "a2", "b2", "c12"]
+        );
+    }
+
+    #[test]
+    fn test_imitator_reentrant_update_same_sink() {
+        let sink = Arc::new(Stream::sink());
+        let stream = sink.stream();
+        let coll = stream.collect();
+        let imitator = Stream::imitator();
+        let sink_clone = sink.clone();
+        let _sub = imitator.stream().subscribe(move |v| {
+            if let Some(v) = v {
+                sink_clone.update(v + 1);
+            }
+        });
+        let small = stream.filter(|v| *v < 5);
+        imitator.imitate(&small);
+        sink.update(0);
+        sink.update(10);
+        assert_eq!(coll.take(), vec![0, 1, 2, 3, 4, 5, 10]);
+    }
+
+    #[test]
+    fn test_emit_latest_on_ends_with_trigger() {
+        let values = Stream::sink();
+        let clock = Stream::sink();
+        let sampled = values.stream().emit_latest_on(&clock.stream());
+        let coll = sampled.collect();
+        values.update(1);
+        clock.update("tick");
+        clock.end();
+        values.update(2);
+        assert!(sampled.is_ended());
+        assert_eq!(coll.wait(), vec![1]);
+    }
+
+    #[test]
+    fn test_zip_other_ends_first() {
+        let sink1 = Stream::sink();
+        let sink2 = Stream::sink();
+        let zipped = sink1.stream().zip(&sink2.stream());
+        let coll = zipped.collect();
+        sink2.update('a');
+        sink1.update(1);
+        sink1.update(2); // dropped when sink2 ends
+        sink2.end();
+        sink1.update(3);
+        assert!(zipped.is_ended());
+        assert_eq!(coll.wait(), vec![(1, 'a')]);
+    }
+
+    #[test]
+    fn test_chunks_between_errors_end_after_error() {
+        let sink: Sink<Result<u32, &str>> = Stream::sink();
+        let chunked = sink.stream().chunks_between_errors();
+        let coll = chunked.collect();
+        sink.update(Err("first"));
+        sink.update(Ok(1));
+        sink.update(Err("second"));
+        sink.end();
+        assert_eq!(
+            coll.wait(),
+            vec![(vec![], Some("first")), (vec![1], Some("second"))]
+        );
+    }
+
+    #[test]
+    fn test_filter_map_calls_once_per_event() {
+        let calls = Arc::new(AtomicUsize::new(0));
+        let calls_clone = calls.clone();
+        let sink = Stream::sink();
+        let parsed = sink.stream().filter_map(move |v: &&str| {
+            calls_clone.fetch_add(1, Ordering::SeqCst);
+            v.parse::<u32>().ok()
+        });
+        let coll = parsed.collect();
+        sink.update("1");
+        sink.update("x");
+        sink.update("3");
+        sink.end();
+        assert_eq!(coll.wait(), vec![1, 3]);
+        assert_eq!(calls.load(Ordering::SeqCst), 3);
+    }
+
+    #[test]
+    fn test_keep_alive_until_trigger_ends() {
+        let sink = Stream::sink();
+        let stop: Sink<()> = Stream::sink();
+        let kept = sink
+            .stream()
+            .map(|v| v + 1)
+            .keep_alive_until(&stop.stream());
+        let coll = kept.collect();
+        drop(kept);
+        sink.update(1);
+        stop.end();
+        sink.update(2);
+        assert_eq!(coll.wait(), vec![2]);
+        assert_eq!(sink.inner.lock().listener_count(), 0);
+    }
+
+    #[test]
+    fn test_scan_from_first_init_once() {
+        let sink = Stream::sink();
+        let joined = sink.stream().scan_from_first(
+            |v: &&str| v.to_uppercase(),
+            |acc, v| format!("{}-{}", acc, v),
+        );
+        assert!(!joined.has_memory());
+        let coll = joined.collect();
+        sink.update("a");
+        sink.update("b");
+        sink.update("c");
+        sink.end();
+        assert_eq!(coll.wait(), vec!["A", "A-b", "A-b-c"]);
+    }
+
+    #[test]
+    fn test_gate_initial_shared_go() {
+        let sink1 = Stream::sink();
+        let sink2 = Stream::sink();
+        let go = Stream::sink();
+        let gated1 = sink1.stream().remember().gate_initial(&go.stream());
+        let gated2 = sink2.stream().gate_initial(&go.stream());
+        let coll1 = gated1.collect();
+        let coll2 = gated2.collect();
+        sink1.update("a");
+        sink1.update("b");
+        sink1.update("c");
+        go.update(());
+        go.update(()); // only the first go releases
+        sink1.update("d");
+        sink2.update("x");
+        // late subscriber gets the latest
+        let late = gated1.collect();
+        sink1.end();
+        sink2.end();
+        assert_eq!(coll1.wait(), vec!["c", "d"]);
+        assert_eq!(coll2.wait(), vec!["x"]);
+        assert_eq!(late.wait(), vec!["d"]);
+    }
+
+    #[test]
+    fn test_zip_drains_queue_after_end() {
+        let sink1 = Stream::sink();
+        let sink2 = Stream::sink();
+        let zipped = sink1.stream().zip(&sink2.stream());
+        let coll = zipped.collect();
+        sink1.update(1);
+        sink1.update(2);
+        sink1.update(3);
+        sink1.end();
+        sink2.update('a');
+        assert!(!zipped.is_ended());
+        sink2.update('b');
+        sink2.end(); // nothing more for 3
+        assert_eq!(coll.wait(), vec![(1, 'a'), (2, 'b')]);
+    }
+
+    #[test]
+    fn test_combine8_heterogeneous() {
+        let s1 = Stream::sink();
+        let s2 = Stream::sink();
+        let s3 = Stream::sink();
+        let s4 = Stream::sink();
+        let s5 = Stream::sink();
+        let s6 = Stream::sink();
+        let s7 = Stream::sink();
+        let s8 = Stream::sink();
+        let comb = Stream::combine8(
+            &s1.stream(),
+            &s2.stream(),
+            &s3.stream(),
+            &s4.stream(),
+            &s5.stream(),
+            &s6.stream(),
+            &s7.stream(),
+            &s8.stream(),
+        );
+        let coll = comb.collect();
+        s1.update(1_u8);
+        s2.update("two");
+        s3.update(3.0_f32);
+        s4.update('4');
+        s5.update(5_i64);
+        s6.update(true);
+        s7.update(7_usize);
+        s8.update(String::from("eight")); // all have a value
+        s1.update(10);
+        s1.end();
+        s2.end();
+        s3.end();
+        s4.end();
+        s5.end();
+        s6.end();
+        s7.end();
+        s8.end();
+        let all = coll.wait();
+        assert_eq!(all.len(), 2);
+        assert_eq!(all[0].0, 1);
+        assert_eq!(all[1].0, 10);
+        assert_eq!(all[1].1, "two");
+        assert_eq!(all[1].7, "eight");
+    }
+
+    #[test]
+    fn test_pairwise_releases_previous_on_end() {
+        let value = Arc::new(42);
+        let sink = Stream::sink();
+        let pairs = sink.stream().pairwise();
+        let coll = pairs.collect();
+        sink.update(value.clone());
+        assert_eq!(Arc::strong_count(&value), 2);
+        sink.end();
+        // the only event formed no pair, and is let go of
+        assert_eq!(Arc::strong_count(&value), 1);
+        assert_eq!(coll.wait(), vec![]);
+    }
+
+    #[test]
+    fn test_zip3_lockstep() {
+        let sink1 = Stream::sink();
+        let sink2 = Stream::sink();
+        let sink3 = Stream::sink();
+        let zipped = Stream::zip3(&sink1.stream(), &sink2.stream(), &sink3.stream());
+        let coll = zipped.collect();
+        sink1.update(1);
+        sink1.update(2);
+        sink2.update("a");
+        sink3.update('x'); // (1, "a", 'x')
+        sink2.update("b");
+        sink1.end(); // 2 is still queued
+        sink3.update('y'); // (2, "b", 'y'), and nothing more from sink1
+        sink2.update("c");
+        assert!(zipped.is_ended());
+        assert_eq!(coll.wait(), vec![(1, "a", 'x'), (2, "b", 'y')]);
+    }
+
+    #[test]
+    fn test_zip8_types() {
+        let s: Vec<Sink<u8>> = (0..7).map(|_| Stream::sink()).collect();
+        let last: Sink<&str> = Stream::sink();
+        let zipped = Stream::zip8(
+            &s[0].stream(),
+            &s[1].stream(),
+            &s[2].stream(),
+            &s[3].stream(),
+            &s[4].stream(),
+            &s[5].stream(),
+            &s[6].stream(),
+            &last.stream(),
+        );
+        let coll = zipped.collect();
+        for (i, sink) in s.iter().enumerate() {
+            sink.update(i as u8);
+        }
+        last.update("done");
+        last.end();
+        assert_eq!(coll.wait(), vec![(0, 1, 2, 3, 4, 5, 6, "done")]);
+    }
+
+    #[test]
+    fn test_pairwise_no_memory() {
+        let sink = Stream::sink();
+        let pairs = sink.stream().pairwise();
+        assert!(!pairs.has_memory());
+        let coll1 = pairs.collect();
+        sink.update(1);
+        sink.update(2);
+        // a late subscriber doesn't get the last pair
+        let coll2 = pairs.collect();
+        sink.update(3);
+        sink.end();
+        assert_eq!(coll1.wait(), vec![(1, 2), (2, 3)]);
+        assert_eq!(coll2.wait(), vec![(2, 3)]);
+    }
+
+    #[test]
+    fn test_dedupe_ptr_snapshots() {
+        let sink = Stream::sink();
+        let deduped = sink.stream().dedupe_ptr();
+        let coll = deduped.collect();
+        let first = Arc::new(NoClone(1));
+        let second = Arc::new(NoClone(1));
+        sink.update(first.clone());
+        sink.update(first.clone());
+        sink.update(second.clone());
+        sink.update(second.clone());
+        sink.update(first.clone());
+        sink.end();
+        let out = coll.wait();
+        assert_eq!(out.len(), 3);
+        assert!(Arc::ptr_eq(&out[0], &first));
+        assert!(Arc::ptr_eq(&out[1], &second));
+        assert!(Arc::ptr_eq(&out[2], &first));
+    }
+
+    #[test]
+    fn test_buffer_count_exact_multiple() {
+        let sink = Stream::sink();
+        let batched = sink.stream().buffer_count(2);
+        let coll = batched.collect();
+        for i in 0..4 {
+            sink.update(i);
+        }
+        sink.end();
+        assert_eq!(coll.wait(), vec![vec![0, 1], vec![2, 3]]);
+    }
+
+    #[test]
+    fn test_buffer_count_empty_stream() {
+        let sink: Sink<u32> = Stream::sink();
+        let batched = sink.stream().buffer_count(3);
+        let coll = batched.collect();
+        sink.end();
+        assert_eq!(coll.wait(), Vec::<Vec<u32>>::new());
+    }
+
+    #[test]
+    #[should_panic(expected = "buffer_count() with a size of 0")]
+    fn test_buffer_count_zero() {
+        let sink: Sink<u32> = Stream::sink();
+        sink.stream().buffer_count(0);
+    }
+
+    #[test]
+    #[should_panic(expected = "chunk() with a size of 0")]
+    fn test_chunk_zero() {
+        let sink: Sink<u32> = Stream::sink();
+        sink.stream().chunk(0);
+    }
+
+    #[test]
+    fn test_chunk_exact_releases_tail() {
+        let value = Arc::new(1);
+        let sink = Stream::sink();
+        let chunked = sink.stream().chunk_exact(2);
+        let coll = chunked.collect();
+        sink.update(value.clone());
+        sink.end();
+        assert_eq!(Arc::strong_count(&value), 1);
+        assert!(coll.wait().is_empty());
+    }
+
+    #[test]
+    fn test_zip2_generated() {
+        let sink1 = Stream::sink();
+        let sink2 = Stream::sink();
+        let zipped = Stream::zip2(&sink1.stream(), &sink2.stream());
+        let coll = zipped.collect();
+        sink1.update(1);
+        sink2.update('a');
+        sink2.update('b');
+        sink1.end(); // nothing more for 'b'
+        assert_eq!(coll.wait(), vec![(1, 'a')]);
+    }
+
+    #[test]
+    fn test_window_count_gaps() {
+        let sink = Stream::sink();
+        let windows = sink.stream().window_count(2, 3);
+        let coll = windows.collect();
+        for i in 0..8 {
+            sink.update(i);
+        }
+        sink.end();
+        assert_eq!(coll.wait(), vec![vec![0, 1], vec![3, 4], vec![6, 7]]);
+    }
+
+    #[test]
+    fn test_window_count_size_one() {
+        let sink = Stream::sink();
+        let every = sink.stream().window_count(1, 1);
+        let every_other = sink.stream().window_count(1, 2);
+        let coll1 = every.collect();
+        let coll2 = every_other.collect();
+        for i in 0..4 {
+            sink.update(i);
+        }
+        sink.end();
+        assert_eq!(coll1.wait(), vec![vec![0], vec![1], vec![2], vec![3]]);
+        assert_eq!(coll2.wait(), vec![vec![0], vec![2]]);
+    }
+
+    #[test]
+    fn test_window_count_no_partial() {
+        let sink = Stream::sink();
+        let windows = sink.stream().window_count(3, 2);
+        let coll = windows.collect();
+        for i in 0..6 {
+            sink.update(i);
+        }
+        sink.end();
+        // [4, 5] is never full
+        assert_eq!(coll.wait(), vec![vec![0, 1, 2], vec![2, 3, 4]]);
+    }
+
+    #[test]
+    fn test_partition_predicate_once() {
+        let calls = Arc::new(AtomicUsize::new(0));
+        let calls_clone = calls.clone();
+        let sink = Stream::sink();
+        let (big, small) = sink.stream().partition(move |v: &u32| {
+            calls_clone.fetch_add(1, Ordering::SeqCst);
+            *v > 10
+        });
+        let coll1 = big.collect();
+        let coll2 = small.collect();
+        sink.update(5);
+        sink.update(50);
+        sink.end();
+        assert_eq!(calls.load(Ordering::SeqCst), 2);
+        assert_eq!(coll1.wait(), vec![50]);
+        assert_eq!(coll2.wait(), vec![5]);
+    }
+
+    #[test]
+    fn test_enumerate_counts_per_derived_stream() {
+        let sink = Stream::sink();
+        let stream = sink.stream();
+        let first = stream.enumerate();
+        let coll1 = first.collect();
+        sink.update("a");
+        let second = stream.enumerate();
+        let coll2 = second.collect();
+        sink.update("b");
+        sink.end();
+        assert_eq!(coll1.wait(), vec![(0, "a"), (1, "b")]);
+        assert_eq!(coll2.wait(), vec![(0, "b")]);
+    }
+
+    #[test]
+    fn test_filter_with_state_end_passes() {
+        let sink = Stream::sink();
+        let filtered = sink.stream().filter_with_state((), |_, _| false);
+        let coll = filtered.collect();
+        sink.update(1);
+        sink.end();
+        assert_eq!(coll.wait(), Vec::<i32>::new());
+        assert!(filtered.is_ended());
+    }
+
+    #[test]
+    fn test_select_seeded_from_memory() {
+        let sink = Stream::sink();
+        let state = sink.stream().remember();
+        sink.update((1, "a"));
+        let sel = state.select(|(_, s)| *s);
+        // the same projection as the remembered value is suppressed
+        sink.update((2, "a"));
+        let coll1 = sel.collect();
+        sink.update((3, "b"));
+        // late subscriber gets the current projection
+        let coll2 = sel.collect();
+        sink.update((4, "b"));
+        sink.end();
+        assert_eq!(coll1.wait(), vec!["a", "b"]);
+        assert_eq!(coll2.wait(), vec!["b"]);
+    }
+
+    #[test]
+    fn test_select_no_memory() {
+        let sink = Stream::sink();
+        let sel = sink.stream().select(|v: &u32| v / 10);
+        assert!(!sel.has_memory());
+        let coll = sel.collect();
+        sink.update(1);
+        sink.update(5);
+        sink.update(12);
+        sink.update(3);
+        sink.end();
+        assert_eq!(coll.wait(), vec![0, 1, 0]);
+    }
+
+    #[test]
+    fn test_concat_empty_is_ended() {
+        let concat: Stream<u32> = Stream::concat(vec![]);
+        assert!(concat.is_ended());
+        assert_eq!(concat.collect().wait(), vec![]);
+    }
+
+    #[test]
+    fn test_concat_ended_and_remembered() {
+        let sink1 = Stream::sink();
+        let sink2 = Stream::sink();
+        let sink3 = Stream::sink();
+        let ended = sink1.stream();
+        sink1.end();
+        let rem = sink3.stream().remember();
+        sink3.update(30);
+        let concat = Stream::concat(vec![ended, sink2.stream(), rem]);
+        let coll = concat.collect();
+        sink2.update(20);
+        sink3.update(31); // lost, sink2 hasn't ended
+        sink2.end();
+        sink3.update(32);
+        sink3.end();
+        assert_eq!(coll.wait(), vec![20, 31, 32]);
+    }
+
+    #[test]
+    fn test_concat_drop_unsubscribes() {
+        let sink1: Sink<u32> = Stream::sink();
+        let sink2: Sink<u32> = Stream::sink();
+        let concat = Stream::concat(vec![sink1.stream(), sink2.stream()]);
+        sink1.end();
+        assert_eq!(sink2.inner.lock().listener_count(), 1);
+        drop(concat);
+        assert_eq!(sink2.inner.lock().listener_count(), 0);
+    }
+
+    #[test]
+    fn test_concat_subscribes_in_turn() {
+        let sink1 = Stream::sink();
+        let sink2 = Stream::sink();
+        let concat = Stream::concat(vec![sink1.stream(), sink2.stream()]);
+        let coll = concat.collect();
+        // nothing is queued for the second stream before its turn
+        assert_eq!(sink2.inner.lock().listener_count(), 0);
+        sink2.update(10);
+        sink1.update(0);
+        sink1.end();
+        assert_eq!(sink2.inner.lock().listener_count(), 1);
+        sink2.update(11);
+        sink2.end();
+        assert_eq!(coll.wait(), vec![0, 11]);
+    }
+
+    #[test]
+    fn test_map_does_not_clone() {
+        let counter = bench_support::CloneCounter::new();
+        let sink = Stream::sink();
+        let map = sink
+            .stream()
+            .map(|c: &bench_support::Counted<u32>| c.value * 2);
+        let coll = map.collect();
+        sink.update(counter.wrap(1));
+        sink.update(counter.wrap(2));
+        sink.end();
+        assert_eq!(coll.wait(), vec![2, 4]);
+        assert_eq!(counter.count(), 0);
+    }
+
+    #[test]
+    fn test_remember_clones_once_per_event() {
+        let counter = bench_support::CloneCounter::new();
+        let sink = Stream::sink();
+        let rem = sink.stream().remember();
+        let _sub1 = rem.subscribe(|_| {});
+        let _sub2 = rem.subscribe(|_| {});
+        sink.update(counter.wrap(1));
+        sink.update(counter.wrap(2));
+        assert_eq!(counter.count(), 2);
+    }
+
+    #[test]
+    fn test_partition_halves_independent() {
+        let sink = Stream::sink();
+        let (even, odd) = sink.stream().partition(|v| v % 2 == 0);
+        let coll1 = even.collect();
+        let coll2 = odd.collect();
+        sink.update(0);
+        sink.update(1);
+        let mut set = SubscriptionSet::new();
+        set.push(&even);
+        set.clear();
+        drop(even);
+        sink.update(2);
+        sink.update(3);
+        sink.end();
+        assert_eq!(coll1.take(), vec![0]);
+        assert_eq!(coll2.wait(), vec![1, 3]);
+    }
+
+    #[test]
+    fn test_race_drops_losers() {
+        let sink1: Sink<u32> = Stream::sink();
+        let sink2: Sink<u32> = Stream::sink();
+        let race = Stream::race(vec![sink1.stream(), sink2.stream()]);
+        let coll = race.collect();
+        assert_eq!(sink2.inner.lock().listener_count(), 1);
+        sink1.update(1);
+        assert_eq!(sink2.inner.lock().listener_count(), 0);
+        sink2.end(); // lost
+        sink1.update(2);
+        sink1.end();
+        assert_eq!(coll.wait(), vec![1, 2]);
+    }
+
+    #[test]
+    fn test_race_remembered_wins_at_once() {
+        let sink1: Sink<u32> = Stream::sink();
+        let sink2: Sink<u32> = Stream::sink();
+        let rem = sink1.stream().remember();
+        sink1.update(1);
+        let race = Stream::race(vec![sink2.stream(), rem, sink2.stream()]);
+        assert_eq!(sink2.inner.lock().listener_count(), 0);
+        let coll = race.collect();
+        sink2.update(10);
+        sink1.update(2);
+        sink1.end();
+        assert_eq!(coll.wait(), vec![2]);
+    }
+
+    #[test]
+    fn test_race_empty_is_ended() {
+        let race: Stream<u32> = Stream::race(vec![]);
+        assert!(race.is_ended());
+    }
+
+    #[test]
+    fn test_group_by_children_concurrently() {
+        let sink = Stream::sink();
+        let grouped = sink.stream().group_by(|v: &u32| v % 2);
+        let children = Arc::new(Mutex::new(vec![]));
+        let children_clone = children.clone();
+        let _sub = grouped.subscribe(move |g| {
+            if let Some((k, child)) = g {
+                children_clone.lock().unwrap().push((*k, child.clone()));
+            }
+        });
+        sink.update(0);
+        sink.update(1);
+        // late subscribers get the latest event of each child
+        let (keys, handles): (Vec<_>, Vec<_>) = children
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|(k, child)| {
+                let coll = child.collect();
+                let k = *k;
+                (k, std::thread::spawn(move || coll.wait()))
+            })
+            .unzip();
+        sink.update(2);
+        sink.update(3);
+        sink.update(4);
+        sink.end();
+        let all: Vec<_> = handles.into_iter().map(|h| h.join().unwrap()).collect();
+        assert_eq!(keys, vec![0, 1]);
+        assert_eq!(all, vec![vec![0, 2, 4], vec![1, 3]]);
+    }
+
+    #[test]
+    fn test_for_each_concurrent_dropped_subscription_keeps_listening() {
+        let sink1 = Stream::sink();
+        let sink2 = Stream::sink();
+        let calls = Arc::new(AtomicUsize::new(0));
+        let calls_clone = calls.clone();
+        let sub = sink1
+            .stream()
+            .for_each_concurrent(vec![sink2.stream()], move |_: &u32| {
+                calls_clone.fetch_add(1, Ordering::SeqCst);
+            });
+        drop(sub);
+        sink1.update(1);
+        sink2.update(2);
+        sink2.end();
+        sink1.update(3);
+        assert_eq!(calls.load(Ordering::SeqCst), 3);
+    }
+
+    #[test]
+    fn test_take_until_notifier_end_and_remembered() {
+        let sink = Stream::sink();
+        let notifier: Sink<u32> = Stream::sink();
+        let taken = sink.stream().take_until(&notifier.stream());
+        let coll = taken.collect();
+        sink.update(0);
+        notifier.end();
+        sink.update(1);
+        assert_eq!(coll.wait(), [0]);
+
+        // a notifier that already has a value ends straight away
+        let sink = Stream::sink();
+        let taken = sink.stream().take_until(&Stream::of(()));
+        assert!(taken.is_ended());
+        sink.update(0);
+        assert_eq!(taken.collect().wait(), Vec::<u32>::new());
+    }
+
+    #[test]
+    fn test_take_until_releases_notifier() {
+        let sink: Sink<u32> = Stream::sink();
+        let notifier: Sink<()> = Stream::sink();
+        let trigger = notifier.stream();
+        let coll = sink.stream().take_until(&trigger).collect();
+        assert_eq!(trigger.inner.lock().listener_count(), 1);
+        sink.update(1);
+        sink.end();
+        // the notifier listener is gone once the result has ended
+        assert_eq!(trigger.inner.lock().listener_count(), 0);
+        assert_eq!(coll.wait(), vec![1]);
+
+        let sink: Sink<u32> = Stream::sink();
+        let source = sink.stream();
+        let coll = source.take_until(&trigger).collect();
+        notifier.update(());
+        assert_eq!(trigger.inner.lock().listener_count(), 0);
+        assert_eq!(source.inner.lock().listener_count(), 0);
+        assert_eq!(coll.wait(), Vec::<u32>::new());
+    }
+
+    #[test]
+    fn test_split_first_halves_independent() {
+        let sink = Stream::sink();
+        let (header, payload) = sink.stream().split_first();
+        let coll = payload.collect();
+        drop(header);
+        sink.update(0);
+        sink.update(1);
+        sink.update(2);
+        sink.end();
+        assert_eq!(coll.wait(), vec![1, 2]);
+
+        let sink = Stream::sink();
+        let (header, payload) = sink.stream().split_first();
+        let coll = header.collect();
+        let mut set = SubscriptionSet::new();
+        set.push(&payload);
+        set.clear();
+        sink.update(0);
+        sink.update(1);
+        assert_eq!(coll.wait(), vec![0]);
+    }
+
+    #[test]
+    fn test_take_last_short_and_zero() {
+        let sink = Stream::sink();
+        let tail = sink.stream().take_last(3);
+        let none = sink.stream().take_last(0);
+        let coll1 = tail.collect();
+        let coll2 = none.collect();
+        sink.update(0);
+        sink.update(1);
+        assert!(!tail.is_ended());
+        sink.end();
+        assert_eq!(coll1.wait(), vec![0, 1]);
+        assert_eq!(coll2.wait(), Vec::<u32>::new());
+    }
+
+    #[test]
+    fn test_imitator_memory_not_copied() {
+        let imitator = Stream::imitator();
+        let stream = imitator.stream();
+        let sink = Stream::sink();
+        let rem = sink.stream().remember();
+        sink.update(1);
+        imitator.imitate(&rem);
+        // the imitated memory isn't copied
+        assert!(!stream.has_memory());
+        let coll = stream.collect();
+        sink.update(2);
+        assert_eq!(coll.take(), vec![2]);
+    }
+
+    #[test]
+    fn test_skip_until_drops_notifier() {
+        let sink = Stream::sink();
+        let start: Sink<u32> = Stream::sink();
+        let started = sink.stream().skip_until(&start.stream());
+        let coll = started.collect();
+        assert_eq!(start.inner.lock().listener_count(), 1);
+        start.update(1);
+        assert_eq!(start.inner.lock().listener_count(), 0);
+        sink.update(0);
+        start.end();
+        sink.update(1);
+        sink.end();
+        assert_eq!(coll.wait(), vec![0, 1]);
+    }
+
+    #[test]
+    fn test_skip_until_never_and_remembered() {
+        let sink = Stream::sink();
+        let skipped = sink.stream().skip_until(&Stream::<()>::never());
+        let opened = sink.stream().skip_until(&Stream::of(()));
+        let coll1 = skipped.collect();
+        let coll2 = opened.collect();
+        sink.update(0);
+        sink.end();
+        assert_eq!(coll1.wait(), Vec::<u32>::new());
+        assert_eq!(coll2.wait(), vec![0]);
+    }
+
+    #[test]
+    fn test_skip_until_dropped_before_notifier() {
+        let sink: Sink<u32> = Stream::sink();
+        let start: Sink<u32> = Stream::sink();
+        let started = sink.stream().skip_until(&start.stream());
+        drop(started);
+        assert_eq!(start.inner.lock().listener_count(), 0);
+    }
+
+    #[test]
+    fn test_drop_last_short_and_zero() {
+        let sink = Stream::sink();
+        let all = sink.stream().drop_last(0);
+        let none = sink.stream().drop_last(3);
+        let coll1 = all.collect();
+        let coll2 = none.collect();
+        sink.update(0);
+        sink.update(1);
+        sink.end();
+        assert_eq!(coll1.wait(), vec![0, 1]);
+        assert_eq!(coll2.wait(), Vec::<u32>::new());
+    }
+
+    #[test]
+    fn test_from_iter_memory_and_collect() {
+        let stream: Stream<u32> = (0..3).collect();
+        assert!(stream.has_memory());
+        assert!(stream.is_ended());
+        let calls = Arc::new(AtomicUsize::new(0));
+        let calls_clone = calls.clone();
+        let _sub = stream.subscribe(move |v| {
+            if v.is_some() {
+                calls_clone.fetch_add(1, Ordering::SeqCst);
+            }
+        });
+        assert_eq!(calls.load(Ordering::SeqCst), 3);
+        assert_eq!(stream.collect().wait(), vec![0, 1, 2]);
+
+        let empty = Stream::from_iter(Vec::<u32>::new());
+        assert_eq!(empty.collect().wait(), vec![]);
+    }
+
+    #[test]
+    fn test_merge_all_from_inner_ends() {
+        let batches: Sink<Vec<Stream<u32>>> = Stream::sink();
+        let sink1 = Stream::sink();
+        let merged = batches.stream().merge_all_from_inner();
+        let coll = merged.collect();
+        batches.update(vec![]);
+        batches.update(vec![sink1.stream()]);
+        sink1.update(1);
+        sink1.end();
+        assert!(!merged.is_ended());
+        batches.update(vec![Stream::from_iter(vec![5])]);
+        batches.end();
+        assert_eq!(coll.wait(), vec![1, 5]);
+    }
+
+    #[test]
+    fn test_first_value_end_and_memory() {
+        let sink: Sink<u32> = Stream::sink();
+        let stream = sink.stream();
+        let ended = std::thread::spawn(move || stream.first_value());
+        std::thread::sleep(std::time::Duration::from_millis(10));
+        sink.end();
+        assert_eq!(ended.join().unwrap(), None);
+
+        let sink = Stream::sink();
+        let rem = sink.stream().remember();
+        sink.update(7);
+        assert_eq!(rem.first_value(), Some(7));
+        // the subscription is gone after the first value
+        assert_eq!(rem.inner.lock().listener_count(), 0);
+    }
+
+    #[test]
+    fn test_subscribe_deferred_replay_race() {
+        let sink = Stream::sink();
+        let rem = sink.stream().remember();
+        sink.update(1);
+
+        let seen = Arc::new(Mutex::new(vec![]));
+        let seen2 = seen.clone();
+        let _sub = rem.subscribe_deferred_replay(move |v| seen2.lock().unwrap().push(v.cloned()));
+
+        // a live update from another thread delivers the queue
+        let sink = std::thread::spawn(move || {
+            sink.update(2);
+            sink
+        })
+        .join()
+        .unwrap();
+        assert_eq!(*seen.lock().unwrap(), vec![Some(1), Some(2)]);
+
+        sink.update(3);
+        assert_eq!(*seen.lock().unwrap(), vec![Some(1), Some(2), Some(3)]);
+
+        flush_deferred();
+        sink.end();
+        assert_eq!(*seen.lock().unwrap(), vec![Some(1), Some(2), Some(3), None]);
+    }
+
+    #[test]
+    fn test_subscribe_deferred_replay_unsubscribe() {
+        let sink = Stream::sink();
+        let rem = sink.stream().remember();
+        sink.update(1);
+
+        let seen = Arc::new(Mutex::new(vec![]));
+        let seen2 = seen.clone();
+        let sub = rem.subscribe_deferred_replay(move |v| seen2.lock().unwrap().push(v.cloned()));
+        sub.unsubscribe();
+        flush_deferred();
+        sink.update(2);
+        assert!(seen.lock().unwrap().is_empty());
+
+        // an ended stream replays only its end on flush
+        sink.end();
+        let seen2 = seen.clone();
+        let _sub = rem.subscribe_deferred_replay(move |v| seen2.lock().unwrap().push(v.cloned()));
+        assert!(seen.lock().unwrap().is_empty());
+        flush_deferred();
+        assert_eq!(*seen.lock().unwrap(), vec![None]);
+    }
+
+    #[test]
+    fn test_then_concat_chained() {
+        let sink = Stream::sink();
+        let chained = sink
+            .stream()
+            .then_concat(|| Stream::from_iter(vec![2, 3]))
+            .then_concat(|| Stream::from_iter(vec![4]));
+        let coll = chained.collect();
+        sink.update(1);
+        sink.end();
+        assert_eq!(coll.wait(), vec![1, 2, 3, 4]);
+
+        // an ended stream moves on straight away
+        let ended = Stream::from_iter(vec![1]).then_concat(|| Stream::from_iter(vec![2]));
+        assert!(ended.is_ended());
+    }
+
+    #[test]
+    fn test_fold2_interleaved() {
+        let sink1 = Stream::sink();
+        let sink2 = Stream::sink();
+        let folded = Stream::fold2(
+            &sink1.stream(),
+            &sink2.stream(),
+            String::new(),
+            |s, a: &u32| format!("{}a{}", s, a),
+            |s, b: &u32| format!("{}b{}", s, b),
+        );
+        let coll = folded.collect();
+        sink1.update(1);
+        sink2.update(2);
+        sink2.update(3);
+        sink1.end();
+        // the other stream keeps folding
+        sink2.update(4);
+        assert!(!folded.is_ended());
+        sink2.end();
+        assert_eq!(coll.wait(), vec!["", "a1", "a1b2", "a1b2b3", "a1b2b3b4"]);
+    }
+
+    #[test]
+    fn test_fold2_threads() {
+        let sink1 = Stream::sink();
+        let sink2 = Stream::sink();
+        let count = Stream::fold2(
+            &sink1.stream(),
+            &sink2.stream(),
+            0,
+            |n, _: &u32| n + 1,
+            |n, _: &u32| n + 1,
+        );
+        let coll = count.collect();
+        let handles: Vec<_> = vec![sink1, sink2]
+            .into_iter()
+            .map(|sink| {
+                std::thread::spawn(move || {
+                    for i in 0..1000 {
+                        sink.update(i);
+                    }
+                    sink.end();
+                })
+            })
+            .collect();
+        for h in handles {
+            h.join().unwrap();
+        }
+        assert_eq!(coll.wait(), (0..=2000).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn test_count_no_events() {
+        let sink: Sink<u32> = Stream::sink();
+        let coll = sink.stream().count().collect();
+        sink.end();
+        assert_eq!(coll.wait(), vec![0]);
+
+        // a remembered value counts
+        let sink = Stream::sink();
+        let rem = sink.stream().remember();
+        sink.update(1);
+        let coll = rem.count().collect();
+        sink.update(2);
+        sink.end();
+        assert_eq!(coll.wait(), vec![2]);
+    }
+
+    #[test]
+    fn test_range_bounds() {
+        let all = |s: Stream<u8>| s.collect().wait();
+        assert_eq!(all(Stream::range(3..3)), Vec::<u8>::new());
+        assert_eq!(
+            all(Stream::range((Bound::Included(3), Bound::Excluded(0)))),
+            Vec::<u8>::new()
+        );
+        assert_eq!(all(Stream::range(0..0)), Vec::<u8>::new());
+        assert_eq!(all(Stream::range(..2)), vec![0, 1]);
+        assert_eq!(all(Stream::range(253..=u8::MAX)), vec![253, 254, 255]);
+        assert_eq!(
+            all(Stream::range((Bound::Excluded(1), Bound::Included(3)))),
+            vec![2, 3]
+        );
+        assert_eq!(
+            all(Stream::range((Bound::Excluded(255), Bound::Included(255)))),
+            Vec::<u8>::new()
+        );
+    }
+
+    #[test]
+    fn test_collector_len_threads() {
+        let sink = Arc::new(Stream::sink());
+        let coll = Arc::new(sink.stream().collect());
+        let monitor = {
+            let coll = coll.clone();
+            std::thread::spawn(move || {
+                let mut last = 0;
+                while !coll.ended_flag() {
+                    let len = coll.len();
+                    assert!(len >= last);
+                    last = len;
+                }
+            })
+        };
+        let producers: Vec<_> = (0..4)
+            .map(|_| {
+                let sink = sink.clone();
+                std::thread::spawn(move || {
+                    for i in 0..1000 {
+                        sink.update(i);
+                    }
+                })
+            })
+            .collect();
+        for p in producers {
+            p.join().unwrap();
+        }
+        sink.end_shared();
+        monitor.join().unwrap();
+        let coll = Arc::try_unwrap(coll).ok().unwrap();
+        assert_eq!(coll.len(), 4000);
+        assert_eq!(coll.wait().len(), 4000);
+    }
+
+    #[test]
+    fn test_or_value_until_first() {
+        let sink = Stream::sink();
+        let stream = sink.stream().or_value_until_first(0);
+
+        // before the first event
+        let before = stream.collect();
+        sink.update(1);
+        sink.update(2);
+
+        // after real data exists, no placeholder
+        let after = stream.collect();
+        sink.update(3);
+        sink.end();
+
+        assert_eq!(before.wait(), vec![0, 1, 2, 3]);
+        assert_eq!(after.wait(), vec![2, 3]);
+
+        // the source remembering a value replaces the placeholder straight away
+        let sink = Stream::sink();
+        let rem = sink.stream().remember();
+        sink.update(5);
+        let stream = rem.or_value_until_first(0);
+        assert_eq!(stream.collect().take(), vec![5]);
+    }
+
+    #[test]
+    fn test_flat_map_single_node() {
+        let sink = Stream::sink();
+        let inner_sink = Stream::sink();
+        let inner_stream = inner_sink.stream();
+        let flat = sink
+            .stream()
+            .flat_map(move |v: &u32| inner_stream.map_to(*v));
+        let coll = flat.collect();
+        sink.update(1);
+        inner_sink.update(());
+        // the switched stream is the only listener on the source
+        assert_eq!(sink.inner.lock().listener_count(), 1);
+        sink.update(2);
+        // the previous mapped stream is unsubscribed
+        assert_eq!(inner_sink.inner.lock().listener_count(), 1);
+        inner_sink.update(());
+        inner_sink.end();
+        assert!(!flat.is_ended());
+        sink.end();
+        assert_eq!(coll.wait(), vec![1, 2]);
+    }
+
+    #[test]
+    fn test_flatten_with_tombstone_releases_inner() {
+        let sink1 = Stream::sink();
+        let sink2 = Stream::sink();
+        let flat = sink1.stream().flatten_with_tombstone(0);
+        let coll = flat.collect();
+        sink1.update(sink2.stream());
+        sink2.update(1);
+        assert_eq!(sink2.inner.lock().listener_count(), 1);
+        sink2.update(0);
+        // unsubscribed once the update has propagated
+        assert_eq!(sink2.inner.lock().listener_count(), 0);
+        sink2.update(2);
+
+        // a remembered tombstone
+        let sink3 = Stream::sink();
+        let rem = sink3.stream().remember();
+        sink3.update(0);
+        sink1.update(rem);
+        sink3.update(3);
+        assert_eq!(sink3.inner.lock().listener_count(), 0);
+
+        sink1.end();
+        assert_eq!(coll.wait(), vec![1]);
+    }
+
+    #[test]
+    fn test_shared_map_three_consumers() {
+        let sink = Stream::sink();
+        let calls = Arc::new(AtomicUsize::new(0));
+        let consumer = |source: Stream<u32>| {
+            let calls = calls.clone();
+            source
+                .shared_map("doubled", move |v| {
+                    calls.fetch_add(1, Ordering::SeqCst);
+                    v * 2
+                })
+                .collect()
+        };
+        // each consumer with its own clone of the source
+        let colls: Vec<_> = (0..3).map(|_| consumer(sink.stream())).collect();
+        sink.update(1);
+        sink.update(2);
+        assert_eq!(calls.load(Ordering::SeqCst), 2);
+        // other tags are other streams
+        let other = sink.stream().shared_map("other", |v| *v).collect();
+        let source = sink.stream();
+        sink.end();
+        for coll in colls {
+            assert_eq!(coll.wait(), vec![2, 4]);
         }
+        assert_eq!(other.wait(), Vec::<u32>::new());
+        // the registry is released on end
+        assert!(source.inner.lock().shared("doubled").is_none());
     }
-}
 
-#[cfg(test)]
-mod test {
-    use super::*;
-    use std::sync::mpsc::sync_channel;
+    #[test]
+    #[should_panic(expected = "shared_map() tag used with another type")]
+    fn test_shared_map_type_mismatch() {
+        let sink: Sink<u32> = Stream::sink();
+        let _a = sink.stream().shared_map("tag", |v| *v);
+        let _b = sink.stream().shared_map("tag", |v| v.to_string());
+    }
 
     #[test]
-    fn test_sink_auto_traits() {
-        fn f<X: Sync + Send>(_: X) {}
-        let sink: Sink<u32> = Sink::new();
-        f(sink);
+    fn test_first_unsubscribes() {
+        let sink = Stream::sink();
+        let first = sink.stream().first();
+        let coll = first.collect();
+        assert_eq!(sink.inner.lock().listener_count(), 1);
+        sink.update(1);
+        assert_eq!(sink.inner.lock().listener_count(), 0);
+        sink.update(2);
+        assert_eq!(coll.wait(), vec![1]);
+
+        // remembered value
+        let rem = sink.stream().remember();
+        sink.update(3);
+        let first = rem.first();
+        assert!(first.is_ended());
+        assert_eq!(rem.inner.lock().listener_count(), 0);
+
+        // ended without events
+        let sink: Sink<u32> = Stream::sink();
+        let coll = sink.stream().first().collect();
+        sink.end();
+        assert_eq!(coll.wait(), vec![]);
     }
 
     #[test]
-    fn test_stream_auto_traits() {
-        fn f<X: Sync + Send + Clone>(_: X) {}
-        struct Foo(); // not clonable, but Stream<Foo> should be
-        let sink: Sink<Foo> = Sink::new();
-        f(sink.stream());
+    fn test_map_end_none() {
+        let sink = Stream::sink();
+        let mut ends = 0;
+        let mapped = sink.stream().map_end(move || {
+            ends += 1;
+            assert_eq!(ends, 1);
+            None
+        });
+        let coll = mapped.collect();
+        sink.update(1);
+        sink.end();
+        assert_eq!(coll.wait(), vec![1]);
+    }
+    /// Run a producer on another thread once the stream has a listener.
+    fn produce_when_listened<F>(sink: Sink<u32>, f: F) -> std::thread::JoinHandle<()>
+    where
+        F: FnOnce(Sink<u32>) + Send + 'static,
+    {
+        std::thread::spawn(move || {
+            while sink.inner.lock().listener_count() == 0 {
+                std::thread::yield_now();
+            }
+            f(sink)
+        })
     }
 
     #[test]
-    fn test_subscription_auto_traits() {
-        fn f<X: Sync + Send + Clone>(_: X) {}
-        let sink: Sink<u32> = Sink::new();
-        let sub = sink.stream().subscribe(|_| {});
-        f(sub);
+    fn test_wait_n_threads() {
+        let sink = Stream::sink();
+        let stream = sink.stream();
+        let producer = produce_when_listened(sink, |sink| {
+            for i in 0..5 {
+                sink.update(i);
+            }
+        });
+        assert_eq!(stream.wait_n(3), 3);
+        producer.join().unwrap();
+        // the temporary subscription is gone
+        assert_eq!(stream.inner.lock().listener_count(), 0);
+
+        // early end
+        let sink = Stream::sink();
+        let stream = sink.stream();
+        let producer = produce_when_listened(sink, |sink| {
+            sink.update(0);
+            sink.end();
+        });
+        assert_eq!(stream.wait_n(3), 1);
+        producer.join().unwrap();
     }
 
     #[test]
-    fn test_chained_maps() {
-        let sink: Sink<u32> = Sink::new();
-        // the risk is that the intermediary map drops the subscription
-        // and the entire chain stalls.
-        let map = sink.stream().map(|x| x + 1).map(|x| x * 2);
-        let coll = map.collect();
+    fn test_wait_until_threads() {
+        let sink = Stream::sink();
+        let stream = sink.stream();
+        let producer = produce_when_listened(sink, |sink| {
+            for i in 0..5 {
+                sink.update(i);
+            }
+        });
+        assert_eq!(stream.wait_until(|v| *v == 3), Some(3));
+        producer.join().unwrap();
+        assert_eq!(stream.inner.lock().listener_count(), 0);
+
+        // early end
+        let sink = Stream::sink();
+        let stream = sink.stream();
+        let producer = produce_when_listened(sink, |sink| {
+            sink.update(0);
+            sink.end();
+        });
+        assert_eq!(stream.wait_until(|v| *v == 3), None);
+        producer.join().unwrap();
+    }
+
+    #[test]
+    fn test_merge_with_ends_with_both() {
+        let sink1: Sink<u32> = Stream::sink();
+        let sink2 = Stream::sink();
+        let merged = sink1.stream().merge_with(&sink2.stream());
+        let coll = merged.collect();
+        sink1.end();
+        assert!(!merged.is_ended());
+        sink2.update(1);
+        sink2.end();
+        assert!(merged.is_ended());
+        assert_eq!(coll.wait(), vec![1]);
+    }
+
+    #[test]
+    fn test_element_at_ends_early() {
+        let sink = Stream::sink();
+        let at = sink.stream().element_at(3);
+        let coll = at.collect();
         sink.update(0);
         sink.update(1);
-        sink.update(2);
+        assert_eq!(sink.inner.lock().listener_count(), 1);
         sink.end();
-        assert_eq!(coll.wait(), vec![2, 4, 6]);
+        assert_eq!(coll.wait(), vec![]);
+
+        // a replayed history counts
+        let at = Stream::from_iter(0..10).element_at(3);
+        assert!(at.is_ended());
     }
 
     #[test]
-    fn test_of() {
-        let stream = Stream::of(42);
-        let (tx, rx) = sync_channel(1);
-        stream.subscribe(move |x| tx.send(*x.unwrap()).unwrap());
-        assert_eq!(rx.recv().unwrap(), 42);
+    fn test_interleave_rates() {
+        let sink1 = Stream::sink();
+        let sink2 = Stream::sink();
+        let sink3 = Stream::sink();
+        let turns = Stream::interleave(vec![sink1.stream(), sink2.stream(), sink3.stream()]);
+        let coll = turns.collect();
+        // sink1 fast, sink2 slow, sink3 in between
+        sink1.update(1);
+        sink1.update(2);
+        sink1.update(3); // replaces 2
+        sink2.update(10);
+        sink3.update(20);
+        sink2.end(); // skipped from now on
+        sink3.update(21);
+        sink1.update(4);
+        sink3.end();
+        sink1.update(5);
+        sink1.update(6);
+        assert!(!turns.is_ended());
+        sink1.end();
+        assert_eq!(coll.wait(), vec![1, 10, 20, 3, 21, 4, 5, 6]);
     }
 
     #[test]
-    fn test_imitate() {
-        let sink: Sink<u32> = Sink::new();
-        let imit: Imitator<u32> = Imitator::new();
-        let map = sink.stream().map(|x| x * 2);
-        let coll = imit.stream().collect();
-        imit.imitate(&map);
-        sink.update(0);
+    fn test_interleave_flush_on_end() {
+        let sink1 = Stream::sink();
+        let sink2 = Stream::sink();
+        let sink3 = Stream::sink();
+        let turns = Stream::interleave(vec![sink1.stream(), sink2.stream(), sink3.stream()]);
+        let coll = turns.collect();
+        sink1.update(1);
+        // waiting for sink2
+        sink3.update(30);
+        sink1.update(2);
+        sink1.end();
+        sink3.end();
+        assert_eq!(sink2.inner.lock().listener_count(), 1);
+        // held events are emitted in turn, then the end
+        sink2.end();
+        assert_eq!(coll.wait(), vec![1, 30, 2]);
+
+        let empty: Stream<u32> = Stream::interleave(vec![]);
+        assert!(empty.is_ended());
+    }
+
+    #[test]
+    fn test_zip_with_done_stops_queueing() {
+        let counter = bench_support::CloneCounter::new();
+        let sink1: Sink<u32> = Stream::sink();
+        let sink2 = Stream::sink();
+        let zipped = sink1
+            .stream()
+            .zip_with(&sink2.stream(), |a, b: &bench_support::Counted<u32>| {
+                a + b.value
+            });
+        let coll = zipped.collect();
+        sink1.update(1);
+        sink1.end();
+        sink2.update(counter.wrap(10)); // paired, and nothing more can be paired
+        assert_eq!(sink2.inner.lock().listener_count(), 0);
+        counter.reset();
+        sink2.update(counter.wrap(20));
+        sink2.update(counter.wrap(30));
+        assert_eq!(counter.count(), 0);
+        assert_eq!(coll.wait(), vec![11]);
+    }
+
+    #[test]
+    fn test_zip3_done_stops_queueing() {
+        let counter = bench_support::CloneCounter::new();
+        let sink1: Sink<u32> = Stream::sink();
+        let sink2: Sink<u32> = Stream::sink();
+        let sink3 = Stream::sink();
+        let zipped = Stream::zip3(&sink1.stream(), &sink2.stream(), &sink3.stream())
+            .map(|(a, b, c): &(u32, u32, bench_support::Counted<u32>)| a + b + c.value);
+        let coll = zipped.collect();
+        sink1.update(1);
+        sink2.update(2);
+        sink1.end();
+        sink3.update(counter.wrap(3)); // paired, and nothing more can be paired
+        assert_eq!(sink2.inner.lock().listener_count(), 0);
+        assert_eq!(sink3.inner.lock().listener_count(), 0);
+        counter.reset();
+        sink3.update(counter.wrap(4));
+        sink3.update(counter.wrap(5));
+        assert_eq!(counter.count(), 0);
+        assert_eq!(coll.wait(), vec![6]);
+    }
+
+    #[test]
+    fn test_concat_same_source_no_deadlock() {
+        let sink = Stream::sink();
+        let s = sink.stream();
+        let concat = Stream::concat(vec![s.take(2), s.clone()]);
+        let coll = concat.collect();
         sink.update(1);
         sink.update(2);
+        sink.update(3); // ends take(2), before the second stream is subscribed
+        sink.update(4);
         sink.end();
-        assert_eq!(coll.wait(), vec![0, 2, 4]);
+        assert_eq!(coll.wait(), vec![1, 2, 4]);
     }
 
     #[test]
-    fn test_fold_and_last() {
-        let sink: Sink<u32> = Sink::new();
-        // this potentially creates an edge case where
-        // last might hang on to the rc value that fold has in memory
-        let fold = sink
-            .stream()
-            .fold("|".to_string(), |p, c| format!("{} {}", p, c))
-            .last();
-        let coll = fold.collect();
-        sink.update(42);
+    fn test_concat_ended_streams() {
+        let sink: Sink<u32> = Stream::sink();
+        let s = sink.stream();
         sink.end();
-        assert_eq!(coll.wait(), vec!["| 42".to_string()]);
+        let last = Stream::sink();
+        // moves past the ended streams while subscribing
+        let concat = Stream::concat(vec![s.clone(), s.clone(), last.stream()]);
+        let coll = concat.collect();
+        last.update(1);
+        last.end();
+        assert_eq!(coll.wait(), vec![1]);
     }
 
     #[test]
-    fn test_fold_and_remember() {
-        let sink: Sink<u32> = Sink::new();
-        // this potentially creates an edge case where
-        // remember might hang on to the rc value that fold has in memory
-        let fold = sink
-            .stream()
-            .fold("|".to_string(), |p, c| format!("{} {}", p, c))
-            .remember();
-        let coll = fold.collect();
-        sink.update(42);
+    fn test_then_concat_same_source_no_deadlock() {
+        let sink = Stream::sink();
+        let s = sink.stream();
+        let s2 = s.clone();
+        let both = s.take(1).then_concat(move || s2.clone());
+        let coll = both.collect();
+        sink.update(1);
+        sink.update(2); // ends take(1)
+        sink.update(3);
         sink.end();
-        assert_eq!(coll.wait(), vec!["|".to_string(), "| 42".to_string()]);
+        assert_eq!(coll.wait(), vec![1, 3]);
     }
 
     #[test]
-    fn test_imitate_cycle() {
-        let imitator = Stream::imitator();
+    fn test_subscribe_local_other_thread_no_poison() {
+        let sink = Stream::sink();
+        let stream = sink.stream();
+        let seen = std::rc::Rc::new(std::cell::Cell::new(0));
+        let seen_clone = seen.clone();
+        let _sub = stream.subscribe_local(move |v: Option<&u32>| {
+            if let Some(v) = v {
+                seen_clone.set(*v);
+            }
+        });
+        let res = std::thread::spawn(move || sink.update(1)).join();
+        assert!(res.is_err());
+        // the subscriber was skipped, and the stream is still usable
+        assert_eq!(seen.get(), 0);
+        assert_eq!(stream.inner.lock().listener_count(), 1);
+    }
 
-        let fold = imitator
-            .stream()
-            .fold(1, |p, c| if *c < 10 { p + c } else { p })
-            .dedupe();
+    #[test]
+    fn test_subscribe_local_not_dropped_on_other_thread() {
+        struct OnDrop(Arc<Mutex<Option<std::thread::ThreadId>>>);
+        impl Drop for OnDrop {
+            fn drop(&mut self) {
+                *self.0.lock().unwrap() = Some(std::thread::current().id());
+            }
+        }
+        let dropped = Arc::new(Mutex::new(None));
+        let sink: Sink<u32> = Stream::sink();
+        let on_drop = OnDrop(dropped.clone());
+        let rc = std::rc::Rc::new(0);
+        let _sub = sink.stream().subscribe_local(move |_| {
+            let _ = (&on_drop, &rc);
+        });
+        // the end is skipped, but releases the subscriber on the other thread
+        let res = std::thread::spawn(move || sink.end()).join();
+        assert!(res.is_err());
+        assert_eq!(*dropped.lock().unwrap(), None);
+
+        let sink: Sink<u32> = Stream::sink();
+        let on_drop = OnDrop(dropped.clone());
+        let sub = sink.stream().subscribe_local(move |_| {
+            let _ = &on_drop;
+        });
+        sub.unsubscribe();
+        assert_eq!(*dropped.lock().unwrap(), Some(std::thread::current().id()));
+    }
+
+    #[test]
+    fn test_keep_alive_until_releases_trigger() {
+        let sink: Sink<u32> = Stream::sink();
+        let stop: Sink<()> = Stream::sink();
+        let trigger = stop.stream();
+        let coll = sink.stream().keep_alive_until(&trigger).collect();
+        assert_eq!(trigger.inner.lock().listener_count(), 1);
+        sink.update(1);
+        stop.update(());
+        // the trigger listener is gone once it has fired
+        assert_eq!(trigger.inner.lock().listener_count(), 0);
+        assert_eq!(coll.wait(), vec![1]);
+    }
 
+    #[test]
+    fn test_split_at_zero() {
         let sink = Stream::sink();
+        let (first, rest) = sink.stream().split_at(0);
+        assert!(first.is_ended());
+        let coll = rest.collect();
+        sink.update(1);
+        sink.update(2);
+        sink.end();
+        assert_eq!(first.collect().wait(), vec![]);
+        assert_eq!(coll.wait(), vec![1, 2]);
+    }
 
-        let merge = Stream::merge(vec![fold, sink.stream()]);
-        imitator.imitate(&merge);
+    #[test]
+    fn test_subscribe_deferred_replay_other_thread() {
+        let sink = Stream::sink();
+        let rem = sink.stream().remember();
+        sink.update(0);
 
-        let coll = merge.collect();
+        let seen = Arc::new(Mutex::new(vec![]));
+        let seen2 = seen.clone();
+        let _sub = rem.subscribe_deferred_replay(move |v| seen2.lock().unwrap().push(v.cloned()));
+
+        // the subscribing thread never updates or flushes, the events don't pile up.
+        std::thread::spawn(move || {
+            for i in 1..1000 {
+                sink.update(i);
+                assert_eq!(seen.lock().unwrap().len(), i as usize + 1);
+            }
+            sink.end();
+            assert_eq!(seen.lock().unwrap().last(), Some(&None));
+        })
+        .join()
+        .unwrap();
+    }
 
+    #[test]
+    fn test_shared_map_released() {
+        let sink = Stream::sink();
+        let source = sink.stream().map(|v| v + 1);
+        let weak = source.inner.downgrade();
+        let calls = Arc::new(AtomicUsize::new(0));
+        let shared = {
+            let calls = calls.clone();
+            source.shared_map("plus", move |v| {
+                calls.fetch_add(1, Ordering::SeqCst);
+                *v
+            })
+        };
+        drop(shared);
+
+        // registered again once the shared stream is gone
+        let coll = source.shared_map("plus", |v| v * 10).collect();
         sink.update(1);
-        assert_eq!(coll.take(), vec![1, 2, 4, 8, 16]);
+        assert_eq!(coll.take(), vec![20]);
+        assert_eq!(calls.load(Ordering::SeqCst), 0);
+
+        // the registration doesn't keep the source alive
+        let shared = source.shared_map("plus", |v| *v);
+        drop(source);
+        drop(shared);
+        assert!(weak.is_dropped());
     }
 
     #[test]
-    fn test_combine() {
+    fn test_enumerate_late_subscriber() {
+        let sink = Stream::sink();
+        let numbered = sink.stream().enumerate();
+        let early = numbered.collect();
+        sink.update("a");
+        sink.update("b");
+        // the count belongs to the stream, not the subscription
+        let late = numbered.collect();
+        // a stream per subscriber counts from 0
+        let own = sink.stream().enumerate().collect();
+        sink.update("c");
+        sink.end();
+        assert_eq!(early.wait(), vec![(0, "a"), (1, "b"), (2, "c")]);
+        assert_eq!(late.wait(), vec![(2, "c")]);
+        assert_eq!(own.wait(), vec![(0, "c")]);
+    }
+
+    #[test]
+    fn test_scan_emit_all_empty_and_end() {
+        let sink = Stream::sink();
+        let seen = Arc::new(Mutex::new(vec![]));
+        let seen_clone = seen.clone();
+        let scanned = sink.stream().scan_emit_all(0, |count, v: &usize| {
+            *count += 1;
+            vec![*count; *v]
+        });
+        // the function and its iterator run with their own output unlocked
+        let slot: Arc<Mutex<Option<Stream<u32>>>> = Arc::new(Mutex::new(None));
+        let slot_clone = slot.clone();
+        let probed = sink.stream().scan_emit_all((), move |_, _| {
+            let ended = slot_clone.lock().unwrap().as_ref().map(|s| s.is_ended());
+            seen_clone.lock().unwrap().push(ended);
+            None
+        });
+        *slot.lock().unwrap() = Some(probed.clone());
+        let coll = scanned.collect();
+        let probe_coll = probed.collect();
+        sink.update(0); // emits nothing
+        sink.update(2);
+        sink.update(0);
+        sink.update(1);
+        sink.end();
+        assert_eq!(coll.wait(), vec![2, 2, 4]);
+        assert!(probe_coll.wait().is_empty());
+        assert_eq!(*seen.lock().unwrap(), vec![Some(false); 4]);
+    }
+
+    #[test]
+    fn test_with_default_if_empty_shared_source() {
+        let sink = Stream::sink();
+        let s = sink.stream();
+        let defaulted = s.filter(|_| false).with_default_if_empty(&s);
+        let coll = defaulted.collect();
+        sink.update(1);
+        // the fallback has ended too, by the time it's subscribed to
+        sink.end();
+        assert_eq!(coll.wait(), Vec::<u32>::new());
+
         let sink1 = Stream::sink();
         let sink2 = Stream::sink();
+        let defaulted = sink1.stream().with_default_if_empty(&sink2.stream());
+        let coll = defaulted.collect();
+        sink1.end();
+        sink2.update(2);
+        assert_eq!(sink2.inner.lock().listener_count(), 1);
+        drop(defaulted);
+        drop(coll);
+        // the fallback subscription goes with the stream
+        assert_eq!(sink2.inner.lock().listener_count(), 0);
+    }
 
-        let comb = Stream::combine2(&sink1.stream(), &sink2.stream());
-
-        let coll = comb.collect();
+    #[test]
+    fn test_flatten_with_max_concurrency_shared_source() {
+        let outer = Stream::sink();
+        let sink = Stream::sink();
+        let s = sink.stream();
+        let flat = outer.stream().flatten_with_max_concurrency(1);
+        let coll = flat.collect();
+        outer.update(s.take(1));
+        outer.update(s.clone()); // waits for take(1) to end
+        sink.update(1);
+        sink.update(2); // ends take(1), and isn't seen by the next stream
+        sink.update(3);
+        outer.end();
+        assert_eq!(coll.wait(), vec![1, 3]);
+    }
 
-        sink1.update(0.0);
-        sink2.update(10);
-        sink1.update(1.0);
-        sink1.update(2.0);
-        sink2.update(11);
-        sink1.update(3.0);
+    #[test]
+    fn test_flatten_with_max_concurrency_dropped() {
+        let outer = Stream::sink();
+        let sink1: Sink<u32> = Stream::sink();
+        let sink2: Sink<u32> = Stream::sink();
+        let flat = outer.stream().flatten_with_max_concurrency(1);
+        let coll = flat.collect();
+        outer.update(sink1.stream());
+        outer.update(sink2.stream());
         sink1.end();
-        sink2.end();
+        assert_eq!(sink2.inner.lock().listener_count(), 1);
+        drop(coll);
+        drop(flat);
+        // the inner subscription goes with the stream
+        assert_eq!(sink2.inner.lock().listener_count(), 0);
+    }
 
-        assert_eq!(
-            coll.wait(),
-            vec![(0.0, 10), (1.0, 10), (2.0, 10), (2.0, 11), (3.0, 11)]
-        );
+    #[test]
+    fn test_take_last_huge_n() {
+        let sink = Stream::sink();
+        let coll = sink.stream().take_last(usize::MAX).collect();
+        sink.update(1);
+        sink.update(2);
+        sink.end();
+        assert_eq!(coll.wait(), vec![1, 2]);
+    }
+
+    #[test]
+    fn test_drop_last_huge_n() {
+        let sink = Stream::sink();
+        let coll = sink.stream().drop_last(usize::MAX).collect();
+        sink.update(1);
+        sink.update(2);
+        sink.end();
+        assert_eq!(coll.wait(), Vec::<u32>::new());
+    }
+
+    #[test]
+    fn test_buffer_count_huge_n() {
+        let sink = Stream::sink();
+        let coll = sink.stream().buffer_count(usize::MAX).collect();
+        sink.update(1);
+        sink.update(2);
+        sink.end();
+        assert_eq!(coll.wait(), vec![vec![1, 2]]);
     }
 
+    #[test]
+    fn test_window_count_huge_size() {
+        let sink = Stream::sink();
+        let coll = sink.stream().window_count(usize::MAX, 1).collect();
+        sink.update(1);
+        sink.end();
+        assert_eq!(coll.wait(), Vec::<Vec<u32>>::new());
+    }
 }
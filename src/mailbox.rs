@@ -0,0 +1,38 @@
+use std::sync::{Arc, Mutex};
+
+use crate::peg::Peg;
+use crate::Stream;
+
+/// A slot holding the latest value of a stream that hasn't been taken yet. Created by
+/// [`Stream::mailbox()`](struct.Stream.html#method.mailbox).
+///
+/// Dropping the mailbox stops listening to the stream.
+pub struct Mailbox<T: 'static> {
+    #[allow(dead_code)]
+    peg: Peg,
+    slot: Arc<Mutex<Option<T>>>,
+}
+
+impl<T: Clone> Mailbox<T> {
+    pub(crate) fn new(stream: &Stream<T>) -> Self {
+        let slot = Arc::new(Mutex::new(None));
+        let clone = slot.clone();
+        let peg = stream.internal_subscribe(move |t| {
+            if let Some(t) = t {
+                let mut lock = clone.lock().unwrap();
+                *lock = Some(t.clone());
+            }
+        });
+        Mailbox { peg, slot }
+    }
+
+    /// Take the latest value, if there has been any new value since the last poll.
+    pub fn poll(&self) -> Option<T> {
+        self.slot.lock().unwrap().take()
+    }
+
+    /// Clone the latest value without taking it.
+    pub fn peek(&self) -> Option<T> {
+        self.slot.lock().unwrap().clone()
+    }
+}
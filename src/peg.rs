@@ -1,72 +1,177 @@
-use std::sync::{Arc, Mutex};
+#![allow(clippy::arc_with_non_send_sync)]
+
+use std::sync::{Arc, Mutex, Weak};
 
 type Destructor = Box<dyn Fn()>;
+type Retainer = Box<dyn Fn(Vec<Peg>)>;
+
+struct PegState {
+    destructor: Option<Destructor>,
+    keep: bool,
+    // hands over related pegs to the pegged value, when a kept peg is dropped.
+    retainer: Option<Retainer>,
+}
 
 #[derive(Clone)]
-pub struct Peg(Arc<Mutex<Option<Destructor>>>, Option<Vec<Peg>>);
-pub struct Pegged<P>(Arc<Mutex<Option<P>>>);
+pub struct Peg {
+    state: Arc<Mutex<PegState>>,
+    // pegs this node is made of, i.e. one per upstream subscription.
+    parts: Option<Vec<Peg>>,
+    // pegs that must be kept alive as long as this one.
+    related: Option<Vec<Peg>>,
+}
+// the pegged value, and the related pegs it keeps alive once its peg is dropped.
+pub struct Pegged<P>(
+    Arc<Mutex<Option<P>>>,
+    #[allow(dead_code)] Arc<Mutex<Vec<Peg>>>,
+);
+
+// a peg that doesn't keep the pegged value, for pegs that aren't made of parts and have
+// no related pegs of their own.
+pub struct WeakPeg(Weak<Mutex<PegState>>);
 
 unsafe impl Send for Peg {}
 unsafe impl Sync for Peg {}
+unsafe impl Send for WeakPeg {}
+unsafe impl Sync for WeakPeg {}
 unsafe impl<P> Send for Pegged<P> {}
 unsafe impl<P> Sync for Pegged<P> {}
 
 impl<P: 'static> Pegged<P> {
     pub fn new_pair(p: P) -> (Pegged<P>, Peg) {
         let wrap = Arc::new(Mutex::new(Some(p)));
+        let held: Arc<Mutex<Vec<Peg>>> = Arc::new(Mutex::new(vec![]));
         let clone = wrap.clone();
+        let held_clone = held.clone();
         let destroy = move || {
-            let mut lock = clone.lock().unwrap();
-            lock.take();
+            let p = clone.lock().unwrap().take();
+            let related = std::mem::take(&mut *held_clone.lock().unwrap());
+            // drop outside the locks
+            drop(p);
+            drop(related);
+        };
+        let held_clone = held.clone();
+        let retain = move |related: Vec<Peg>| {
+            held_clone.lock().unwrap().extend(related);
         };
-        let destructor: Destructor = Box::new(destroy);
-        (
-            Pegged(wrap),
-            Peg(Arc::new(Mutex::new(Some(destructor))), None),
-        )
+        let peg = Peg::new(Some(Box::new(destroy)), None);
+        peg.state.lock().unwrap().retainer = Some(Box::new(retain));
+        (Pegged(wrap, held), peg)
     }
 
     pub fn with_value<R>(&self, mut f: impl FnMut(Option<&mut P>) -> R) -> R {
         let mut lock = self.0.lock().unwrap();
         f(lock.as_mut())
     }
+
+    #[cfg(test)]
+    pub fn is_pegged(&self) -> bool {
+        self.0.lock().unwrap().is_some()
+    }
 }
 
 impl Peg {
+    fn new(destructor: Option<Destructor>, parts: Option<Vec<Peg>>) -> Peg {
+        Peg {
+            state: Arc::new(Mutex::new(PegState {
+                destructor,
+                keep: false,
+                retainer: None,
+            })),
+            parts,
+            related: None,
+        }
+    }
+
     pub fn new_fake() -> Peg {
-        Peg(Arc::new(Mutex::new(None)), None)
+        Peg::new(None, None)
     }
 
     pub fn many(pegs: Vec<Peg>) -> Peg {
-        Peg(Arc::new(Mutex::new(None)), Some(pegs))
+        Peg::new(None, Some(pegs))
     }
 
     pub fn add_related(&mut self, peg: Peg) {
-        if let Some(v) = self.1.as_mut() {
+        if let Some(v) = self.related.as_mut() {
             v.push(peg);
         } else {
-            self.1 = Some(vec![peg]);
+            self.related = Some(vec![peg]);
         }
     }
 
+    /// Keep the pegged value when the last peg is dropped. An explicit `unpeg()` still
+    /// removes it. The related pegs are kept alive with the pegged value.
     pub fn keep_mode(&self) {
-        let mut lock = self.0.lock().unwrap();
-        lock.take(); // just remove destructor without running it
+        let mut lock = self.state.lock().unwrap();
+        lock.keep = true;
     }
 
     pub fn unpeg(&self) {
-        let mut lock = self.0.lock().unwrap();
-        if let Some(destruct) = lock.take() {
+        let (destruct, _retainer) = {
+            let mut lock = self.state.lock().unwrap();
+            (lock.destructor.take(), lock.retainer.take())
+        };
+        if let Some(destruct) = destruct {
             destruct()
         }
     }
+
+    pub fn downgrade(&self) -> WeakPeg {
+        WeakPeg(Arc::downgrade(&self.state))
+    }
+
+    /// Whether this peg still holds its pegged value. Fake pegs never do.
+    pub fn is_pegged(&self) -> bool {
+        self.state.lock().unwrap().destructor.is_some()
+    }
+
+    /// Unpeg this peg and the parts it is made of, but not the related pegs.
+    pub fn detach(&self) {
+        self.unpeg();
+        if let Some(parts) = self.parts.as_ref() {
+            for part in parts {
+                part.unpeg();
+            }
+        }
+    }
+}
+
+impl WeakPeg {
+    /// A peg of the pegged value, unless it has been unpegged.
+    pub fn upgrade(&self) -> Option<Peg> {
+        let peg = Peg {
+            state: self.0.upgrade()?,
+            parts: None,
+            related: None,
+        };
+        // the last peg may be unpegging as we upgrade.
+        if peg.is_pegged() {
+            Some(peg)
+        } else {
+            None
+        }
+    }
 }
 
 impl Drop for Peg {
     fn drop(&mut self) {
-        if Arc::strong_count(&self.0) == 1 {
+        if Arc::strong_count(&self.state) == 1 {
             // this is the last peg to drop.
-            self.unpeg();
+            let retainer = {
+                let mut lock = self.state.lock().unwrap();
+                if lock.keep {
+                    lock.retainer.take()
+                } else {
+                    None
+                }
+            };
+            if let Some(retainer) = retainer {
+                if let Some(related) = self.related.take() {
+                    retainer(related);
+                }
+            } else if !self.state.lock().unwrap().keep {
+                self.unpeg();
+            }
         }
     }
 }
@@ -103,4 +208,52 @@ mod test {
             assert!(lock.is_some());
         }
     }
+
+    #[test]
+    pub fn test_peg_keep_mode_unpeg() {
+        let (pegged, peg) = Pegged::new_pair(());
+        peg.keep_mode();
+        peg.unpeg();
+        assert!(!pegged.is_pegged());
+    }
+
+    #[test]
+    pub fn test_peg_keep_mode_retains_related() {
+        let (pegged1, peg1) = Pegged::new_pair(());
+        let (pegged2, mut peg2) = Pegged::new_pair(());
+        peg2.add_related(peg1);
+        peg2.keep_mode();
+        drop(peg2);
+        // the kept value holds on to the related peg
+        assert!(pegged1.is_pegged());
+        assert!(pegged2.is_pegged());
+        drop(pegged2);
+        assert!(!pegged1.is_pegged());
+    }
+
+    #[test]
+    pub fn test_peg_keep_mode_unpeg_releases_related() {
+        let (pegged1, peg1) = Pegged::new_pair(());
+        let (pegged2, mut peg2) = Pegged::new_pair(());
+        peg2.add_related(peg1);
+        peg2.keep_mode();
+        peg2.unpeg();
+        assert!(!pegged2.is_pegged());
+        drop(peg2);
+        assert!(!pegged1.is_pegged());
+    }
+
+    #[test]
+    pub fn test_peg_detach_parts() {
+        let (pegged1, peg1) = Pegged::new_pair(());
+        let (pegged2, peg2) = Pegged::new_pair(());
+        let (pegged3, peg3) = Pegged::new_pair(());
+        let mut many = Peg::many(vec![peg1, peg2]);
+        many.add_related(peg3);
+        many.detach();
+        assert!(!pegged1.is_pegged());
+        assert!(!pegged2.is_pegged());
+        // related pegs are left alone
+        assert!(pegged3.is_pegged());
+    }
 }
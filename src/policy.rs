@@ -0,0 +1,92 @@
+use std::cell::Cell;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Mutex;
+
+const IGNORE: usize = 0;
+const PANIC_IN_DEBUG: usize = 1;
+const CALLBACK: usize = 2;
+
+static POLICY: AtomicUsize = AtomicUsize::new(IGNORE);
+static CALLBACK_FN: Mutex<Option<fn(&'static str)>> = Mutex::new(None);
+
+// the stream of an update after the end to panic for, once its locks are released.
+thread_local!(static LATE: Cell<Option<&'static str>> = const { Cell::new(None) });
+
+/// What to do on an update into a sink that has ended. Set with
+/// [`set_ended_update_policy()`](fn.set_ended_update_policy.html).
+#[derive(Debug, Clone, Copy)]
+pub enum EndedUpdatePolicy {
+    /// Silently ignore the update. This is the default.
+    Ignore,
+    /// Panic in debug builds, ignore in release builds.
+    PanicInDebug,
+    /// Call a function with the name of the sink, then ignore the update. The name is the
+    /// [`Sink::id()`](struct.Sink.html#method.id) and the type of the events, like
+    /// `#3 (u32)`. The function is called with the sink locked, so it must not use the
+    /// sink.
+    Callback(fn(&'static str)),
+}
+
+/// Set the process wide policy for updates into a sink that has ended.
+///
+/// A sink ends with [`Sink::end()`](struct.Sink.html#method.end), or on
+/// [`shutdown()`](fn.shutdown.html) for registered sinks. An update after that, or ending
+/// it again, is ignored, which can hide a producer that should have stopped. This policy
+/// makes such updates visible while debugging.
+///
+/// ```
+/// use xi::{set_ended_update_policy, EndedUpdatePolicy};
+///
+/// fn report(name: &'static str) {
+///     eprintln!("update of ended sink {}", name);
+/// }
+///
+/// set_ended_update_policy(EndedUpdatePolicy::Callback(report));
+///
+/// // ...
+///
+/// set_ended_update_policy(EndedUpdatePolicy::Ignore);
+/// ```
+pub fn set_ended_update_policy(policy: EndedUpdatePolicy) {
+    let value = match policy {
+        EndedUpdatePolicy::Ignore => IGNORE,
+        EndedUpdatePolicy::PanicInDebug => PANIC_IN_DEBUG,
+        EndedUpdatePolicy::Callback(f) => {
+            *CALLBACK_FN.lock().unwrap() = Some(f);
+            CALLBACK
+        }
+    };
+    POLICY.store(value, Ordering::SeqCst);
+}
+
+/// Tells if updates into ended sinks are ignored. A single atomic read.
+pub(crate) fn is_ignore() -> bool {
+    POLICY.load(Ordering::Relaxed) == IGNORE
+}
+
+/// Apply the policy for an update into the ended sink `name`. A panic is held back until
+/// `check_ended_update()`, so the sink isn't poisoned.
+pub(crate) fn ended_update(name: &'static str) {
+    match POLICY.load(Ordering::SeqCst) {
+        PANIC_IN_DEBUG => {
+            if cfg!(debug_assertions) {
+                LATE.with(|l| l.set(Some(name)));
+            }
+        }
+        CALLBACK => {
+            let f = *CALLBACK_FN.lock().unwrap();
+            if let Some(f) = f {
+                f(name);
+            }
+        }
+        _ => (),
+    }
+}
+
+/// Panic for an update into an ended sink held back by `ended_update()`. Called once an
+/// update has propagated and its locks are released.
+pub(crate) fn check_ended_update() {
+    if let Some(name) = LATE.with(|l| l.take()) {
+        panic!("update of ended sink {}", name);
+    }
+}
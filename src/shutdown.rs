@@ -0,0 +1,131 @@
+use std::cell::Cell;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::{Condvar, Mutex};
+
+use crate::inner::{defer, run_deferred, SafeInner, WeakInner};
+
+static SHUTDOWN: AtomicBool = AtomicBool::new(false);
+static IN_FLIGHT: AtomicUsize = AtomicUsize::new(0);
+// shutdown() waits on this for IN_FLIGHT to drop to 0.
+static DRAINED: (Mutex<()>, Condvar) = (Mutex::new(()), Condvar::new());
+static REGISTERED: Mutex<Vec<Registered>> = Mutex::new(vec![]);
+
+thread_local!(static DEPTH: Cell<usize> = const { Cell::new(0) });
+
+struct Registered {
+    end: Box<dyn Fn()>,
+    is_dropped: Box<dyn Fn() -> bool>,
+}
+
+unsafe impl Send for Registered {}
+
+/// Guard for an update dispatching through the tree.
+pub(crate) struct InFlight(());
+
+impl InFlight {
+    /// Enter a dispatch, unless xi is shut down.
+    pub fn enter() -> Option<InFlight> {
+        IN_FLIGHT.fetch_add(1, Ordering::SeqCst);
+        if SHUTDOWN.load(Ordering::SeqCst) {
+            leave();
+            return None;
+        }
+        DEPTH.with(|d| d.set(d.get() + 1));
+        Some(InFlight(()))
+    }
+}
+
+impl Drop for InFlight {
+    fn drop(&mut self) {
+        DEPTH.with(|d| d.set(d.get() - 1));
+        leave();
+    }
+}
+
+fn leave() {
+    let left = IN_FLIGHT.fetch_sub(1, Ordering::SeqCst) - 1;
+    if left == 0 && SHUTDOWN.load(Ordering::SeqCst) {
+        // taking the lock means shutdown() is either before its check, or waiting.
+        let _lock = DRAINED.0.lock().unwrap();
+        DRAINED.1.notify_all();
+    }
+}
+
+/// Register a sink to be ended on shutdown.
+pub(crate) fn register<T>(inner: &SafeInner<T>) {
+    let weak: WeakInner<T> = inner.downgrade();
+    let weak2: WeakInner<T> = inner.downgrade();
+    let reg = Registered {
+        end: Box::new(move || {
+            if let Some(inner) = weak.upgrade() {
+                let mut lock = inner.lock();
+                // ending a sink that already ended isn't a late update here.
+                if !lock.is_ended() {
+                    lock.update_owned(None);
+                }
+                drop(lock);
+                run_deferred();
+            }
+        }),
+        is_dropped: Box::new(move || weak2.is_dropped()),
+    };
+    let mut lock = REGISTERED.lock().unwrap();
+    lock.retain(|r| !(r.is_dropped)());
+    lock.push(reg);
+}
+
+/// Shut down xi to get a quiescent point before the process exits.
+///
+/// 1. All future calls to [`Sink::update()`](struct.Sink.html#method.update) and
+///    [`Sink::end()`](struct.Sink.html#method.end) become no-ops, in any thread.
+/// 2. Waits for updates that are already dispatching in other threads to complete.
+/// 3. Ends all sinks created with
+///    [`Stream::sink_registered()`](struct.Stream.html#method.sink_registered).
+///
+/// Once this function returns, no more events are delivered. There is no way to undo a
+/// shutdown.
+///
+/// Called from inside a subscriber, this can't wait for other threads, since they may be
+/// waiting for locks held by the update dispatching on the calling thread. Updates stop
+/// being accepted straight away, but updates already dispatching in other threads may
+/// still deliver events after this returns. The registered sinks are ended once the
+/// update on the calling thread is done.
+///
+/// ```
+/// use xi::Stream;
+///
+/// let sink = Stream::sink_registered();
+/// let coll = sink.stream().collect();
+///
+/// sink.update(0);
+///
+/// xi::shutdown(); // ends the registered sink
+///
+/// sink.update(1); // ignored
+///
+/// assert_eq!(coll.wait(), vec![0]);
+/// ```
+pub fn shutdown() {
+    SHUTDOWN.store(true, Ordering::SeqCst);
+    if DEPTH.with(|d| d.get()) > 0 {
+        defer(end_registered);
+        return;
+    }
+    let mut lock = DRAINED.0.lock().unwrap();
+    while IN_FLIGHT.load(Ordering::SeqCst) > 0 {
+        lock = DRAINED.1.wait(lock).unwrap();
+    }
+    drop(lock);
+    end_registered();
+}
+
+/// End all registered sinks, last registered first.
+fn end_registered() {
+    let registered = {
+        let mut lock = REGISTERED.lock().unwrap();
+        lock.split_off(0)
+    };
+    for r in registered.iter().rev() {
+        (r.end)();
+    }
+}
@@ -0,0 +1,38 @@
+/// Values that can be counted through by [`Stream::range()`](struct.Stream.html#method.range).
+///
+/// Implemented for the primitive integer types. The trait is sealed, it can't be
+/// implemented outside of this crate.
+pub trait Step: Copy + Ord + sealed::Sealed + 'static {
+    #[doc(hidden)]
+    fn min_value() -> Self;
+    #[doc(hidden)]
+    fn forward(self) -> Option<Self>;
+    #[doc(hidden)]
+    fn backward(self) -> Option<Self>;
+}
+
+mod sealed {
+    pub trait Sealed {}
+}
+
+macro_rules! impl_step {
+    ($($t:ty)+) => {
+        $(
+            impl sealed::Sealed for $t {}
+
+            impl Step for $t {
+                fn min_value() -> Self {
+                    <$t>::MIN
+                }
+                fn forward(self) -> Option<Self> {
+                    self.checked_add(1)
+                }
+                fn backward(self) -> Option<Self> {
+                    self.checked_sub(1)
+                }
+            }
+        )+
+    };
+}
+
+impl_step!(u8 u16 u32 u64 u128 usize i8 i16 i32 i64 i128 isize);
@@ -1,4 +1,8 @@
+use std::error::Error;
+use std::fmt;
+
 use crate::peg::{Peg, Pegged};
+use crate::Stream;
 
 #[doc(hidden)]
 #[derive(Default)]
@@ -26,6 +30,12 @@ impl<T> Listeners<T> {
         self.ls.clear();
     }
 
+    /// Number of listeners that are still pegged.
+    #[cfg(test)]
+    pub fn len(&self) -> usize {
+        self.ls.iter().filter(|p| p.is_pegged()).count()
+    }
+
     /// Iterate over listeners and receive them one by one in a callback.
     pub fn iter(&mut self, mut cb: impl FnMut(&mut Listener<T>)) {
         self.ls.retain(|p| {
@@ -94,8 +104,120 @@ impl Subscription {
         Subscription { peg }
     }
 
+    pub(crate) fn into_peg(self) -> Peg {
+        self.peg
+    }
+
     /// Stops listening to the stream.
     pub fn unsubscribe(&self) {
-        self.peg.unpeg()
+        // a subscription to many streams is made of one peg per stream.
+        self.peg.detach()
+    }
+}
+
+/// A set of subscriptions and streams that are torn down together.
+///
+/// Pieces built for a feature are pushed into the set as they are created. When the set
+/// is cleared or dropped, every piece is released in reverse order of being pushed:
+/// subscriptions are unsubscribed, and pushed streams are detached from their parent
+/// streams, even if other clones of those streams are still held elsewhere.
+///
+/// ```
+/// use xi::{Stream, SubscriptionSet};
+///
+/// let sink = Stream::sink();
+///
+/// let mut set = SubscriptionSet::new();
+///
+/// let doubled = sink.stream().map(|v| v * 2);
+/// set.push(&doubled);
+/// set.push(doubled.subscribe(|v| if let Some(v) = v {
+///     println!("{}", v);
+/// }));
+///
+/// let coll = doubled.collect();
+///
+/// sink.update(1);
+/// set.clear(); // doubled no longer receives events
+/// sink.update(2);
+///
+/// assert_eq!(coll.take(), vec![2]);
+/// ```
+#[derive(Default)]
+pub struct SubscriptionSet {
+    pegs: Vec<Retained>,
+}
+
+/// Something retained by a [`SubscriptionSet`](struct.SubscriptionSet.html). Created from
+/// a `Subscription` or a `&Stream`.
+#[doc(hidden)]
+pub struct Retained(RetainedKind);
+
+enum RetainedKind {
+    Subscription(Subscription),
+    Stream(Peg),
+}
+
+impl From<Subscription> for Retained {
+    fn from(sub: Subscription) -> Self {
+        Retained(RetainedKind::Subscription(sub))
     }
 }
+
+impl<'a, T> From<&'a Stream<T>> for Retained {
+    fn from(stream: &'a Stream<T>) -> Self {
+        Retained(RetainedKind::Stream(stream.peg.clone()))
+    }
+}
+
+impl SubscriptionSet {
+    /// Create a new empty set.
+    pub fn new() -> Self {
+        SubscriptionSet { pegs: vec![] }
+    }
+
+    /// Add a subscription or a stream to the set.
+    pub fn push<R: Into<Retained>>(&mut self, retained: R) {
+        self.pegs.push(retained.into());
+    }
+
+    /// Number of subscriptions and streams in the set.
+    pub fn len(&self) -> usize {
+        self.pegs.len()
+    }
+
+    /// Check if the set is empty.
+    pub fn is_empty(&self) -> bool {
+        self.pegs.is_empty()
+    }
+
+    /// Release everything in the set, in reverse order of being pushed. The set can be
+    /// reused afterwards, and clearing an empty set does nothing.
+    pub fn clear(&mut self) {
+        while let Some(Retained(kind)) = self.pegs.pop() {
+            match kind {
+                RetainedKind::Subscription(sub) => sub.unsubscribe(),
+                RetainedKind::Stream(peg) => peg.detach(),
+            }
+        }
+    }
+}
+
+impl Drop for SubscriptionSet {
+    fn drop(&mut self) {
+        self.clear();
+    }
+}
+
+/// Error returned by [`Stream::try_subscribe()`](struct.Stream.html#method.try_subscribe)
+/// when the stream has already ended.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct StreamEnded;
+
+impl fmt::Display for StreamEnded {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "stream has ended")
+    }
+}
+
+impl Error for StreamEnded {}
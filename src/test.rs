@@ -0,0 +1,365 @@
+//! Helpers for testing pipelines.
+
+use std::sync::{Arc, Mutex};
+
+use crate::{Sink, Stream};
+
+/// One step of input to drive through a sink.
+#[derive(Debug, Clone, PartialEq)]
+pub enum ScriptStep<T> {
+    /// Update a value into the sink.
+    Update(T),
+    /// End the sink.
+    End,
+}
+
+/// An event observed on a stream.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Event<T> {
+    /// A value.
+    Next(T),
+    /// The stream ended.
+    End,
+}
+
+/// Drive a script of input through a pipeline and return every event it produced.
+///
+/// A sink is created and its stream handed to `build`, which constructs the pipeline under
+/// test. The script is then played into the sink, and the full log of output events is
+/// returned, including any remembered value delivered on subscribe and the end. Updates
+/// after an `End` step are ignored.
+///
+/// The log is meant to be compared with a stored expectation, to check that refactoring a
+/// pipeline doesn't change its observable behavior.
+///
+/// ```
+/// use xi::test::{golden, Event, ScriptStep};
+///
+/// let script = vec![
+///     ScriptStep::Update(1),
+///     ScriptStep::Update(2),
+///     ScriptStep::End,
+/// ];
+///
+/// let log = golden(script, |s| s.map(|v| v * 10));
+///
+/// assert_eq!(log, vec![Event::Next(10), Event::Next(20), Event::End]);
+/// ```
+pub fn golden<TIn, TOut, F>(script: Vec<ScriptStep<TIn>>, build: F) -> Vec<Event<TOut>>
+where
+    TIn: 'static,
+    TOut: Clone + 'static,
+    F: FnOnce(Stream<TIn>) -> Stream<TOut>,
+{
+    let sink = Stream::sink();
+    let out = build(sink.stream());
+    let log = Arc::new(Mutex::new(vec![]));
+    let log_clone = log.clone();
+    let sub = out.subscribe_unchecked(move |t| {
+        let event = match t {
+            Some(t) => Event::Next(t.clone()),
+            None => Event::End,
+        };
+        log_clone.lock().unwrap().push(event);
+    });
+    let mut sink = Some(sink);
+    for step in script {
+        match step {
+            ScriptStep::Update(v) => {
+                if let Some(sink) = sink.as_ref() {
+                    sink.update(v);
+                }
+            }
+            ScriptStep::End => {
+                if let Some(sink) = sink.take() {
+                    sink.end();
+                }
+            }
+        }
+    }
+    sub.unsubscribe();
+    let mut lock = log.lock().unwrap();
+    std::mem::take(&mut *lock)
+}
+
+/// A script of input over a number of sinks, for property style testing.
+///
+/// Each step is the index of a sink and what to do with it. Scripts can be generated
+/// from a seeded [`Rng`](struct.Rng.html), played into sinks with `run()` and made smaller
+/// with `shrink()`, which is the building blocks of a property test harness: generate
+/// scripts, check an invariant, and shrink a failing script to a minimal one.
+///
+/// ```
+/// use xi::test::{Rng, Script};
+/// use xi::Stream;
+///
+/// let mut rng = Rng::new(42);
+/// let script = Script::random(&mut rng, 2, 10, |rng| rng.below(100));
+///
+/// let sinks = vec![Stream::sink(), Stream::sink()];
+/// let merged = Stream::merge(sinks.iter().map(|s| s.stream()).collect());
+/// let coll = merged.collect();
+///
+/// script.run(&sinks);
+///
+/// // every update before the sink ended is merged
+/// assert!(coll.take().len() <= script.len());
+/// ```
+#[derive(Debug, Clone, PartialEq)]
+pub struct Script<T> {
+    /// The steps, as sink index and action.
+    pub steps: Vec<(usize, ScriptStep<T>)>,
+}
+
+impl<T: Clone> Script<T> {
+    /// Create a script from steps.
+    pub fn new(steps: Vec<(usize, ScriptStep<T>)>) -> Self {
+        Script { steps }
+    }
+
+    /// Generate a script of `len` steps over `sinks` sinks.
+    ///
+    /// About one in eight steps ends a sink. Values are created by `value`. The same seed
+    /// always generates the same script.
+    pub fn random<F>(rng: &mut Rng, sinks: usize, len: usize, mut value: F) -> Self
+    where
+        F: FnMut(&mut Rng) -> T,
+    {
+        let steps = (0..len)
+            .map(|_| {
+                let idx = rng.below(sinks);
+                if rng.below(8) == 0 {
+                    (idx, ScriptStep::End)
+                } else {
+                    (idx, ScriptStep::Update(value(rng)))
+                }
+            })
+            .collect();
+        Script { steps }
+    }
+
+    /// Number of steps.
+    pub fn len(&self) -> usize {
+        self.steps.len()
+    }
+
+    /// Tells if there are no steps.
+    pub fn is_empty(&self) -> bool {
+        self.steps.is_empty()
+    }
+
+    /// Play the script into the sinks.
+    ///
+    /// Ending a sink doesn't consume it, and updates after a sink has ended are ignored, or
+    /// handled according to [`set_ended_update_policy()`](../fn.set_ended_update_policy.html).
+    /// Panics if a step refers to a sink that isn't in `sinks`.
+    pub fn run(&self, sinks: &[Sink<T>]) {
+        for (idx, step) in &self.steps {
+            let sink = sinks
+                .get(*idx)
+                .unwrap_or_else(|| panic!("script step for sink {} of {}", idx, sinks.len()));
+            match step {
+                ScriptStep::Update(v) => sink.update(v.clone()),
+                ScriptStep::End => sink.end_shared(),
+            }
+        }
+    }
+
+    /// Smaller variants of this script, the most aggressive first.
+    ///
+    /// First the prefix of half the length, then every script with one step removed, in
+    /// the order of the steps. An empty script has no smaller variants.
+    pub fn shrink(&self) -> impl Iterator<Item = Script<T>> {
+        let steps = self.steps.clone();
+        let len = steps.len();
+        let half = if len >= 2 {
+            Some(Script::new(steps[..len / 2].to_vec()))
+        } else {
+            None
+        };
+        let drop_one = (0..len).map(move |i| {
+            let mut steps = steps.clone();
+            steps.remove(i);
+            Script::new(steps)
+        });
+        half.into_iter().chain(drop_one)
+    }
+}
+
+/// A small deterministic pseudo random number generator, for generating scripts.
+///
+/// This is xorshift64*, which is plenty for test input but not for anything else.
+///
+/// ```
+/// let mut rng1 = xi::test::Rng::new(7);
+/// let mut rng2 = xi::test::Rng::new(7);
+///
+/// assert_eq!(rng1.next_u64(), rng2.next_u64());
+/// assert!(rng1.below(10) < 10);
+/// ```
+#[derive(Debug, Clone)]
+pub struct Rng(u64);
+
+impl Rng {
+    /// Create a generator from a seed.
+    pub fn new(seed: u64) -> Self {
+        const MIX: u64 = 0x9e37_79b9_7f4a_7c15;
+        // xorshift gets stuck on a state of 0, so the one seed mixing to 0 is moved off it.
+        match seed ^ MIX {
+            0 => Rng(MIX),
+            state => Rng(state),
+        }
+    }
+
+    /// Next pseudo random number.
+    pub fn next_u64(&mut self) -> u64 {
+        self.0 ^= self.0 >> 12;
+        self.0 ^= self.0 << 25;
+        self.0 ^= self.0 >> 27;
+        self.0.wrapping_mul(0x2545_f491_4f6c_dd1d)
+    }
+
+    /// Next pseudo random number in `0..n`. Panics if `n` is 0.
+    pub fn below(&mut self, n: usize) -> usize {
+        assert!(n > 0, "Rng::below(0)");
+        (self.next_u64() % n as u64) as usize
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::ScriptStep::{End, Update};
+    use super::*;
+
+    #[test]
+    fn test_golden_map() {
+        let log = golden(vec![Update(1), Update(2), End], |s| s.map(|v| v + 1));
+        assert_eq!(log, vec![Event::Next(2), Event::Next(3), Event::End]);
+    }
+
+    #[test]
+    fn test_golden_filter() {
+        let log = golden(vec![Update(1), Update(2), Update(3), End], |s| {
+            s.filter(|v| v % 2 == 1)
+        });
+        assert_eq!(log, vec![Event::Next(1), Event::Next(3), Event::End]);
+    }
+
+    #[test]
+    fn test_golden_fold() {
+        // the seed is part of the log, since fold is a memory stream
+        let log = golden(vec![Update(1), Update(2), End], |s| s.fold(0, |p, c| p + c));
+        assert_eq!(
+            log,
+            vec![Event::Next(0), Event::Next(1), Event::Next(3), Event::End]
+        );
+    }
+
+    #[test]
+    fn test_golden_update_after_end() {
+        let log = golden(vec![Update(1), End, Update(2)], |s| s);
+        assert_eq!(log, vec![Event::Next(1), Event::End]);
+    }
+
+    #[test]
+    fn test_golden_no_end() {
+        let log: Vec<Event<u32>> = golden(vec![Update(1)], |s| s);
+        assert_eq!(log, vec![Event::Next(1)]);
+    }
+
+    #[test]
+    fn test_script_shrink_shapes() {
+        let script = Script::new(vec![
+            (0, Update(1)),
+            (1, Update(2)),
+            (0, End),
+            (1, Update(3)),
+        ]);
+        let shrunk: Vec<_> = script.shrink().collect();
+        assert_eq!(shrunk.len(), 5);
+        assert_eq!(shrunk[0].steps, vec![(0, Update(1)), (1, Update(2))]);
+        assert_eq!(
+            shrunk[1].steps,
+            vec![(1, Update(2)), (0, End), (1, Update(3))]
+        );
+        assert_eq!(
+            shrunk[4].steps,
+            vec![(0, Update(1)), (1, Update(2)), (0, End)]
+        );
+        assert!(shrunk.iter().all(|s| s.len() < script.len()));
+        let one = Script::new(vec![(0, Update(1))]);
+        assert_eq!(one.shrink().collect::<Vec<_>>(), vec![Script::new(vec![])]);
+        assert_eq!(Script::<u32>::new(vec![]).shrink().count(), 0);
+    }
+
+    #[test]
+    fn test_script_random_deterministic() {
+        let gen = |seed| Script::random(&mut Rng::new(seed), 3, 50, |rng| rng.below(10));
+        assert_eq!(gen(1), gen(1));
+        assert_ne!(gen(1), gen(2));
+        assert!(gen(1).steps.iter().all(|(idx, _)| *idx < 3));
+    }
+
+    // Shrink a failing script greedily, as long as some smaller variant also fails.
+    fn minimize<T: Clone>(mut script: Script<T>, fails: impl Fn(&Script<T>) -> bool) -> Script<T> {
+        while let Some(smaller) = script.shrink().find(|s| fails(s)) {
+            script = smaller;
+        }
+        script
+    }
+
+    // Log of events from merging the streams of two sinks with a pipeline.
+    fn merged_log(script: &Script<u32>) -> Vec<Event<u32>> {
+        let sinks = vec![Stream::sink(), Stream::sink()];
+        let a = sinks[0].stream().map(|v| v * 2);
+        let b = sinks[1].stream().filter(|v| v % 3 != 0);
+        let merged = Stream::merge(vec![a.clone(), b.clone()]);
+        let out = Stream::merge(vec![merged, a.zip_with(&b, |x, y| x + y)]);
+        let log = Arc::new(Mutex::new(vec![]));
+        let log_clone = log.clone();
+        let _sub = out.subscribe(move |t| {
+            log_clone.lock().unwrap().push(match t {
+                Some(t) => Event::Next(*t),
+                None => Event::End,
+            });
+        });
+        script.run(&sinks);
+        let log = log.lock().unwrap().clone();
+        log
+    }
+
+    fn value_after_end(log: &[Event<u32>]) -> bool {
+        log.iter().skip_while(|e| **e != Event::End).skip(1).count() > 0
+    }
+
+    #[test]
+    fn test_rng_not_stuck() {
+        let mut rng = Rng::new(0x9e37_79b9_7f4a_7c15);
+        assert_ne!(rng.next_u64(), rng.next_u64());
+    }
+
+    #[test]
+    fn test_property_no_value_after_end() {
+        let mut rng = Rng::new(2024);
+        for _ in 0..200 {
+            let script = Script::random(&mut rng, 2, 30, |rng| rng.below(100) as u32);
+            if value_after_end(&merged_log(&script)) {
+                let minimal = minimize(script, |s| value_after_end(&merged_log(s)));
+                panic!("value after end for script: {:?}", minimal.steps);
+            }
+        }
+    }
+
+    #[test]
+    fn test_minimize_finds_smallest() {
+        // a "bug" triggered by any update of 7 on sink 1
+        let fails = |s: &Script<u32>| s.steps.contains(&(1, Update(7)));
+        let script = Script::new(vec![
+            (0, Update(1)),
+            (1, Update(7)),
+            (0, End),
+            (1, Update(3)),
+        ]);
+        assert_eq!(minimize(script, fails).steps, vec![(1, Update(7))]);
+    }
+}
@@ -0,0 +1,29 @@
+//! Dispatching through a tree of operators doesn't allocate, also not with the
+//! `diagnostics` feature, whose counters are plain atomics.
+
+use xi::bench_support::{allocations, CountingAlloc};
+use xi::Stream;
+
+#[global_allocator]
+static ALLOC: CountingAlloc = CountingAlloc;
+
+#[test]
+fn test_dispatch_does_not_allocate() {
+    let sink = Stream::sink();
+    let chain = sink
+        .stream()
+        .map(|v| v + 1)
+        .filter(|v| v % 2 == 0)
+        .dedupe()
+        .fold(0, |acc, v| acc + v);
+    let _sub = chain.subscribe(|_| {});
+
+    // warm up
+    sink.update(0);
+
+    let before = allocations();
+    for i in 0..1000 {
+        sink.update(i);
+    }
+    assert_eq!(allocations() - before, 0);
+}
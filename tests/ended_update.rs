@@ -0,0 +1,82 @@
+// The ended update policy is process wide, so it gets its own test binary.
+
+use std::panic;
+use std::sync::Mutex;
+
+use xi::test::{Script, ScriptStep};
+use xi::{set_ended_update_policy, EndedUpdatePolicy, Sink, Stream};
+
+static SEEN: Mutex<Vec<&'static str>> = Mutex::new(vec![]);
+
+fn record(name: &'static str) {
+    SEEN.lock().unwrap().push(name);
+}
+
+// update and end a new sink, followed by the late steps, and give the sink.
+fn late_steps<T: Clone + 'static>(v: T, late: Vec<ScriptStep<T>>) -> Sink<T> {
+    let sinks = vec![Stream::sink()];
+    let mut steps = vec![(0, ScriptStep::Update(v)), (0, ScriptStep::End)];
+    steps.extend(late.into_iter().map(|s| (0, s)));
+    Script::new(steps).run(&sinks);
+    sinks.into_iter().next().unwrap()
+}
+
+fn late_update<T: Clone + 'static>(v: T) -> Sink<T> {
+    late_steps(v.clone(), vec![ScriptStep::Update(v)])
+}
+
+fn seen() -> Vec<&'static str> {
+    SEEN.lock().unwrap().split_off(0)
+}
+
+#[test]
+fn test_ended_update_policies() {
+    // default is to ignore, as before
+    late_update(1_u32);
+
+    set_ended_update_policy(EndedUpdatePolicy::Callback(record));
+    assert!(seen().is_empty());
+
+    // once per late update, with the id of the sink
+    let a = late_steps(2_u32, vec![ScriptStep::Update(3), ScriptStep::Update(4)]);
+    let b = late_update(String::from("late"));
+    let name_a = format!("#{} (u32)", a.id());
+    let name_b = format!("#{} ({})", b.id(), std::any::type_name::<String>());
+    assert_ne!(a.id(), b.id());
+    assert_eq!(seen(), vec![&name_a, &name_a, &name_b]);
+
+    // two sinks of the same type are told apart
+    let c = late_update(5_u32);
+    assert_ne!(a.id(), c.id());
+    assert_eq!(seen(), vec![format!("#{} (u32)", c.id())]);
+
+    // ending again is late too
+    let d = late_steps(6_u32, vec![ScriptStep::End]);
+    assert_eq!(seen(), vec![format!("#{} (u32)", d.id())]);
+
+    // ending the operators hanging off a sink isn't
+    let sink = Stream::sink();
+    let _taken = sink.stream().take(1).map(|v| v * 2);
+    sink.update(7_u32);
+    sink.update(8);
+    sink.end();
+    assert!(seen().is_empty());
+
+    set_ended_update_policy(EndedUpdatePolicy::PanicInDebug);
+    let sinks = vec![Stream::sink()];
+    let stream = sinks[0].stream();
+    let script = Script::new(vec![
+        (0, ScriptStep::Update(9_u32)),
+        (0, ScriptStep::End),
+        (0, ScriptStep::Update(10)),
+    ]);
+    let result = panic::catch_unwind(panic::AssertUnwindSafe(|| script.run(&sinks)));
+    assert_eq!(result.is_err(), cfg!(debug_assertions));
+    // the panic doesn't poison the sink
+    assert!(stream.is_ended());
+
+    set_ended_update_policy(EndedUpdatePolicy::Ignore);
+    late_update(11_u32);
+    // no more callbacks
+    assert!(seen().is_empty());
+}
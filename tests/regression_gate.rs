@@ -0,0 +1,68 @@
+//! The allocations and clones of the benchmarked scenarios, so a change making them worse
+//! fails the tests rather than just slowing the benchmarks down. Lower numbers are
+//! welcome, update them here.
+
+use xi::bench_support::{allocations, CloneCounter, CountingAlloc};
+use xi::Stream;
+
+#[global_allocator]
+static ALLOC: CountingAlloc = CountingAlloc;
+
+const UPDATES: usize = 1000;
+
+// allocations per update of the sink, after a warm up.
+fn allocations_per_update<T: Clone>(sink: &xi::Sink<T>, v: T) -> usize {
+    sink.update(v.clone());
+    let before = allocations();
+    for _ in 0..UPDATES {
+        sink.update(v.clone());
+    }
+    (allocations() - before) / UPDATES
+}
+
+#[test]
+fn test_map_chain_allocations() {
+    let sink = Stream::sink();
+    let mut chain = sink.stream();
+    for _ in 0..32 {
+        chain = chain.map(|x| x + 1);
+    }
+    let _sub = chain.subscribe(|_| {});
+    assert_eq!(allocations_per_update(&sink, 0), 0);
+}
+
+#[test]
+fn test_fan_out_allocations() {
+    let sink = Stream::sink();
+    let stream = sink.stream();
+    let maps: Vec<_> = (0..64).map(|_| stream.map(|x| x * 2)).collect();
+    let _subs: Vec<_> = maps.iter().map(|m| m.subscribe(|_| {})).collect();
+    assert_eq!(allocations_per_update(&sink, 42), 0);
+}
+
+#[test]
+fn test_imitator_cycle_allocations() {
+    let imitator = Stream::imitator();
+    let fold = imitator
+        .stream()
+        .fold(1, |p, c| if *c < 10 { p + c } else { p })
+        .dedupe();
+    let sink = Stream::sink();
+    let merge = Stream::merge(vec![fold, sink.stream()]);
+    imitator.imitate(&merge);
+    let _sub = merge.subscribe(|_| {});
+    // an imitated event is queued in a box, and cloned on the way.
+    assert_eq!(allocations_per_update(&sink, 1), 4);
+}
+
+#[test]
+fn test_late_subscribe_clones() {
+    let counter = CloneCounter::new();
+    let sink = Stream::sink();
+    let rem = sink.stream().remember();
+    sink.update(counter.wrap(vec![0_u8; 1024]));
+    counter.reset();
+    // the remembered value is handed over by reference
+    rem.subscribe(|_| {}).unsubscribe();
+    assert_eq!(counter.count(), 0);
+}
@@ -0,0 +1,59 @@
+// xi::shutdown() is process wide, so it gets its own test binary.
+
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::thread;
+use std::time::Duration;
+
+use xi::Stream;
+
+#[test]
+fn test_shutdown_with_spinning_producer() {
+    let sink = Stream::sink_registered();
+    let plain = Stream::sink();
+
+    let delivered = Arc::new(AtomicUsize::new(0));
+    let delivered_clone = delivered.clone();
+    let ended = Arc::new(AtomicBool::new(false));
+    let ended_clone = ended.clone();
+    let doubled = sink.stream().map(|v| v * 2);
+    let _sub = doubled.subscribe(move |v| {
+        if v.is_some() {
+            delivered_clone.fetch_add(1, Ordering::SeqCst);
+        } else {
+            ended_clone.store(true, Ordering::SeqCst);
+        }
+    });
+    let plain_coll = plain.stream().collect();
+
+    let running = Arc::new(AtomicBool::new(true));
+    let running_clone = running.clone();
+    let producer = thread::spawn(move || {
+        let mut i = 0_u64;
+        while running_clone.load(Ordering::SeqCst) {
+            sink.update(i);
+            plain.update(i);
+            i += 1;
+        }
+        plain.end();
+    });
+
+    // let the producer get going
+    while delivered.load(Ordering::SeqCst) < 100 {
+        thread::yield_now();
+    }
+
+    xi::shutdown();
+
+    let at_return = delivered.load(Ordering::SeqCst);
+    assert!(ended.load(Ordering::SeqCst));
+
+    thread::sleep(Duration::from_millis(50));
+    running.store(false, Ordering::SeqCst);
+    producer.join().unwrap();
+
+    // nothing delivered after shutdown returned
+    assert_eq!(delivered.load(Ordering::SeqCst), at_return);
+    // the plain sink is not registered, so it never ended. its end() was a no-op.
+    assert!(plain_coll.take().len() >= 100);
+}
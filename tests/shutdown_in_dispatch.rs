@@ -0,0 +1,45 @@
+// xi::shutdown() is process wide, so it gets its own test binary.
+
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::channel;
+use std::sync::Arc;
+use std::thread;
+use std::time::Duration;
+
+use xi::Stream;
+
+#[test]
+fn test_shutdown_from_subscriber_with_blocked_thread() {
+    let (done_tx, done_rx) = channel();
+    thread::spawn(move || {
+        let a = Stream::sink_registered();
+        let b = Stream::sink();
+        let merged = Stream::merge(vec![a.stream(), b.stream()]);
+        let ended = Arc::new(AtomicBool::new(false));
+        let ended_clone = ended.clone();
+        let _end = a.stream().subscribe(move |v| {
+            if v.is_none() {
+                ended_clone.store(true, Ordering::SeqCst);
+            }
+        });
+        let mut other = Some(b);
+        let _sub = merged.subscribe(move |v| {
+            if v != Some(&0) {
+                return;
+            }
+            if let Some(b) = other.take() {
+                // blocks on the lock of the merged stream, held by this dispatch
+                thread::spawn(move || b.update(1));
+                thread::sleep(Duration::from_millis(50));
+                xi::shutdown();
+            }
+        });
+        a.update(0);
+        // the registered sink is ended once the update is done
+        assert!(ended.load(Ordering::SeqCst));
+        done_tx.send(()).unwrap();
+    });
+    done_rx
+        .recv_timeout(Duration::from_secs(10))
+        .expect("shutdown deadlocked");
+}